@@ -1,7 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::OnceLock;
 
-use goblin::pe::section_table::SectionTable;
+use offset_finder::engine::EngineVersion;
+use pe_utils::PeImage;
 
 // TODO: Check for more false positives
 const FALSE_POSITIVES: [[u8; 32]; 2] = [
@@ -67,23 +68,66 @@ fn get_relaxed_filter() -> &'static Filter {
     })
 }
 
-pub fn dump_aes_key_restricted(image_base: usize,
-                               sections: &[SectionTable],
-                               data: &[u8]) -> Result<HashSet<Vec<u8>>, offset_finder::Error> {
-    dump_aes_key_internal(image_base, sections, data, get_restricted_filter())
+/// The pattern sets the key can be searched with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AesFilter {
+    /// The stores of the key registration callback, few false positives
+    Restricted,
+    /// Any run of eight immediate stores
+    Relaxed,
 }
 
-pub fn dump_aes_key(image_base: usize,
-                    sections: &[SectionTable],
-                    data: &[u8]) -> Result<HashSet<Vec<u8>>, offset_finder::Error> {
-    dump_aes_key_internal(image_base, sections, data, get_relaxed_filter())
+impl AesFilter {
+    fn get(&self) -> &'static Filter {
+        match self {
+            AesFilter::Restricted => get_restricted_filter(),
+            AesFilter::Relaxed => get_relaxed_filter(),
+        }
+    }
+}
+
+/// Keys found and the filter that found them
+#[derive(Debug, Clone)]
+pub struct AesKeys {
+    pub filter: AesFilter,
+    pub keys: HashSet<Vec<u8>>,
+}
+
+/// Every filter, in the order most likely to find the key for an engine version. The version only
+/// orders them, a misdetected or modified engine still gets both
+pub fn aes_filters_for_engine(engine: Option<&EngineVersion>) -> &'static [AesFilter] {
+    match engine {
+        // No registration callback before 4.22, the key is copied around wherever it is used
+        Some(engine) if !engine.at_least(4, 22) => &[AesFilter::Relaxed, AesFilter::Restricted],
+        _ => &[AesFilter::Restricted, AesFilter::Relaxed],
+    }
+}
+
+/// Tries the filters in the order `aes_filters_for_engine` gives for `engine` and returns the keys
+/// of the first one that finds any, `None` when none does
+pub fn dump_aes_key_for_engine(image: &PeImage,
+                               engine: Option<&EngineVersion>) -> Result<Option<AesKeys>, offset_finder::Error> {
+    for filter in aes_filters_for_engine(engine) {
+        match dump_aes_key_internal(image, filter.get()) {
+            Ok(keys) if !keys.is_empty() => return Ok(Some(AesKeys { filter: *filter, keys })),
+            Ok(_) | Err(offset_finder::Error::NotFound(_)) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(None)
+}
+
+pub fn dump_aes_key_restricted(image: &PeImage) -> Result<HashSet<Vec<u8>>, offset_finder::Error> {
+    dump_aes_key_internal(image, get_restricted_filter())
+}
+
+pub fn dump_aes_key(image: &PeImage) -> Result<HashSet<Vec<u8>>, offset_finder::Error> {
+    dump_aes_key_internal(image, get_relaxed_filter())
 }
 
-fn dump_aes_key_internal(image_base: usize,
-                         sections: &[SectionTable],
-                         data: &[u8],
-                         filter: &Filter) -> Result<HashSet<Vec<u8>>, offset_finder::Error> {
-    let results = filter.locator.find_all_partial_only(image_base, sections, data)?;
+fn dump_aes_key_internal(image: &PeImage, filter: &Filter) -> Result<HashSet<Vec<u8>>, offset_finder::Error> {
+    let results = filter.locator.find_all_partial_only(image)?;
+    let data = image.data();
     // Probabilistic allocation, 50% or more will be false positives, so preallocate (n / 2) + 1
     let mut output: HashSet<Vec<u8>> = HashSet::with_capacity((results.len() / 2) + 1);
     for outer in results {
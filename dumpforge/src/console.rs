@@ -0,0 +1,17 @@
+// winconsole only builds on Windows, other platforms get the equivalent ANSI escape sequences
+#[cfg(windows)]
+pub use winconsole::console::{clear, set_title};
+
+#[cfg(not(windows))]
+pub fn set_title(title: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    print!("\x1b]0;{}\x07", title);
+    std::io::stdout().flush()
+}
+
+#[cfg(not(windows))]
+pub fn clear() -> std::io::Result<()> {
+    use std::io::Write;
+    print!("\x1b[2J\x1b[H");
+    std::io::stdout().flush()
+}
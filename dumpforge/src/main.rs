@@ -1,15 +1,32 @@
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
-use goblin::pe::import::SyntheticImportLookupTableEntry;
-use pe_utils::{parse_headers, get_optional_headers, get_sections, get_imports};
-use aes_key_finder::{dump_aes_key, dump_aes_key_restricted};
-use restorer::restore_from_dump;
+use goblin::pe::optional_header::MAGIC_64;
+use pe_utils::{analyze_packing, parse_headers, fix_headers, map_file, map_file_mut, DebugInfo, ImportedLibrary, PeImage, VersionInfo};
+use aes_key_finder::{dump_aes_key_for_engine, AesFilter};
+use offset_finder::engine::{detect_engine_version, EngineVersion};
+use offset_finder::packer::find_protector_stubs;
+use offset_finder::unreal::{find_unreal_globals, UnrealGlobal};
+use pak_reader::{parse_key, PakFile};
+use restorer::{restore_from_dump_layout_to, restore_from_dump_realigned, restore_from_dump_to};
+use restorer::compare::compare_images;
+use restorer::headers::{headers_wiped, rebuild_headers_from_template, rebuild_headers_heuristic, RegionHint};
+use restorer::iat::{rebuild_imports, SymbolMap};
+use restorer::merge::merge_dumps;
+use restorer::minidump::Minidump;
+use restorer::oep::{find_oep_candidates, set_entry_point};
+use restorer::rebase::rebase_dump;
+use restorer::report::{RestoreReport, SectionStatus, SkipReason};
+#[cfg(target_os = "linux")]
+use restorer::source::{read_image, read_zero_filled, region_hints, ProcessMemory};
+use restorer::json::SymbolMapJson;
 use colored::*;
 use serde_json::json;
-use winconsole::console::set_title;
 use std::env;
-use winconsole::console::clear;
+use console::{clear, set_title};
+
+mod console;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     set_title("DumpForge").unwrap();
@@ -18,10 +35,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         clear().expect("failed to clear console.");
         
         println!("{}", "Select an option:".bright_blue());
-        println!("{} {}", " 1.".bright_blue(), "Fetch AES Key (Unreal Engine)");
-        println!("{} {} {}", " 2.".bright_blue(), "Restore Section Headers from Memory Dump", "(experimental)".bright_red());
-        println!("{} {}", " 3.".bright_blue(), "Get Imports");
-        println!("{} {}", " 4.".bright_blue(), "Exit");
+        println!("{} Fetch AES Key (Unreal Engine)", " 1.".bright_blue());
+        println!("{} Restore Section Headers from Memory Dump {}", " 2.".bright_blue(), "(experimental)".bright_red());
+        println!("{} Get Imports", " 3.".bright_blue());
+        println!("{} Get Exports", " 4.".bright_blue());
+        println!("{} Rebuild Imports of a Restored Dump {}", " 5.".bright_blue(), "(experimental)".bright_red());
+        println!("{} Rebase Memory Dump", " 6.".bright_blue());
+        println!("{} Dump Module from a Running Process {}", " 7.".bright_blue(), "(Linux, Wine/Proton)".bright_red());
+        println!("{} Merge Partial Memory Dumps", " 8.".bright_blue());
+        println!("{} Compare Restored Image with the Original", " 9.".bright_blue());
+        println!("{} List Pak Contents (pak ls)", "10.".bright_blue());
+        println!("{} Exit", "11.".bright_blue());
 
         print!("{}", "> ".bright_red());
         io::stdout().flush()?;
@@ -34,11 +58,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "1" => fetch_aes_key()?,
             "2" => restore_section_headers()?,
             "3" => get_executable_imports()?,
-            "4" => break,
+            "4" => get_executable_exports()?,
+            "5" => rebuild_executable_imports()?,
+            "6" => rebase_memory_dump()?,
+            "7" => dump_process_module()?,
+            "8" => merge_memory_dumps()?,
+            "9" => compare_restored_image()?,
+            "10" => list_pak_contents()?,
+            "11" => break,
             _ => println!("{}", "Invalid choice. Please try again.".bright_red().bold()),
         }
 
-        println!("{} {}{}", "Do you want to return to the menu?", "(y/n)".bright_blue(), ":");
+        println!("Do you want to return to the menu? {}:", "(y/n)".bright_blue());
         let mut return_to_menu = String::new();
         io::stdin().read_line(&mut return_to_menu)?;
         let return_to_menu = return_to_menu.trim().to_lowercase();
@@ -68,45 +99,227 @@ fn fetch_aes_key() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    let mut file = File::open(executable_path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
+    let image = load_image(executable_path)?;
+
+    let path_buf = PathBuf::from(executable_path);
+    let executable_name = path_buf
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("AES");
+
+    search_aes_key(executable_name, &image)
+}
+
+/// Keys of a packed executable are still encrypted on disk, point the user at dumping it instead
+fn warn_if_packed(image: &PeImage) {
+    let mut analysis = analyze_packing(image);
+    match find_protector_stubs(image) {
+        Ok(detections) => detections.into_iter().for_each(|detection| analysis.add_detection(detection)),
+        Err(err) => println!("{} {}", "Could not scan the entry point for protector stubs:".bright_yellow(), err),
+    }
+    if !analysis.is_packed() {
+        return;
+    }
+    println!("{}", "The executable looks packed or protected, the AES search will most likely fail.".bright_yellow().bold());
+    for detection in &analysis.detections {
+        println!("  {} ({})", detection.protector.name().bright_red(), detection.evidence);
+    }
+    for section in analysis.high_entropy_sections() {
+        println!("  {:<8} entropy {}", section.name, format!("{:.2}", section.entropy).bright_red());
+    }
+    if analysis.suspicious_entry_point {
+        println!("  Entry point {} is not in a read-only code section", format!("0x{:X}", image.entry_point()).bright_red());
+    }
+    println!("{}", "Dump the running game (option 7) or restore a memory dump (option 2) and search that instead.".bright_blue());
+}
+
+/// Maps and parses an executable in the file layout
+fn load_image(path: &str) -> Result<PeImage, Box<dyn std::error::Error>> {
+    Ok(PeImage::open(path)?)
+}
+
+/// Build metadata only describes the executable, a debug directory that fails to parse is
+/// reported and treated as absent rather than aborting the operation asking for it
+fn read_debug_info(image: &PeImage) -> Option<DebugInfo> {
+    image.debug_info().unwrap_or_else(|err| {
+        println!("{} {}", "Could not parse the debug directory:".bright_yellow(), err);
+        None
+    })
+}
+
+/// Prints the PDB identity of an executable, used to match it with its symbols and build
+fn print_debug_info(debug_info: Option<&DebugInfo>) {
+    let Some(debug_info) = debug_info else {
+        println!("{}", "No debug directory.".bright_yellow());
+        return;
+    };
+    if let Some(codeview) = &debug_info.codeview {
+        println!("PDB: {}", codeview.path().bright_blue());
+        println!("  GUID {} age {}, symbol server key {}", codeview.guid().unwrap_or_default(),
+                 codeview.age(), codeview.symbol_server_key().bright_green());
+    }
+    if let Some(pogo) = &debug_info.pogo {
+        println!("  POGO {} with {} entries", pogo.signature, pogo.entries.len());
+    }
+    if let Some(repro) = &debug_info.repro {
+        println!("  Reproducible build hash: {}", hex_string(repro));
+    }
+}
+
+fn debug_info_json(debug_info: Option<&DebugInfo>) -> serde_json::Value {
+    let Some(debug_info) = debug_info else {
+        return serde_json::Value::Null;
+    };
+    let entries = debug_info.entries.iter()
+        .map(|entry| json!({
+            "type": entry.kind_name(),
+            "time_date_stamp": entry.time_date_stamp,
+            "size": entry.size_of_data,
+            "rva": format!("0x{:X}", entry.address_of_raw_data),
+        }))
+        .collect::<Vec<_>>();
+    let codeview = debug_info.codeview.as_ref().map(|codeview| json!({
+        "pdb_path": codeview.path(),
+        "guid": codeview.guid(),
+        "age": codeview.age(),
+        "symbol_server_key": codeview.symbol_server_key(),
+    }));
+    let pogo = debug_info.pogo.as_ref().map(|pogo| json!({
+        "signature": pogo.signature,
+        "entries": pogo.entries.iter()
+            .map(|entry| json!({
+                "name": entry.name,
+                "rva": format!("0x{:X}", entry.rva),
+                "size": entry.size,
+            }))
+            .collect::<Vec<_>>(),
+    }));
+    json!({
+        "entries": entries,
+        "codeview": codeview,
+        "pogo": pogo,
+        "repro": debug_info.repro.as_deref().map(hex_string),
+    })
+}
+
+/// Same as `read_debug_info` for the version resource
+fn read_version_info(image: &PeImage) -> Option<VersionInfo> {
+    image.version_info().unwrap_or_else(|err| {
+        println!("{} {}", "Could not parse the version resource:".bright_yellow(), err);
+        None
+    })
+}
+
+/// Prints the product and version from the version resource, the build a report belongs to
+fn print_version_info(version_info: Option<&VersionInfo>) {
+    let Some(version_info) = version_info else {
+        println!("{}", "No version resource.".bright_yellow());
+        return;
+    };
+    println!("Build: {} {}", version_info.product_name().unwrap_or("Unknown product").bright_blue(),
+             version_info.product_version_string().unwrap_or_default().bright_green());
+    if let Some(company) = version_info.company_name() {
+        println!("  Company: {}", company);
+    }
+    if let Some(file_version) = version_info.file_version_string() {
+        println!("  File version: {}", file_version);
+    }
+}
+
+fn version_info_json(version_info: Option<&VersionInfo>) -> serde_json::Value {
+    let Some(version_info) = version_info else {
+        return serde_json::Value::Null;
+    };
+    let strings = version_info.strings.iter()
+        .map(|(key, value)| (key.clone(), json!(value)))
+        .collect::<serde_json::Map<String, serde_json::Value>>();
+    json!({
+        "product_name": version_info.product_name(),
+        "company_name": version_info.company_name(),
+        "file_version": version_info.file_version_string(),
+        "product_version": version_info.product_version_string(),
+        "language": version_info.language,
+        "strings": strings,
+    })
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
 
-    let header = parse_headers(&data)?;
-    let optional_header = get_optional_headers(&header)?;
-    let sections = get_sections(&header, &data)?;
+fn engine_version_json(engine: Option<&EngineVersion>) -> serde_json::Value {
+    let Some(engine) = engine else {
+        return serde_json::Value::Null;
+    };
+    json!({
+        "version": engine.to_string(),
+        "major": engine.major,
+        "minor": engine.minor,
+        "patch": engine.patch,
+        "changelist": engine.changelist,
+        "licensee": engine.licensee,
+        "branch": engine.branch,
+    })
+}
 
-    let image_base = optional_header.windows_fields.image_base as usize;
+fn search_aes_key(executable_name: &str, image: &PeImage) -> Result<(), Box<dyn std::error::Error>> {
+    let version_info = read_version_info(image);
+    let debug_info = read_debug_info(image);
+    print_version_info(version_info.as_ref());
+    print_debug_info(debug_info.as_ref());
+    warn_if_packed(image);
 
     let mut json_output = json!({
         "aes_keys": {
             "main": null,
-        }
+        },
+        "version": version_info_json(version_info.as_ref()),
+        "debug": debug_info_json(debug_info.as_ref()),
     });
 
-    let aes_keys_restricted = dump_aes_key_restricted(image_base, &sections, &data)?;
-    if !aes_keys_restricted.is_empty() {
-        let hex_key: String = format!("0x{}", aes_keys_restricted.iter().next().unwrap()
-            .iter().map(|byte| format!("{:02X}", byte)).collect::<String>());
-        json_output["aes_keys"]["main"] = json!(hex_key);
-        println!("{}: {}", "AES key for main found".bold(), hex_key.bright_green().bold());
-    } else {
-        let aes_keys_relaxed = dump_aes_key(image_base, &sections, &data)?;
-        if !aes_keys_relaxed.is_empty() {
-            let hex_key: String = format!("0x{}", aes_keys_relaxed.iter().next().unwrap()
-                .iter().map(|byte| format!("{:02X}", byte)).collect::<String>());
-            json_output["aes_keys"]["main"] = json!(hex_key);
-            println!("{}: {}", "AES key for main found".bold(), hex_key.bright_yellow().bold());
-        } else {
-            println!("{}", "No AES keys found for main in the executable.".bright_red().bold());
+    // Only orders the AES filters, not knowing the version must not stop the search
+    let engine = detect_engine_version(image).unwrap_or_else(|err| {
+        println!("{} {}", "Could not detect the Unreal Engine version:".bright_yellow(), err);
+        None
+    });
+    match &engine {
+        Some(engine) => println!("Unreal Engine: {}", engine.to_string().bright_blue()),
+        None => println!("{}", "Unreal Engine version not found.".bright_yellow()),
+    }
+    json_output["engine"] = engine_version_json(engine.as_ref());
+
+    let globals = find_unreal_globals(image, engine.as_ref());
+    let image_base = image.image_base();
+    for global in [UnrealGlobal::GNames, UnrealGlobal::GObjects, UnrealGlobal::GWorld] {
+        match globals.iter().find(|location| location.global == global) {
+            Some(location) => {
+                println!("{:<8} {} ({} references)", global.name(), format!("0x{:X}", location.rva).bright_green(),
+                         location.references);
+                json_output["offsets"][global.name()] = json!({
+                    "rva": format!("0x{:X}", location.rva),
+                    "va": format!("0x{:X}", image_base + location.rva as u64),
+                    "references": location.references,
+                });
+            }
+            None => {
+                println!("{:<8} {}", global.name(), "not found".bright_yellow());
+                json_output["offsets"][global.name()] = serde_json::Value::Null;
+            }
         }
     }
 
-    let path_buf = PathBuf::from(executable_path);
-    let executable_name = path_buf
-        .file_stem()
-        .and_then(|name| name.to_str())
-        .unwrap_or("AES");
+    match dump_aes_key_for_engine(image, engine.as_ref())? {
+        Some(aes_keys) => {
+            let hex_key: String = format!("0x{}", hex_string(aes_keys.keys.iter().next().unwrap()));
+            json_output["aes_keys"]["main"] = json!(hex_key);
+            let hex_key = match aes_keys.filter {
+                AesFilter::Restricted => hex_key.bright_green(),
+                AesFilter::Relaxed => hex_key.bright_yellow(),
+            };
+            println!("{}: {}", "AES key for main found".bold(), hex_key.bold());
+        }
+        None => println!("{}", "No AES keys found for main in the executable.".bright_red().bold()),
+    }
 
     let aes_filename = format!("{}_AES.json", executable_name);
     let mut output_file = File::create(&aes_filename)?;
@@ -132,9 +345,29 @@ fn restore_section_headers() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    let mut file = File::open(executable_path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
+    let mapped = map_file(executable_path)?;
+    let mut data = Cow::Borrowed(&mapped[..]);
+
+    let mut region_hints = vec![];
+    if data.starts_with(b"MDMP") {
+        let minidump = Minidump::parse(&data)?;
+        println!("{}", "Modules in the minidump:".bright_blue());
+        for module in minidump.modules() {
+            println!("  0x{:016X} {}", module.base, module.file_name());
+        }
+        println!("{}", "Enter the name of the module to restore:".bright_blue());
+        let mut module_name = String::new();
+        io::stdin().read_line(&mut module_name)?;
+
+        if let Some(module) = minidump.find_module(module_name.trim()) {
+            region_hints = minidump.region_hints(module);
+        }
+        let module = minidump.extract_module(module_name.trim())?;
+        if module.missing != 0 {
+            println!("{} bytes of {} were not captured and are zero filled", module.missing.to_string().bright_red(), module.name);
+        }
+        data = Cow::Owned(module.image);
+    }
 
     let path_buf = PathBuf::from(executable_path);
     let executable_name = path_buf
@@ -142,6 +375,45 @@ fn restore_section_headers() -> Result<(), Box<dyn std::error::Error>> {
         .and_then(|name| name.to_str())
         .unwrap_or("executable");
 
+    restore_image(executable_name, data, &region_hints)?;
+    Ok(())
+}
+
+/// Repairs the headers of a memory layout image when needed, restores it in the mode picked by the
+/// user and saves it next to the executable
+fn restore_image(executable_name: &str,
+                 mut data: Cow<[u8]>,
+                 region_hints: &[RegionHint]) -> Result<PeImage, Box<dyn std::error::Error>> {
+    if headers_wiped(&data) {
+        println!("{}", "The headers of the dump are damaged or wiped.".bright_red().bold());
+        println!("{}", "Enter the path to the original executable (leave empty to rebuild them heuristically):".bright_blue());
+        let mut template_path = String::new();
+        io::stdin().read_line(&mut template_path)?;
+
+        data = match template_path.trim() {
+            "" => {
+                println!("{}", "Enter the load base of the dumped module (hex):".bright_blue());
+                let mut load_base = String::new();
+                io::stdin().read_line(&mut load_base)?;
+                let load_base = parse_address(&load_base)?;
+                let is_64 = match parse_headers(&data).ok().and_then(|header| header.optional_header) {
+                    Some(optional_header) => optional_header.standard_fields.magic == MAGIC_64,
+                    // Only a 64-bit module can be loaded above 4 GiB
+                    None if load_base > u32::MAX as u64 => true,
+                    None => {
+                        println!("Is the module 64-bit? {}:", "(y/n)".bright_blue());
+                        let mut is_64 = String::new();
+                        io::stdin().read_line(&mut is_64)?;
+                        is_64.trim().to_lowercase() != "n"
+                    }
+                };
+                Cow::Owned(rebuild_headers_heuristic(&data, load_base, is_64, region_hints)?)
+            }
+            template_path => Cow::Owned(rebuild_headers_from_template(&data, &load_image(template_path)?)?),
+        };
+        println!("{}", "Headers rebuilt.".bright_green());
+    }
+
     let current_exe_dir = env::current_exe()?
         .parent()
         .ok_or("Failed to get current executable directory")?
@@ -150,17 +422,113 @@ fn restore_section_headers() -> Result<(), Box<dyn std::error::Error>> {
     let new_name = format!("{}_restored.exe", executable_name);
     let restored_filename = current_exe_dir.join(new_name).to_string_lossy().into_owned();
 
-    let restored_executable = restore_from_dump("restored_executable", &data, Some(restored_filename.clone()))?;
+    println!("{}", "Select the restore mode:".bright_blue());
+    println!("{} Original raw layout", " 1.".bright_blue());
+    println!("{} Realigned from virtual sizes", " 2.".bright_blue());
+    println!("{} Dump layout (raw pointers equal to RVAs)", " 3.".bright_blue());
+    print!("{}", "> ".bright_red());
+    io::stdout().flush()?;
+
+    let mut mode = String::new();
+    io::stdin().read_line(&mut mode)?;
+
+    let report = match mode.trim() {
+        // Realigning lays the sections out again in memory, the file is only written once it is done
+        "2" => {
+            let report = restore_from_dump_realigned(executable_name, &data)?;
+            std::fs::write(&restored_filename, &report.image)?;
+            report
+        }
+        mode => {
+            // Streamed to disk, a failed restore must not leave a partial file behind
+            let mut file = BufWriter::new(File::create(&restored_filename)?);
+            let result = match mode {
+                "3" => restore_from_dump_layout_to(executable_name, &data, &mut file),
+                _ => restore_from_dump_to(executable_name, &data, &mut file),
+            };
+            drop(file);
+            match result {
+                Ok(report) => report,
+                Err(error) => {
+                    std::fs::remove_file(&restored_filename)?;
+                    return Err(error.into());
+                }
+            }
+        }
+    };
+    drop(data);
+    print_restore_report(&report);
 
-    let mut file = File::create(&restored_filename)?;
-    file.write_all(&restored_executable)?;
+    let oep = select_entry_point(&PeImage::open(&restored_filename)?)?;
+    let mut image = map_file_mut(&restored_filename)?;
+    if let Some(oep) = oep {
+        let previous = set_entry_point(&mut image, oep)?;
+        println!("Entry point moved from {} to {}", format!("0x{:X}", previous).bright_blue(), format!("0x{:X}", oep).bright_green());
+    }
+    fix_image_headers(&mut image)?;
+    image.flush()?;
+    drop(image);
     println!("Restored executable saved to: {}", restored_filename.bright_red());
 
     println!("{}", "Executable restored successfully.".bright_green().bold());
 
+    Ok(PeImage::open(&restored_filename)?)
+}
+
+/// Lists the OEP candidates of a restored image and lets the user move the entry point, packed
+/// executables point it at the unpacker stub. Returns the chosen RVA, `None` keeps the entry point
+fn select_entry_point(image: &PeImage) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+    let candidates = find_oep_candidates(image)?;
+
+    println!("Current entry point: {}", format!("0x{:X}", image.entry_point()).bright_blue());
+    for (index, candidate) in candidates.iter().enumerate() {
+        let confirmed = match candidate.confirmed {
+            true => "confirmed".bright_green(),
+            false => "unconfirmed".bright_yellow(),
+        };
+        println!("{} 0x{:X} {:?} ({})", format!("{:>2}.", index + 1).bright_blue(), candidate.rva, candidate.kind, confirmed);
+    }
+    println!("{}", "Enter a candidate number or an OEP RVA (0x prefixed hex), leave empty to keep the entry point:".bright_blue());
+    let mut oep = String::new();
+    io::stdin().read_line(&mut oep)?;
+
+    let oep = match oep.trim() {
+        "" => return Ok(None),
+        oep => match oep.parse::<usize>() {
+            Ok(index) if index >= 1 && index <= candidates.len() => candidates[index - 1].rva,
+            _ => parse_address(oep)? as u32,
+        },
+    };
+    Ok(Some(oep))
+}
+
+/// Recomputes the stale size fields and the checksum of a restored image
+fn fix_image_headers(image: &mut [u8]) -> Result<(), Box<dyn std::error::Error>> {
+    for fixup in fix_headers(image)? {
+        println!("Fixed {} from {} to {}", fixup.field,
+                 format!("0x{:X}", fixup.old).bright_red(), format!("0x{:X}", fixup.new).bright_green());
+    }
     Ok(())
 }
 
+fn print_restore_report(report: &RestoreReport) {
+    for section in &report.sections {
+        let status = match section.status {
+            SectionStatus::Copied => "copied".bright_green(),
+            SectionStatus::Truncated => "truncated".bright_yellow(),
+            SectionStatus::Empty => "empty".normal(),
+            SectionStatus::Skipped(SkipReason::OutOfBounds) => "skipped, out of bounds".bright_red(),
+            SectionStatus::Skipped(SkipReason::MissingFromDump) => "skipped, missing from the dump".bright_red(),
+        };
+        println!("  {:<8} RVA 0x{:08X} raw 0x{:08X}, 0x{:X} bytes copied, {}",
+                 section.name, section.virtual_address, section.pointer_to_raw_data, section.bytes_copied, status);
+    }
+    if report.missing != 0 {
+        println!("{} bytes of the input could not be read and are zero filled", report.missing.to_string().bright_red());
+    }
+    println!("Restored size: 0x{:X} bytes", report.eof);
+}
+
 fn get_executable_imports() -> Result<(), Box<dyn std::error::Error>> {
     clear().expect("failed to clear console.");
     println!("{}", "Enter the path to the executable:".bright_blue());
@@ -177,49 +545,39 @@ fn get_executable_imports() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    let mut file = File::open(executable_path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
+    let image = load_image(executable_path)?;
+    let version_info = read_version_info(&image);
+    let debug_info = read_debug_info(&image);
+    print_version_info(version_info.as_ref());
+    print_debug_info(debug_info.as_ref());
 
-    let header = parse_headers(&data)?;
-    let optional_header = get_optional_headers(&header)?;
-    let sections = get_sections(&header, &data)?;
-
-    let imports = get_imports(&data, &optional_header, &sections)?;
+    let imports = image.imports()?;
     if let Some(import_data) = imports {
 
-        // Prepare JSON output
-        let mut json_output = json!({});
-
-        for (_index, import) in import_data.import_data.iter().enumerate() {
-            let library_name = import.name;
-            let mut functions = Vec::new();
-        
-            if let Some(lookup_table) = &import.import_lookup_table {
-                for (func_index, entry) in lookup_table.iter().enumerate() {
-                    match entry {
-                        SyntheticImportLookupTableEntry::OrdinalNumber(ordinal) => {
-                            functions.push(json!({
-                                "index": func_index + 1,
-                                "type": "ordinal",
-                                "ordinal": ordinal,
-                            }));
-                        }
-                        SyntheticImportLookupTableEntry::HintNameTableRVA((_rva, hint_entry)) => {
-                            functions.push(json!({
-                                "index": func_index + 1,
-                                "type": "name",
-                                "name": hint_entry.name,
-                                "hint": hint_entry.hint,
-                            }));
-                        }
-                    }
-                }
-            }
-        
-            json_output[library_name] = json!(functions);
+        let mut bound_imports = json!({});
+        for bound in &import_data.bound {
+            let forwarders = bound.forwarders.iter()
+                .map(|forwarder| json!({
+                    "name": forwarder.name,
+                    "time_date_stamp": forwarder.time_date_stamp,
+                }))
+                .collect::<Vec<_>>();
+            bound_imports[bound.name] = json!({
+                "time_date_stamp": bound.time_date_stamp,
+                "forwarders": forwarders,
+            });
         }
 
+        // Prepare JSON output, libraries stay top level keys as they always were and everything
+        // else goes under the reserved "_meta" key so it is never mistaken for a library
+        let mut json_output = imported_libraries_json(&import_data.libraries);
+        json_output["_meta"] = json!({
+            "delay_load_imports": imported_libraries_json(&import_data.delay_load),
+            "bound_imports": bound_imports,
+            "version": version_info_json(version_info.as_ref()),
+            "debug": debug_info_json(debug_info.as_ref()),
+        });
+
         // Save JSON to file
         let path_buf = PathBuf::from(executable_path);
         let executable_name = path_buf
@@ -239,4 +597,544 @@ fn get_executable_imports() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn imported_libraries_json(libraries: &[ImportedLibrary]) -> serde_json::Value {
+    let mut json_output = json!({});
+
+    for library in libraries {
+        let mut functions = Vec::new();
+
+        for (func_index, function) in library.functions.iter().enumerate() {
+            let mut entry = match (function.name, function.ordinal) {
+                (Some(name), _) => json!({
+                    "index": func_index + 1,
+                    "type": "name",
+                    "name": name,
+                    "hint": function.hint,
+                }),
+                (None, ordinal) => json!({
+                    "index": func_index + 1,
+                    "type": "ordinal",
+                    "ordinal": ordinal,
+                }),
+            };
+            entry["iat_rva"] = json!(format!("0x{:X}", function.iat_rva));
+            entry["iat_va"] = json!(format!("0x{:X}", function.iat_va));
+            entry["thunk"] = json!(format!("0x{:X}", function.thunk));
+            functions.push(entry);
+        }
+
+        json_output[library.name] = json!(functions);
+    }
+
+    json_output
+}
+
+fn get_executable_exports() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the path to the executable:".bright_blue());
+
+    let mut executable_path = String::new();
+    io::stdin().read_line(&mut executable_path)?;
+    let executable_path = executable_path.trim();
+
+    if !Path::new(executable_path).exists() {
+        eprintln!("{}: {}", "Error".bright_red().bold(), format!("The specified path does not exist: {}", executable_path).bright_red());
+        println!("{}", "Press Enter to continue...".bright_blue());
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        return Ok(());
+    }
+
+    let image = load_image(executable_path)?;
+    let version_info = read_version_info(&image);
+    let debug_info = read_debug_info(&image);
+    print_version_info(version_info.as_ref());
+    print_debug_info(debug_info.as_ref());
+
+    let exports = image.exports()?;
+    if let Some(export_data) = exports {
+        let image_base = image.image_base();
+
+        // Prepare JSON output
+        let mut functions = Vec::new();
+        for (func_index, export) in export_data.functions.iter().enumerate() {
+            functions.push(json!({
+                "index": func_index + 1,
+                "ordinal": export.ordinal,
+                "name": export.name,
+                "rva": format!("0x{:X}", export.rva),
+                "va": format!("0x{:X}", image_base + export.rva as u64),
+                "forwarder": export.forwarder,
+            }));
+        }
+
+        let path_buf = PathBuf::from(executable_path);
+        let executable_name = path_buf
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("exports");
+
+        // The library stays the top level key, metadata goes under "_meta" like in the imports
+        let library_name = export_data.name.unwrap_or(executable_name);
+        let mut json_output = json!({});
+        json_output[library_name] = json!(functions);
+        json_output["_meta"] = json!({
+            "version": version_info_json(version_info.as_ref()),
+            "debug": debug_info_json(debug_info.as_ref()),
+        });
+
+        // Save JSON to file
+        let exports_filename = format!("{}_exports.json", executable_name);
+        let mut output_file = File::create(&exports_filename)?;
+        writeln!(output_file, "{}", serde_json::to_string_pretty(&json_output)?)?;
+        println!("Exports saved to: {}", exports_filename.bright_red());
+    } else {
+        println!(
+            "{}",
+            "No exports found in the executable.".bright_red().bold()
+        );
+    }
+
+    Ok(())
+}
+
+fn parse_address(address: &str) -> Result<u64, std::num::ParseIntError> {
+    let address = address.trim();
+    let address = address.strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    u64::from_str_radix(address, 16)
+}
+
+fn rebuild_executable_imports() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the path to the restored executable:".bright_blue());
+
+    let mut executable_path = String::new();
+    io::stdin().read_line(&mut executable_path)?;
+    let executable_path = executable_path.trim();
+
+    if !Path::new(executable_path).exists() {
+        eprintln!("{}: {}", "Error".bright_red().bold(), format!("The specified path does not exist: {}", executable_path).bright_red());
+        println!("{}", "Press Enter to continue...".bright_blue());
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        return Ok(());
+    }
+
+    println!("{}", "Enter a JSON symbol map, or the export sources as DLL_PATH@LOAD_BASE separated by commas:".bright_blue());
+    let mut export_sources = String::new();
+    io::stdin().read_line(&mut export_sources)?;
+    let export_sources = export_sources.trim();
+
+    let symbols = if export_sources.to_lowercase().ends_with(".json") {
+        let symbol_map: SymbolMapJson = serde_json::from_reader(File::open(export_sources)?)?;
+        SymbolMap::try_from(&symbol_map)?
+    } else {
+        let mut symbols = SymbolMap::new();
+        for source in export_sources.split(',') {
+            let (module_path, load_base) = source.trim().rsplit_once('@')
+                .ok_or(format!("Missing load base for export source: {}", source.trim()))?;
+            let module_name = Path::new(module_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(module_path);
+            let module = load_image(module_path)?;
+            let added = symbols.add_module(module_name, parse_address(load_base)?, &module)?;
+            println!("Loaded {} exports from {}", added, module_name.bright_blue());
+        }
+        symbols
+    };
+
+    let image = load_image(executable_path)?;
+
+    let rebuilt = rebuild_imports(&image, &symbols)?;
+    println!("IAT found at RVA {} ({} bytes)",
+             format!("0x{:X}", rebuilt.iat_rva).bright_blue(), rebuilt.iat_size);
+    println!("Resolved {} imports from {} modules", rebuilt.resolved.to_string().bright_green(), rebuilt.modules.len());
+    for slot in &rebuilt.unresolved {
+        println!("{} 0x{:X}", "Unresolved IAT entry at RVA".bright_red(), slot);
+    }
+
+    let path_buf = PathBuf::from(executable_path);
+    let executable_name = path_buf
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("executable");
+
+    let current_exe_dir = env::current_exe()?
+        .parent()
+        .ok_or("Failed to get current executable directory")?
+        .to_path_buf();
+
+    let mut image = rebuilt.image;
+    fix_image_headers(&mut image)?;
+
+    let rebuilt_filename = current_exe_dir.join(format!("{}_iat.exe", executable_name)).to_string_lossy().into_owned();
+    let mut file = File::create(&rebuilt_filename)?;
+    file.write_all(&image)?;
+    println!("Rebuilt executable saved to: {}", rebuilt_filename.bright_red());
+
+    Ok(())
+}
+
+fn rebase_memory_dump() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the path to the memory dump:".bright_blue());
+
+    let mut dump_path = String::new();
+    io::stdin().read_line(&mut dump_path)?;
+    let dump_path = dump_path.trim();
+
+    if !Path::new(dump_path).exists() {
+        eprintln!("{}: {}", "Error".bright_red().bold(), format!("The specified path does not exist: {}", dump_path).bright_red());
+        println!("{}", "Press Enter to continue...".bright_blue());
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        return Ok(());
+    }
+
+    println!("{}", "Enter the address the module was loaded at (hex):".bright_blue());
+    let mut load_base = String::new();
+    io::stdin().read_line(&mut load_base)?;
+    let load_base = parse_address(&load_base)?;
+
+    println!("{}", "Enter the base to relocate to (hex) or the path to the original executable to use its ImageBase:".bright_blue());
+    let mut target_base = String::new();
+    io::stdin().read_line(&mut target_base)?;
+    let target_base = match target_base.trim() {
+        "" => None,
+        target_base if Path::new(target_base).is_file() => Some(load_image(target_base)?.image_base()),
+        target_base => Some(parse_address(target_base)?),
+    };
+
+    let path_buf = PathBuf::from(dump_path);
+    let dump_name = path_buf
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("dump");
+    let extension = path_buf
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("bin");
+
+    // Relocations are applied in place on a mapped copy of the dump
+    let rebased_filename = format!("{}_rebased.{}", dump_name, extension);
+    std::fs::copy(dump_path, &rebased_filename)?;
+    let mut data = map_file_mut(&rebased_filename)?;
+    let report = match rebase_dump(&mut data, load_base, target_base) {
+        Ok(report) => report,
+        Err(error) => {
+            drop(data);
+            std::fs::remove_file(&rebased_filename)?;
+            return Err(error.into());
+        }
+    };
+    data.flush()?;
+    println!("Rebased from {} to {}, {} fixups applied",
+             format!("0x{:X}", report.from_base).bright_blue(),
+             format!("0x{:X}", report.to_base).bright_blue(),
+             report.applied.to_string().bright_green());
+    for page_rva in &report.out_of_range_blocks {
+        println!("{} 0x{:X}", "Relocation block out of range at page RVA".bright_red(), page_rva);
+    }
+
+    println!("Rebased dump saved to: {}", rebased_filename.bright_red());
+
+    Ok(())
+}
+
+fn merge_memory_dumps() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the paths to the memory dumps of the same module, one per line (leave empty to finish):".bright_blue());
+
+    let mut dump_paths = vec![];
+    loop {
+        let mut dump_path = String::new();
+        io::stdin().read_line(&mut dump_path)?;
+        let dump_path = dump_path.trim().to_string();
+        if dump_path.is_empty() {
+            break;
+        }
+        if !Path::new(&dump_path).exists() {
+            eprintln!("{}: {}", "Error".bright_red().bold(), format!("The specified path does not exist: {}", dump_path).bright_red());
+            continue;
+        }
+        dump_paths.push(dump_path);
+    }
+    if dump_paths.len() < 2 {
+        println!("{}", "At least two dumps are needed to merge.".bright_red().bold());
+        return Ok(());
+    }
+
+    let dumps = dump_paths.iter()
+        .map(map_file)
+        .collect::<Result<Vec<_>, pe_utils::Error>>()?;
+    let report = merge_dumps(&dumps)?;
+
+    println!("{} of {} pages covered, {} conflicting",
+             report.covered_pages().to_string().bright_green(),
+             report.pages.len(),
+             report.conflicting_pages().to_string().bright_red());
+    for section in &report.sections {
+        let coverage = format!("{:.1}%", section.ratio() * 100.0);
+        let coverage = match section.covered == section.pages {
+            true => coverage.bright_green(),
+            false => coverage.bright_red(),
+        };
+        println!("  {:<8} 0x{:08X} {:>5}/{:<5} pages {} ({} conflicting)",
+                 section.name, section.virtual_address, section.covered, section.pages, coverage, section.conflicting);
+    }
+
+    let path_buf = PathBuf::from(&dump_paths[0]);
+    let dump_name = path_buf
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("dump");
+    let extension = path_buf
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("bin");
+
+    let merged_filename = format!("{}_merged.{}", dump_name, extension);
+    let mut file = File::create(&merged_filename)?;
+    file.write_all(&report.image)?;
+    println!("Merged dump saved to: {}", merged_filename.bright_red());
+
+    Ok(())
+}
+
+fn list_pak_contents() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the path to the pak file:".bright_blue());
+
+    let mut pak_path = String::new();
+    io::stdin().read_line(&mut pak_path)?;
+    let pak_path = pak_path.trim();
+
+    if !Path::new(pak_path).exists() {
+        eprintln!("{}: {}", "Error".bright_red().bold(), format!("The specified path does not exist: {}", pak_path).bright_red());
+        println!("{}", "Press Enter to continue...".bright_blue());
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+        return Ok(());
+    }
+
+    println!("{}", "Enter the AES key in hex (leave empty if the index is not encrypted):".bright_blue());
+    let mut key = String::new();
+    io::stdin().read_line(&mut key)?;
+    let key = match key.trim() {
+        "" => None,
+        key => Some(parse_key(key)?),
+    };
+
+    let pak = PakFile::open(pak_path, key.as_ref())?;
+    let info = pak.info();
+    println!("Pak version {}, mount point {}, {} files, index {}", info.version, pak.mount_point().bright_blue(),
+             pak.entries().len(), if info.encrypted_index { "encrypted".bright_yellow() } else { "plain".normal() });
+
+    let mut files = vec![];
+    for entry in pak.entries() {
+        let compression = entry.compression.as_deref().unwrap_or("None");
+        println!("  {:>12} {:>12} {:<8} {} {}", entry.uncompressed_size, entry.size, compression,
+                 if entry.encrypted { "E".bright_yellow() } else { " ".normal() }, entry.path);
+        files.push(json!({
+            "path": entry.path,
+            "offset": format!("0x{:X}", entry.offset),
+            "size": entry.size,
+            "uncompressed_size": entry.uncompressed_size,
+            "compression": entry.compression,
+            "encrypted": entry.encrypted,
+        }));
+    }
+
+    let json_output = json!({
+        "version": info.version,
+        "mount_point": pak.mount_point(),
+        "encrypted_index": info.encrypted_index,
+        "encryption_key_guid": hex_string(&info.encryption_key_guid),
+        "compression_methods": info.compression_methods,
+        "files": files,
+    });
+
+    let path_buf = PathBuf::from(pak_path);
+    let pak_name = path_buf
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("pak");
+    let files_filename = format!("{}_files.json", pak_name);
+    let mut output_file = File::create(&files_filename)?;
+    writeln!(output_file, "{}", serde_json::to_string_pretty(&json_output)?)?;
+    println!("Pak listing saved to: {}", files_filename.bright_red());
+
+    Ok(())
+}
+
+fn compare_restored_image() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the path to the restored executable:".bright_blue());
+
+    let mut restored_path = String::new();
+    io::stdin().read_line(&mut restored_path)?;
+    let restored_path = restored_path.trim();
+
+    println!("{}", "Enter the path to the original executable:".bright_blue());
+    let mut original_path = String::new();
+    io::stdin().read_line(&mut original_path)?;
+    let original_path = original_path.trim();
+
+    for path in [restored_path, original_path] {
+        if !Path::new(path).exists() {
+            eprintln!("{}: {}", "Error".bright_red().bold(), format!("The specified path does not exist: {}", path).bright_red());
+            println!("{}", "Press Enter to continue...".bright_blue());
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).unwrap();
+            return Ok(());
+        }
+    }
+
+    let restored = load_image(restored_path)?;
+    let original = load_image(original_path)?;
+    let original_version_info = read_version_info(&original);
+    let original_debug_info = read_debug_info(&original);
+    print_version_info(original_version_info.as_ref());
+    print_debug_info(original_debug_info.as_ref());
+    let comparison = compare_images(&restored, &original)?;
+
+    match comparison.headers_match {
+        true => println!("{}", "Headers match the original.".bright_green()),
+        false => println!("{} {}", "Headers differ:".bright_red(), comparison.header_differences.join(", ")),
+    }
+    let mut sections = vec![];
+    for section in &comparison.sections {
+        let similarity = format!("{:.1}%", section.similarity() * 100.0);
+        let similarity = match section.differing_pages.is_empty() {
+            true => similarity.bright_green(),
+            false => similarity.bright_yellow(),
+        };
+        println!("  {:<8} RVA 0x{:08X} similarity {:>6} entropy {:.2} -> {:.2} ({:+.2}), {} differing ranges",
+                 section.name, section.virtual_address, similarity, section.original_entropy,
+                 section.restored_entropy, section.entropy_change(), section.differing_pages.len());
+        sections.push(json!({
+            "name": section.name,
+            "rva": format!("0x{:X}", section.virtual_address),
+            "compared": section.compared,
+            "identical": section.identical,
+            "similarity": section.similarity(),
+            "original_entropy": section.original_entropy,
+            "restored_entropy": section.restored_entropy,
+            "entropy_change": section.entropy_change(),
+            "differing_pages": section.differing_pages.iter()
+                .map(|(start, end)| format!("0x{:X}-0x{:X}", start, end))
+                .collect::<Vec<String>>(),
+        }));
+    }
+    for name in &comparison.missing_sections {
+        println!("  {:<8} {}", name, "missing from the restored image".bright_red());
+    }
+
+    let json_output = json!({
+        "headers_match": comparison.headers_match,
+        "header_differences": comparison.header_differences,
+        "sections": sections,
+        "missing_sections": comparison.missing_sections,
+        "version": {
+            "restored": version_info_json(read_version_info(&restored).as_ref()),
+            "original": version_info_json(original_version_info.as_ref()),
+        },
+        "debug": {
+            "restored": debug_info_json(read_debug_info(&restored).as_ref()),
+            "original": debug_info_json(original_debug_info.as_ref()),
+        },
+    });
+
+    let path_buf = PathBuf::from(restored_path);
+    let executable_name = path_buf
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("restored");
+
+    let compare_filename = format!("{}_compare.json", executable_name);
+    let mut output_file = File::create(&compare_filename)?;
+    writeln!(output_file, "{}", serde_json::to_string_pretty(&json_output)?)?;
+    println!("Comparison saved to: {}", compare_filename.bright_red());
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn dump_process_module() -> Result<(), Box<dyn std::error::Error>> {
+    clear().expect("failed to clear console.");
+    println!("{}", "Enter the PID of the running game:".bright_blue());
+
+    let mut pid = String::new();
+    io::stdin().read_line(&mut pid)?;
+    let pid: u32 = pid.trim().parse()?;
+
+    let process = match ProcessMemory::open(pid) {
+        Ok(process) => process,
+        Err(error) => {
+            eprintln!("{}: {}", "Error".bright_red().bold(), format!("Failed to open the process memory: {}", error).bright_red());
+            println!("{}", "Reading another process requires ptrace access, run as the same user with kernel.yama.ptrace_scope = 0 or as root.".bright_blue());
+            return Ok(());
+        }
+    };
+
+    let modules = process.modules()?;
+    if modules.is_empty() {
+        println!("{}", "No PE modules are mapped in the process.".bright_red().bold());
+        return Ok(());
+    }
+    println!("{}", "Modules in the process:".bright_blue());
+    for module in &modules {
+        println!("  0x{:016X} 0x{:08X} {}", module.base, module.size, module.name);
+    }
+    println!("{}", "Enter the name of the module to dump (leave empty for the first executable):".bright_blue());
+    let mut module_name = String::new();
+    io::stdin().read_line(&mut module_name)?;
+
+    let module = match module_name.trim() {
+        "" => modules.into_iter().find(|module| module.name.to_lowercase().ends_with(".exe")),
+        module_name => process.find_module(module_name)?,
+    };
+    let Some(module) = module else {
+        println!("{}", "Module not found in the process.".bright_red().bold());
+        return Ok(());
+    };
+
+    // Wiped headers can not size the image, read the whole mapping and let the rebuild handle them
+    let (data, missing) = match read_image(&process, module.base) {
+        Ok(image) => image,
+        Err(err) => {
+            println!("{} {}", "Could not read the image from its headers:".bright_yellow(), err);
+            read_zero_filled(&process, module.base, module.size as usize)?
+        }
+    };
+    if missing != 0 {
+        println!("{} bytes of {} could not be read and are zero filled", missing.to_string().bright_red(), module.name);
+    }
+    let region_hints = region_hints(&process, module.base, data.len() as u64);
+
+    let executable_name = Path::new(&module.name)
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("executable");
+    let restored_executable = restore_image(executable_name, Cow::Owned(data), &region_hints)?;
+
+    println!("Do you want to search the restored executable for the AES key? {}:", "(y/n)".bright_blue());
+    let mut search = String::new();
+    io::stdin().read_line(&mut search)?;
+    if search.trim().eq_ignore_ascii_case("y") {
+        search_aes_key(executable_name, &restored_executable)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn dump_process_module() -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", "Dumping from a running process is only supported on Linux (Wine/Proton).".bright_red().bold());
+    Ok(())
+}
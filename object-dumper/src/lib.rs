@@ -0,0 +1,68 @@
+pub mod memory;
+pub mod names;
+pub mod objects;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("PE Utils Error: {0}")]
+    PEUtils(#[from] pe_utils::Error),
+    #[error("Restorer Error: {0}")]
+    Restorer(#[from] restorer::Error),
+    #[error("Memory at: 0x{0:X} could not be read")]
+    Unreadable(u64),
+    #[error("Invalid name entry: 0x{0:X}")]
+    InvalidName(u32),
+    #[error("Invalid object array: {0}")]
+    InvalidObjectArray(String),
+}
+
+/// Field offsets of the engine structures the walk reads. The defaults match 64-bit UE 4.23 to
+/// 5.x without case preserving names, games built with a modified engine may need their own
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// `FNamePool::Entries.CurrentBlock`
+    pub name_pool_current_block: u64,
+    /// `FNamePool::Entries.Blocks`
+    pub name_pool_blocks: u64,
+    /// Alignment of `FNameEntry`, the unit of the block offset stored in `FNameEntryId`
+    pub name_entry_stride: u64,
+    /// Shift of `Len` in `FNameEntryHeader`, `bIsWide` is always the lowest bit
+    pub name_header_length_shift: u16,
+    /// `FUObjectArray::ObjObjects.Objects`
+    pub object_array_objects: u64,
+    /// `FUObjectArray::ObjObjects.NumElements`
+    pub object_array_num_elements: u64,
+    /// `FUObjectArray::ObjObjects.NumChunks`
+    pub object_array_num_chunks: u64,
+    pub objects_per_chunk: u64,
+    /// `sizeof(FUObjectItem)`
+    pub object_item_size: u64,
+    /// `UObjectBase::InternalIndex`
+    pub object_internal_index: u64,
+    /// `UObjectBase::ClassPrivate`
+    pub object_class: u64,
+    /// `UObjectBase::NamePrivate`
+    pub object_name: u64,
+    /// `UObjectBase::OuterPrivate`
+    pub object_outer: u64,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            name_pool_current_block: 0x8,
+            name_pool_blocks: 0x10,
+            name_entry_stride: 2,
+            name_header_length_shift: 6,
+            object_array_objects: 0x10,
+            object_array_num_elements: 0x24,
+            object_array_num_chunks: 0x2C,
+            objects_per_chunk: 0x10000,
+            object_item_size: 0x18,
+            object_internal_index: 0xC,
+            object_class: 0x10,
+            object_name: 0x18,
+            object_outer: 0x20,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use goblin::pe::section_table::{IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_WRITE};
+use pe_utils::PeImage;
+use restorer::source::{MemoryRegion, MemorySource};
+
+use crate::Error;
+
+/// A restored executable (file layout) seen at the address it was dumped from, reads are
+/// translated to file offsets through the section table
+pub struct ImageSource<'a> {
+    pub base: u64,
+    pub image: &'a PeImage,
+}
+
+impl<'a> ImageSource<'a> {
+    /// Mapped at its `ImageBase`, which the loader sets to the actual base in a dump
+    pub fn new(image: &'a PeImage) -> Self {
+        ImageSource { base: image.image_base(), image }
+    }
+
+    pub fn with_base(base: u64, image: &'a PeImage) -> Self {
+        ImageSource { base, image }
+    }
+}
+
+impl MemorySource for ImageSource<'_> {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, restorer::Error> {
+        let Some(rva) = address.checked_sub(self.base) else { return Ok(0) };
+        let size_of_headers = self.image.optional_header().windows_fields.size_of_headers as u64;
+        let section = self.image.sections().iter().find(|section| {
+            let start = section.virtual_address as u64;
+            rva >= start && rva < start + section.size_of_raw_data as u64
+        });
+        // Uninitialized data past the raw size was never written to the file
+        let (offset, end) = match section {
+            Some(section) => (
+                (rva - section.virtual_address as u64 + section.pointer_to_raw_data as u64) as usize,
+                section.pointer_to_raw_data as usize + section.size_of_raw_data as usize,
+            ),
+            None if rva < size_of_headers => (rva as usize, size_of_headers as usize),
+            None => return Ok(0),
+        };
+        // A truncated file can end before the raw data of a section even starts
+        let end = end.min(self.image.data().len());
+        if offset >= end {
+            return Ok(0);
+        }
+        let length = buffer.len().min(end - offset);
+        buffer[0..length].copy_from_slice(&self.image.data()[offset..offset + length]);
+        Ok(length)
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        let rva = address.checked_sub(self.base)?;
+        self.image.sections().iter()
+            .find(|section| {
+                let start = section.virtual_address as u64;
+                rva >= start && rva < start + section.virtual_size.max(section.size_of_raw_data) as u64
+            })
+            .map(|section| MemoryRegion {
+                base: self.base + section.virtual_address as u64,
+                size: section.virtual_size.max(section.size_of_raw_data) as u64,
+                readable: true,
+                writable: section.characteristics & IMAGE_SCN_MEM_WRITE != 0,
+                executable: section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0,
+            })
+    }
+}
+
+/// Several sources over the same address space, e.g. the restored module over a minidump holding
+/// the heap. Every read is served by the first source able to provide all of it, or the one
+/// providing the most
+pub struct LayeredSource<'a> {
+    pub sources: Vec<&'a dyn MemorySource>,
+}
+
+impl<'a> LayeredSource<'a> {
+    pub fn new(sources: Vec<&'a dyn MemorySource>) -> Self {
+        LayeredSource { sources }
+    }
+}
+
+impl MemorySource for LayeredSource<'_> {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, restorer::Error> {
+        let mut best: Option<(usize, &dyn MemorySource)> = None;
+        for source in &self.sources {
+            let read = source.read(address, buffer)?;
+            if read == buffer.len() {
+                return Ok(read);
+            }
+            if read > best.map_or(0, |(read, _)| read) {
+                best = Some((read, *source));
+            }
+        }
+        match best {
+            Some((_, source)) => source.read(address, buffer),
+            None => Ok(0),
+        }
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.sources.iter().find_map(|source| source.query_region(address))
+    }
+}
+
+pub(crate) fn read_exact<S: MemorySource + ?Sized>(source: &S, address: u64, buffer: &mut [u8]) -> Result<(), Error> {
+    match source.read(address, buffer)? == buffer.len() {
+        true => Ok(()),
+        false => Err(Error::Unreadable(address)),
+    }
+}
+
+pub(crate) fn read_u32<S: MemorySource + ?Sized>(source: &S, address: u64) -> Result<u32, Error> {
+    let mut buffer = [0; 4];
+    read_exact(source, address, &mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+pub(crate) fn read_u64<S: MemorySource + ?Sized>(source: &S, address: u64) -> Result<u64, Error> {
+    let mut buffer = [0; 8];
+    read_exact(source, address, &mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA};
+    use pe_utils::PeBuilder;
+    use restorer::source::BufferSource;
+
+    use super::*;
+
+    const BASE: u64 = 0x7FF6_0000_0000;
+
+    /// `.text` (0x300 bytes at 0x1000, raw data at 0x200) and `.data` (0x80 bytes at 0x2000, raw
+    /// data at 0x600)
+    fn build_image() -> Vec<u8> {
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", vec![0xAA; 0x300], IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE).unwrap();
+        builder.add_section(".data", vec![0xBB; 0x80], IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_WRITE).unwrap();
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn reads_images_through_the_section_table() {
+        let image = PeImage::parse(build_image()).unwrap();
+        let source = ImageSource::with_base(BASE, &image);
+
+        let mut buffer = [0; 0x10];
+        assert_eq!(source.read(BASE, &mut buffer).unwrap(), 0x10);
+        assert_eq!(buffer[0..2], *b"MZ");
+        assert_eq!(source.read(BASE + 0x2070, &mut buffer).unwrap(), 0x10);
+        assert_eq!(buffer, [0xBB; 0x10]);
+        // Zero padding up to the raw size is in the file, the rest of the page is not
+        assert_eq!(source.read(BASE + 0x13F8, &mut buffer).unwrap(), 8);
+        assert_eq!(source.read(BASE + 0x1400, &mut buffer).unwrap(), 0);
+        assert_eq!(source.read(BASE - 1, &mut buffer).unwrap(), 0);
+
+        let region = source.query_region(BASE + 0x2000).unwrap();
+        assert_eq!((region.base, region.size, region.writable, region.executable), (BASE + 0x2000, 0x200, true, false));
+        assert!(source.query_region(BASE + 0x3000).is_none());
+    }
+
+    #[test]
+    fn reads_nothing_past_a_truncated_file() {
+        let mut data = build_image();
+        data.truncate(0x500);
+        let image = PeImage::parse(data).unwrap();
+        let source = ImageSource::with_base(BASE, &image);
+
+        let mut buffer = [0; 0x400];
+        assert_eq!(source.read(BASE + 0x1000, &mut buffer).unwrap(), 0x300);
+        assert_eq!(source.read(BASE + 0x2000, &mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn serves_reads_from_the_most_complete_layer() {
+        let small = [0x11; 0x100];
+        let large = [0x22; 0x200];
+        let small = BufferSource::new(BASE, &small);
+        let large = BufferSource::new(BASE, &large);
+        let source = LayeredSource::new(vec![&small, &large]);
+
+        let mut buffer = [0; 0x80];
+        assert_eq!(source.read(BASE, &mut buffer).unwrap(), 0x80);
+        assert_eq!(buffer, [0x11; 0x80]);
+        let mut buffer = [0; 0x300];
+        assert_eq!(source.read(BASE, &mut buffer).unwrap(), 0x200);
+        assert_eq!(buffer[0x1FF], 0x22);
+        assert_eq!(source.read(BASE + 0x1000, &mut buffer).unwrap(), 0);
+        assert_eq!(source.query_region(BASE + 0x180).unwrap().size, 0x200);
+
+        assert!(matches!(read_u32(&source, BASE + 0x1FE), Err(Error::Unreadable(_))));
+        assert_eq!(read_u64(&source, BASE + 0x1F8).unwrap(), 0x2222_2222_2222_2222);
+    }
+}
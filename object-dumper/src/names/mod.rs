@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use restorer::source::MemorySource;
+
+use crate::memory::{read_exact, read_u32, read_u64};
+use crate::{Error, Layout};
+
+// NAME_SIZE, longer entries are garbage
+const MAX_NAME_LENGTH: usize = 1024;
+const BLOCK_OFFSET_BITS: u32 = 16;
+// FNameMaxBlocks, the size of the block table, a higher current block means the address is wrong
+const MAX_BLOCKS: u32 = 1 << 13;
+
+/// Reads names out of an `FNamePool` (`NamePoolData`, what `GNames` points at since 4.23)
+pub struct NamePool<'a, S: MemorySource + ?Sized> {
+    source: &'a S,
+    layout: Layout,
+    blocks: Vec<u64>,
+    cache: HashMap<u32, String>,
+}
+
+impl<'a, S: MemorySource + ?Sized> NamePool<'a, S> {
+    /// Reads the block table of the pool at `address`, rejecting a current block past the end of
+    /// the table
+    pub fn new(source: &'a S, address: u64, layout: Layout) -> Result<Self, Error> {
+        let current_block = read_u32(source, address + layout.name_pool_current_block)?;
+        if current_block >= MAX_BLOCKS {
+            return Err(Error::InvalidName(current_block));
+        }
+        let blocks = (0..=current_block as u64)
+            .map(|block| read_u64(source, address + layout.name_pool_blocks + block * 8))
+            .collect::<Result<Vec<u64>, Error>>()?;
+        Ok(NamePool { source, layout, blocks, cache: HashMap::new() })
+    }
+
+    pub fn blocks(&self) -> &[u64] {
+        &self.blocks
+    }
+
+    /// The string of an `FNameEntryId`
+    pub fn entry(&mut self, id: u32) -> Result<&str, Error> {
+        if !self.cache.contains_key(&id) {
+            let name = self.read_entry(id)?;
+            self.cache.insert(id, name);
+        }
+        Ok(&self.cache[&id])
+    }
+
+    /// An `FName` as `ToString` prints it, the number is stored plus one and omitted when zero
+    pub fn name(&mut self, id: u32, number: u32) -> Result<String, Error> {
+        let entry = self.entry(id)?;
+        Ok(match number {
+            0 => entry.to_string(),
+            number => format!("{}_{}", entry, number - 1),
+        })
+    }
+
+    fn read_entry(&self, id: u32) -> Result<String, Error> {
+        let block = *self.blocks.get((id >> BLOCK_OFFSET_BITS) as usize).ok_or(Error::InvalidName(id))?;
+        if block == 0 {
+            return Err(Error::InvalidName(id));
+        }
+        let entry = block + (id & ((1 << BLOCK_OFFSET_BITS) - 1)) as u64 * self.layout.name_entry_stride;
+
+        let mut header = [0; 2];
+        read_exact(self.source, entry, &mut header)?;
+        let header = u16::from_le_bytes(header);
+        let wide = header & 1 != 0;
+        let length = (header >> self.layout.name_header_length_shift) as usize;
+        if length == 0 || length > MAX_NAME_LENGTH {
+            return Err(Error::InvalidName(id));
+        }
+
+        let mut characters = vec![0; if wide { length * 2 } else { length }];
+        read_exact(self.source, entry + 2, &mut characters)?;
+        Ok(match wide {
+            true => {
+                let units = characters.chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect::<Vec<u16>>();
+                String::from_utf16_lossy(&units)
+            }
+            // ANSI entries are Latin-1
+            false => characters.iter().map(|byte| *byte as char).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use restorer::source::BufferSource;
+
+    use super::*;
+
+    pub(crate) const BASE: u64 = 0x2_0000_0000;
+    pub(crate) const POOL: u64 = BASE;
+    const BLOCK: u64 = BASE + 0x100;
+
+    pub(crate) fn put(memory: &mut [u8], address: u64, bytes: &[u8]) {
+        let offset = (address - BASE) as usize;
+        memory[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Writes a pool at `POOL` with one block of ANSI `names`, returning their ids
+    pub(crate) fn write_pool(memory: &mut [u8], names: &[&str]) -> Vec<u32> {
+        put(memory, POOL + 0x8, &0u32.to_le_bytes());
+        put(memory, POOL + 0x10, &BLOCK.to_le_bytes());
+        let mut entry = BLOCK;
+        names.iter().map(|name| {
+            put(memory, entry, &((name.len() as u16) << 6).to_le_bytes());
+            put(memory, entry + 2, name.as_bytes());
+            let id = ((entry - BLOCK) / 2) as u32;
+            entry += (2 + name.len() as u64).next_multiple_of(2);
+            id
+        }).collect()
+    }
+
+    #[test]
+    fn reads_ansi_and_wide_entries() {
+        let mut memory = vec![0; 0x400];
+        let ids = write_pool(&mut memory, &["None", "Actor"]);
+        assert_eq!(ids, [0, 3]);
+        // A wide entry after them, "Ÿes"
+        put(&mut memory, BLOCK + 0x10, &(3u16 << 6 | 1).to_le_bytes());
+        put(&mut memory, BLOCK + 0x12, &[0x78, 0x01, b'e', 0, b's', 0]);
+
+        let source = BufferSource::new(BASE, &memory);
+        let mut pool = NamePool::new(&source, POOL, Layout::default()).unwrap();
+        assert_eq!(pool.blocks(), [BLOCK]);
+        assert_eq!(pool.entry(0).unwrap(), "None");
+        assert_eq!(pool.name(3, 0).unwrap(), "Actor");
+        assert_eq!(pool.name(3, 5).unwrap(), "Actor_4");
+        assert_eq!(pool.entry(8).unwrap(), "\u{178}es");
+
+        // An empty entry, and an entry in a block the pool does not have
+        assert!(matches!(pool.entry(0x40), Err(Error::InvalidName(0x40))));
+        assert!(matches!(pool.entry(1 << BLOCK_OFFSET_BITS), Err(Error::InvalidName(_))));
+    }
+
+    #[test]
+    fn rejects_pools_past_the_block_table() {
+        let mut memory = vec![0; 0x400];
+        write_pool(&mut memory, &["None"]);
+        put(&mut memory, POOL + 0x8, &MAX_BLOCKS.to_le_bytes());
+
+        let source = BufferSource::new(BASE, &memory);
+        assert!(matches!(NamePool::new(&source, POOL, Layout::default()), Err(Error::InvalidName(MAX_BLOCKS))));
+        assert!(matches!(NamePool::new(&source, BASE + 0x3FC, Layout::default()), Err(Error::Unreadable(_))));
+    }
+}
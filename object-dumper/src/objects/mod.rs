@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use log::{debug, info};
+use restorer::source::MemorySource;
+
+use crate::memory::{read_exact, read_u32, read_u64};
+use crate::names::NamePool;
+use crate::{Error, Layout};
+
+// Well above what shipped games reach, larger counts mean the array address is wrong
+const MAX_OBJECTS: u64 = 0x400_0000;
+// Deeper outer chains are loops in a corrupted dump
+const MAX_OUTER_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ObjectEntry {
+    /// Index in `GUObjectArray`
+    pub index: u32,
+    pub address: u64,
+    pub name: String,
+    /// Name of the object's `UClass`
+    pub class: String,
+    /// Address of the outer object, zero for packages
+    pub outer: u64,
+    /// Names of the outer chain down to the object, e.g. `/Script/Engine.Actor`
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjectDump {
+    pub objects: Vec<ObjectEntry>,
+    /// `ObjObjects.NumElements`
+    pub num_elements: u32,
+    /// Used slots whose object could not be read or did not pass validation
+    pub skipped: usize,
+}
+
+struct RawObject {
+    index: u32,
+    address: u64,
+    class: u64,
+    name: String,
+    outer: u64,
+}
+
+/// Walks the `FUObjectArray` at `gobjects` and names every live object through the `FNamePool`
+/// at `gnames`. Works on any memory source, a minidump or a restored module layered over one,
+/// without touching the process
+pub fn dump_objects<S: MemorySource + ?Sized>(source: &S, gnames: u64, gobjects: u64, layout: Layout) -> Result<ObjectDump, Error> {
+    let mut names = NamePool::new(source, gnames, layout)?;
+    let objects = read_u64(source, gobjects + layout.object_array_objects)?;
+    let num_elements = read_u32(source, gobjects + layout.object_array_num_elements)?;
+    let num_chunks = read_u32(source, gobjects + layout.object_array_num_chunks)?;
+    if num_elements as u64 > MAX_OBJECTS || (num_chunks as u64) * layout.objects_per_chunk < num_elements as u64 {
+        return Err(Error::InvalidObjectArray(format!("{} elements in {} chunks", num_elements, num_chunks)));
+    }
+    info!("{} objects in {} chunks, {} name blocks", num_elements, num_chunks, names.blocks().len());
+
+    let header_size = [
+        layout.object_internal_index + 4,
+        layout.object_class + 8,
+        layout.object_name + 8,
+        layout.object_outer + 8,
+    ].into_iter().max().unwrap_or_default() as usize;
+
+    let mut dump = ObjectDump { num_elements, ..Default::default() };
+    let mut raw_objects = vec![];
+    for chunk_index in 0..num_chunks as u64 {
+        let first = chunk_index * layout.objects_per_chunk;
+        let count = (num_elements as u64).saturating_sub(first).min(layout.objects_per_chunk) as usize;
+        let chunk = read_u64(source, objects + chunk_index * 8)?;
+        let mut items = vec![0; count * layout.object_item_size as usize];
+        let read = match chunk {
+            0 => 0,
+            chunk => source.read(chunk, &mut items)?,
+        };
+        if read != items.len() {
+            debug!("Chunk {} at 0x{:X} is only readable up to 0x{:X}", chunk_index, chunk, read);
+        }
+
+        for item in 0..count {
+            let index = (first + item as u64) as u32;
+            let item = item * layout.object_item_size as usize;
+            let Some(address) = items.get(item..item + 8).filter(|_| item + 8 <= read) else {
+                dump.skipped += 1;
+                continue;
+            };
+            let address = u64::from_le_bytes(address.try_into().unwrap());
+            // Free slot
+            if address == 0 {
+                continue;
+            }
+            match read_object(source, &mut names, &layout, index, address, header_size) {
+                Ok(object) => raw_objects.push(object),
+                Err(err) => {
+                    debug!("Skipping object {} at 0x{:X}: {}", index, address, err);
+                    dump.skipped += 1;
+                }
+            }
+        }
+    }
+
+    let by_address = raw_objects.iter()
+        .map(|object| (object.address, object))
+        .collect::<HashMap<u64, &RawObject>>();
+    for object in &raw_objects {
+        let class = match by_address.get(&object.class) {
+            Some(class) => class.name.clone(),
+            None => read_name(source, &mut names, &layout, object.class).unwrap_or_else(|_| "Unknown".to_string()),
+        };
+        let mut path = vec![object.name.as_str()];
+        let mut outer = object.outer;
+        while let Some(parent) = by_address.get(&outer).filter(|_| path.len() < MAX_OUTER_DEPTH) {
+            path.push(&parent.name);
+            outer = parent.outer;
+        }
+        path.reverse();
+
+        dump.objects.push(ObjectEntry {
+            index: object.index,
+            address: object.address,
+            name: object.name.clone(),
+            class,
+            outer: object.outer,
+            path: path.join("."),
+        });
+    }
+    Ok(dump)
+}
+
+fn read_object<S: MemorySource + ?Sized>(source: &S,
+                                         names: &mut NamePool<S>,
+                                         layout: &Layout,
+                                         index: u32,
+                                         address: u64,
+                                         header_size: usize) -> Result<RawObject, Error> {
+    let mut header = vec![0; header_size];
+    read_exact(source, address, &mut header)?;
+    let field_u32 = |offset: u64| u32::from_le_bytes(header[offset as usize..offset as usize + 4].try_into().unwrap());
+    let field_u64 = |offset: u64| u64::from_le_bytes(header[offset as usize..offset as usize + 8].try_into().unwrap());
+
+    // Every live object knows its own slot, anything else is a stale or misread pointer
+    if field_u32(layout.object_internal_index) != index {
+        return Err(Error::InvalidObjectArray(format!("object {} has internal index {}", index, field_u32(layout.object_internal_index))));
+    }
+    Ok(RawObject {
+        index,
+        address,
+        class: field_u64(layout.object_class),
+        name: names.name(field_u32(layout.object_name), field_u32(layout.object_name + 4))?,
+        outer: field_u64(layout.object_outer),
+    })
+}
+
+fn read_name<S: MemorySource + ?Sized>(source: &S, names: &mut NamePool<S>, layout: &Layout, object: u64) -> Result<String, Error> {
+    let id = read_u32(source, object + layout.object_name)?;
+    let number = read_u32(source, object + layout.object_name + 4)?;
+    names.name(id, number)
+}
+
+#[cfg(test)]
+mod tests {
+    use restorer::source::BufferSource;
+
+    use super::*;
+    use crate::names::tests::{put, write_pool, BASE, POOL};
+
+    const GOBJECTS: u64 = BASE + 0x400;
+    const CHUNKS: u64 = BASE + 0x480;
+    const ITEMS: u64 = BASE + 0x500;
+
+    fn object(index: u64) -> u64 {
+        BASE + 0x800 + index * 0x40
+    }
+
+    fn write_object(memory: &mut [u8], address: u64, index: u32, class: u64, name: u32, outer: u64) {
+        put(memory, address + 0xC, &index.to_le_bytes());
+        put(memory, address + 0x10, &class.to_le_bytes());
+        put(memory, address + 0x18, &name.to_le_bytes());
+        put(memory, address + 0x20, &outer.to_le_bytes());
+    }
+
+    /// A package, a class and an actor class in slots 0 to 2, a free slot and a stale pointer to
+    /// an object claiming another index. The package's class is not in the array
+    fn build_memory() -> Vec<u8> {
+        let mut memory = vec![0; 0x1000];
+        let ids = write_pool(&mut memory, &["/Script/Engine", "Actor", "Class", "Package"]);
+
+        put(&mut memory, GOBJECTS + 0x10, &CHUNKS.to_le_bytes());
+        put(&mut memory, GOBJECTS + 0x24, &5u32.to_le_bytes());
+        put(&mut memory, GOBJECTS + 0x2C, &1u32.to_le_bytes());
+        put(&mut memory, CHUNKS, &ITEMS.to_le_bytes());
+        for (slot, address) in [object(0), object(1), object(2), 0, object(5)].into_iter().enumerate() {
+            put(&mut memory, ITEMS + slot as u64 * 0x18, &address.to_le_bytes());
+        }
+
+        write_object(&mut memory, object(0), 0, object(4), ids[0], 0);
+        write_object(&mut memory, object(1), 1, object(2), ids[1], object(0));
+        write_object(&mut memory, object(2), 2, object(2), ids[2], object(0));
+        write_object(&mut memory, object(4), 9, 0, ids[3], 0);
+        write_object(&mut memory, object(5), 7, object(2), ids[1], 0);
+        memory
+    }
+
+    #[test]
+    fn names_objects_and_their_outer_chains() {
+        let memory = build_memory();
+        let source = BufferSource::new(BASE, &memory);
+        let dump = dump_objects(&source, POOL, GOBJECTS, Layout::default()).unwrap();
+
+        assert_eq!((dump.num_elements, dump.skipped), (5, 1));
+        let objects = dump.objects.iter()
+            .map(|object| (object.index, object.class.as_str(), object.path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(objects, [
+            (0, "Package", "/Script/Engine"),
+            (1, "Class", "/Script/Engine.Actor"),
+            (2, "Class", "/Script/Engine.Class"),
+        ]);
+        assert_eq!((dump.objects[1].address, dump.objects[1].outer), (object(1), object(0)));
+    }
+
+    #[test]
+    fn rejects_object_arrays_with_too_few_chunks() {
+        let mut memory = build_memory();
+        put(&mut memory, GOBJECTS + 0x2C, &0u32.to_le_bytes());
+        let source = BufferSource::new(BASE, &memory);
+        assert!(matches!(dump_objects(&source, POOL, GOBJECTS, Layout::default()), Err(Error::InvalidObjectArray(_))));
+    }
+}
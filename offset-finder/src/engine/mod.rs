@@ -0,0 +1,329 @@
+use std::fmt;
+
+use log::debug;
+use pe_utils::PeImage;
+
+use crate::{Error, OffsetLocator};
+
+// Set on the changelist of licensee builds
+const LICENSEE_BIT: u32 = 0x8000_0000;
+const BRANCH_PREFIX: &str = "++UE";
+const MAX_BRANCH_LENGTH: usize = 64;
+
+/// The engine version the executable was built with, as stored in `FEngineVersion::Current()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineVersion {
+    pub major: u16,
+    pub minor: u16,
+    /// `None` when only the branch name was found
+    pub patch: Option<u16>,
+    pub changelist: Option<u32>,
+    /// Built from a licensee changelist rather than an Epic one
+    pub licensee: bool,
+    /// e.g. `++UE5+Release-5.3`
+    pub branch: Option<String>,
+}
+
+impl EngineVersion {
+    pub fn at_least(&self, major: u16, minor: u16) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+}
+
+impl fmt::Display for EngineVersion {
+    /// Same layout as `FEngineVersion::ToString`, `5.3.2-29314046+++UE5+Release-5.3`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        }
+        if let Some(changelist) = self.changelist {
+            write!(f, "-{}", changelist)?;
+        }
+        if let Some(branch) = &self.branch {
+            write!(f, "+{}", branch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Locators that only apply to a range of engine versions
+pub struct OffsetProfile<'a> {
+    pub name: &'a str,
+    /// Inclusive `(major, minor)` bounds, `None` leaves that side open
+    pub min_engine_version: Option<(u16, u16)>,
+    pub max_engine_version: Option<(u16, u16)>,
+    pub locators: Vec<OffsetLocator<'a>>,
+}
+
+impl<'a> OffsetProfile<'a> {
+    pub fn matches(&self, version: &EngineVersion) -> bool {
+        let version = (version.major, version.minor);
+        self.min_engine_version.is_none_or(|min| version >= min)
+            && self.max_engine_version.is_none_or(|max| version <= max)
+    }
+
+    fn is_generic(&self) -> bool {
+        self.min_engine_version.is_none() && self.max_engine_version.is_none()
+    }
+}
+
+/// The first profile covering `version`, falling back to the first one without bounds when the
+/// version is unknown or no profile covers it
+pub fn select_profile<'p, 'a>(profiles: &'p [OffsetProfile<'a>],
+                              version: Option<&EngineVersion>) -> Option<&'p OffsetProfile<'a>> {
+    version
+        .and_then(|version| profiles.iter().find(|profile| !profile.is_generic() && profile.matches(version)))
+        .or_else(|| profiles.iter().find(|profile| profile.is_generic()))
+}
+
+/// A store of the inlined `FEngineVersionBase` constructor: displacement and immediate offsets
+/// inside the match, end of the instruction and the field it writes
+struct Store {
+    displacement: usize,
+    immediate: usize,
+    end: usize,
+    field: i64,
+}
+
+// mov dword ptr [Major], Minor << 16 | Major; mov word ptr [Patch], ?; mov dword ptr [Changelist], ?
+// and the same with the patch and changelist stores swapped
+const INITIALIZERS: [[Store; 3]; 2] = [
+    [
+        Store { displacement: 2, immediate: 6, end: 10, field: 0 },
+        Store { displacement: 13, immediate: 17, end: 19, field: 4 },
+        Store { displacement: 21, immediate: 25, end: 29, field: 8 },
+    ],
+    [
+        Store { displacement: 2, immediate: 6, end: 10, field: 0 },
+        Store { displacement: 23, immediate: 27, end: 29, field: 4 },
+        Store { displacement: 12, immediate: 16, end: 20, field: 8 },
+    ],
+];
+
+fn get_initializer_locator() -> OffsetLocator<'static> {
+    OffsetLocator {
+        name: "FEngineVersion",
+        partial_match: vec![
+            "c7 05 ?? ?? ?? ?? ?? 00 ?? 00 66 c7 05 ?? ?? ?? ?? ?? 00 c7 05 ?? ?? ?? ?? ?? ?? ?? ??",
+            "c7 05 ?? ?? ?? ?? ?? 00 ?? 00 c7 05 ?? ?? ?? ?? ?? ?? ?? ?? 66 c7 05 ?? ?? ?? ?? ?? 00",
+        ],
+        full_match: "",
+        skip_offset_print: true,
+        allow_multiple_matches: true,
+    }
+}
+
+/// Detects the Unreal Engine version from the inlined `FEngineVersion` initializer, completed or
+/// replaced by the `++UE4+Release-4.xx`/`++UE5+Release-5.x` branch name
+pub fn detect_engine_version(image: &PeImage) -> Result<Option<EngineVersion>, Error> {
+    let branch = find_branch(image.data());
+    let initializer = find_initializer(image)?;
+    debug!("Engine initializer: {:?}, branch: {:?}", initializer, branch);
+
+    let version = match (initializer, branch) {
+        (Some(mut version), Some((major, minor, branch))) => {
+            // A branch naming another version belongs to something else linked in, a plugin or SDK
+            if (version.major, version.minor) == (major, minor) {
+                version.branch = Some(branch);
+            }
+            Some(version)
+        }
+        (Some(version), None) => Some(version),
+        (None, Some((major, minor, branch))) => Some(EngineVersion {
+            major,
+            minor,
+            patch: None,
+            changelist: None,
+            licensee: false,
+            branch: Some(branch),
+        }),
+        (None, None) => None,
+    };
+    Ok(version)
+}
+
+fn find_initializer(image: &PeImage) -> Result<Option<EngineVersion>, Error> {
+    let matches = match get_initializer_locator().find_all_partial_only(image) {
+        Ok(matches) => matches,
+        Err(Error::NotFound(_)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let data = image.data();
+    let read = |offset: usize, size: usize| -> Option<u32> {
+        let bytes = data.get(offset..offset + size)?;
+        Some(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32))
+    };
+
+    for (pattern, items) in matches {
+        let stores = &INITIALIZERS[pattern];
+        for (offset, _) in items {
+            // All three stores must land in the same struct, the displacements are relative to the
+            // end of each instruction
+            let targets = stores.iter()
+                .map(|store| Some(read(offset + store.displacement, 4)? as i32 as i64 + store.end as i64 - store.field))
+                .collect::<Option<Vec<i64>>>();
+            if !targets.is_some_and(|targets| targets.iter().all(|target| *target == targets[0])) {
+                continue;
+            }
+            let (Some(major_minor), Some(patch), Some(changelist)) = (
+                read(offset + stores[0].immediate, 4),
+                read(offset + stores[1].immediate, 2),
+                read(offset + stores[2].immediate, 4),
+            ) else { continue };
+            let (major, minor, patch) = (major_minor as u16, (major_minor >> 16) as u16, patch as u16);
+            if !(4..=5).contains(&major) || minor > 30 || patch > 30 {
+                continue;
+            }
+            return Ok(Some(EngineVersion {
+                major,
+                minor,
+                patch: Some(patch),
+                changelist: Some(changelist & !LICENSEE_BIT),
+                licensee: changelist & LICENSEE_BIT != 0,
+                branch: None,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// First `++UE4+Release-4.xx`/`++UE5+Release-5.x` string, UTF-16 (`TEXT()`) or ANSI
+fn find_branch(data: &[u8]) -> Option<(u16, u16, String)> {
+    let wide = BRANCH_PREFIX.bytes().flat_map(|byte| [byte, 0]).collect::<Vec<u8>>();
+    let candidates = find_all(data, &wide).map(|offset| (offset, 2))
+        .chain(find_all(data, BRANCH_PREFIX.as_bytes()).map(|offset| (offset, 1)));
+    for (offset, width) in candidates {
+        let branch = data[offset..].chunks(width)
+            .take(MAX_BRANCH_LENGTH)
+            .take_while(|unit| unit[1..].iter().all(|byte| *byte == 0))
+            .map(|unit| unit[0] as char)
+            .take_while(|char| char.is_ascii_alphanumeric() || "+-._".contains(*char))
+            .collect::<String>();
+        if let Some((major, minor)) = parse_branch(&branch) {
+            return Some((major, minor, branch));
+        }
+    }
+    None
+}
+
+fn parse_branch(branch: &str) -> Option<(u16, u16)> {
+    let rest = branch.strip_prefix(BRANCH_PREFIX)?;
+    let (engine, release) = rest.split_once("+Release-")?;
+    let mut numbers = release.split(['.', '-']);
+    let major = numbers.next()?.parse::<u16>().ok()?;
+    let minor = numbers.next()?.parse::<u16>().ok()?;
+    match engine.parse::<u16>().ok()? == major {
+        true => Some((major, minor)),
+        false => None,
+    }
+}
+
+fn find_all<'d>(data: &'d [u8], needle: &'d [u8]) -> impl Iterator<Item = usize> + 'd {
+    data.windows(needle.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == needle)
+        .map(|(offset, _)| offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA};
+    use pe_utils::PeBuilder;
+
+    use super::*;
+
+    fn wide(text: &str) -> Vec<u8> {
+        text.bytes().flat_map(|byte| [byte, 0]).chain([0, 0]).collect()
+    }
+
+    /// The inlined `FEngineVersionBase` constructor, every store targeting the same struct
+    fn initializer(major: u16, minor: u16, patch: u16, changelist: u32) -> Vec<u8> {
+        let target: i32 = 0x1000;
+        let mut code = vec![0xC7, 0x05];
+        code.extend((target - 10).to_le_bytes());
+        code.extend(((minor as u32) << 16 | major as u32).to_le_bytes());
+        code.extend([0x66, 0xC7, 0x05]);
+        code.extend((target - 19 + 4).to_le_bytes());
+        code.extend(patch.to_le_bytes());
+        code.extend([0xC7, 0x05]);
+        code.extend((target - 29 + 8).to_le_bytes());
+        code.extend(changelist.to_le_bytes());
+        code
+    }
+
+    fn build_image(code: Vec<u8>, rdata: Vec<u8>) -> PeImage {
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", code, IMAGE_SCN_CNT_CODE).unwrap();
+        builder.add_section(".rdata", rdata, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parses_branch_names() {
+        assert_eq!(parse_branch("++UE5+Release-5.3"), Some((5, 3)));
+        assert_eq!(parse_branch("++UE4+Release-4.27-Chaos"), Some((4, 27)));
+        assert_eq!(parse_branch("++UE4+Release-5.0"), None);
+        assert_eq!(parse_branch("++Fortnite+Release-28.10"), None);
+
+        let mut data = b"\0junk ++UE4+Release-4.26\0".to_vec();
+        assert_eq!(find_branch(&data), Some((4, 26, "++UE4+Release-4.26".to_string())));
+        data.splice(0..0, wide("++UE5+Release-5.1"));
+        assert_eq!(find_branch(&data), Some((5, 1, "++UE5+Release-5.1".to_string())));
+        assert_eq!(find_branch(b"++UE5+Main"), None);
+    }
+
+    #[test]
+    fn detects_the_initializer_and_branch() {
+        let image = build_image(initializer(5, 3, 2, 29314046), wide("++UE5+Release-5.3"));
+        let version = detect_engine_version(&image).unwrap().unwrap();
+
+        assert_eq!(version, EngineVersion {
+            major: 5,
+            minor: 3,
+            patch: Some(2),
+            changelist: Some(29314046),
+            licensee: false,
+            branch: Some("++UE5+Release-5.3".to_string()),
+        });
+        assert_eq!(version.to_string(), "5.3.2-29314046+++UE5+Release-5.3");
+        assert!(version.at_least(4, 22) && version.at_least(5, 3) && !version.at_least(5, 4));
+    }
+
+    #[test]
+    fn ignores_branches_of_other_versions() {
+        let image = build_image(initializer(4, 27, 2, LICENSEE_BIT | 1234), wide("++UE5+Release-5.0"));
+        let version = detect_engine_version(&image).unwrap().unwrap();
+
+        assert_eq!((version.major, version.minor, version.patch), (4, 27, Some(2)));
+        assert_eq!((version.changelist, version.licensee), (Some(1234), true));
+        assert_eq!(version.branch, None);
+        assert_eq!(version.to_string(), "4.27.2-1234");
+    }
+
+    #[test]
+    fn falls_back_to_the_branch() {
+        // The stores do not target the same struct
+        let mut code = initializer(5, 2, 1, 1);
+        code[21] ^= 0x40;
+        let image = build_image(code, b"++UE5+Release-5.2\0".to_vec());
+        let version = detect_engine_version(&image).unwrap().unwrap();
+        assert_eq!((version.major, version.minor, version.patch, version.changelist), (5, 2, None, None));
+        assert_eq!(version.to_string(), "5.2+++UE5+Release-5.2");
+
+        let image = build_image(vec![0xC3; 0x10], vec![0; 0x10]);
+        assert_eq!(detect_engine_version(&image).unwrap(), None);
+    }
+
+    #[test]
+    fn selects_the_profile_for_a_version() {
+        let profile = |name, min, max| OffsetProfile { name, min_engine_version: min, max_engine_version: max, locators: vec![] };
+        let profiles = [profile("generic", None, None), profile("ue4", Some((4, 0)), Some((4, 27))), profile("ue5", Some((5, 0)), None)];
+        let version = |major, minor| EngineVersion { major, minor, patch: None, changelist: None, licensee: false, branch: None };
+
+        assert_eq!(select_profile(&profiles, Some(&version(4, 26))).unwrap().name, "ue4");
+        assert_eq!(select_profile(&profiles, Some(&version(5, 4))).unwrap().name, "ue5");
+        assert_eq!(select_profile(&profiles, Some(&version(3, 0))).unwrap().name, "generic");
+        assert_eq!(select_profile(&profiles, None).unwrap().name, "generic");
+    }
+}
@@ -2,6 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::engine::OffsetProfile;
 use crate::OffsetLocator;
 
 #[derive(Serialize, Deserialize)]
@@ -29,4 +30,25 @@ impl<'a> Into<OffsetLocator<'a>> for &'a OffsetLocatorJson {
             allow_multiple_matches: self.allow_multiple_matches,
         }
     }
-}
\ No newline at end of file
+}
+#[derive(Serialize, Deserialize)]
+pub struct OffsetProfileJson {
+    pub name: String,
+    /// `[major, minor]`, inclusive
+    #[serde(default)]
+    pub min_engine_version: Option<(u16, u16)>,
+    #[serde(default)]
+    pub max_engine_version: Option<(u16, u16)>,
+    pub locators: Vec<OffsetLocatorJson>,
+}
+
+impl<'a> From<&'a OffsetProfileJson> for OffsetProfile<'a> {
+    fn from(profile: &'a OffsetProfileJson) -> Self {
+        OffsetProfile {
+            name: &profile.name,
+            min_engine_version: profile.min_engine_version,
+            max_engine_version: profile.max_engine_version,
+            locators: profile.locators.iter().map(|locator| locator.into()).collect(),
+        }
+    }
+}
@@ -3,9 +3,13 @@ use std::collections::HashMap;
 use goblin::pe::section_table::SectionTable;
 use log::{debug, warn};
 use patternscanner::PatternScannerBuilder;
+use pe_utils::PeImage;
 
+pub mod engine;
 #[cfg(feature = "json_input")]
 pub mod json;
+pub mod packer;
+pub mod unreal;
 
 pub struct OffsetLocator<'a> {
     pub name: &'a str,
@@ -21,6 +25,8 @@ pub enum Error {
     NotFound(String),
     #[error("Too many matches found for: {0}")]
     TooManyMatches(String),
+    #[error("Operand out of bounds at: 0x{0:X}")]
+    OutOfBounds(usize),
     #[error("PE Utils: {0}")]
     PeUtils(#[from] pe_utils::Error),
     #[error("Toml Error: {0}")]
@@ -59,13 +65,17 @@ fn find_all_pattern(image_base: usize,
         .build()
         .scan_all(pattern)?;
 
-    if items.is_empty() {
-        return Err(Error::NotFound(name.to_string()));
-    }
-
     let mut output: Vec<(usize, usize)> = Vec::with_capacity(items.len());
     for item in items {
-        output.push((item, pe_utils::resolve_symbol(image_base, sections, item)?));
+        // Matches in the headers or the overlay have no virtual address
+        match pe_utils::resolve_symbol(image_base, sections, item) {
+            Ok(address) => output.push((item, address)),
+            Err(pe_utils::Error::NotInSection(_)) => debug!("Skipping match outside of any section for: {}, 0x{:X}", name, item),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    if output.is_empty() {
+        return Err(Error::NotFound(name.to_string()));
     }
     Ok(output)
 }
@@ -96,13 +106,13 @@ fn find_all_patterns(image_base: usize,
                      patterns: &[&str],
                      name: &str) -> Result<HashMap<usize, Vec<(usize, usize)>>, Error> {
     let mut output = HashMap::new();
-    for i in 0..patterns.len() {
-        let result = match find_all_pattern(image_base, sections, data, &patterns[i], name) {
+    for (i, pattern) in patterns.iter().enumerate() {
+        let result = match find_all_pattern(image_base, sections, data, pattern, name) {
             Ok(result) => Ok(result),
             Err(Error::NotFound(_)) => continue,
             Err(err) => Err(err)
         }?;
-        debug!("Partial pattern match with: {}", &patterns[i]);
+        debug!("Partial pattern match with: {}", pattern);
         output.insert(i, result);
     }
     match output.is_empty() {
@@ -112,14 +122,12 @@ fn find_all_patterns(image_base: usize,
 }
 
 impl<'a> OffsetLocator<'a> {
-    pub fn find_offset(&self,
-                       image_base: usize,
-                       sections: &[SectionTable],
-                       executable: &[u8]) -> Result<(usize, usize, bool), Error> {
+    pub fn find_offset(&self, image: &PeImage) -> Result<(usize, usize, bool), Error> {
+        let image_base = image.image_base() as usize;
         match find_pattern(
             image_base,
-            sections,
-            executable,
+            image.sections(),
+            image.data(),
             self.full_match,
             self.name,
             self.allow_multiple_matches,
@@ -128,8 +136,8 @@ impl<'a> OffsetLocator<'a> {
             Err(Error::NotFound(_)) => {
                 let result = find_patterns(
                     image_base,
-                    sections,
-                    executable,
+                    image.sections(),
+                    image.data(),
                     &self.partial_match,
                     self.name,
                     self.allow_multiple_matches,
@@ -140,16 +148,25 @@ impl<'a> OffsetLocator<'a> {
         }
     }
 
-    pub fn find_all_partial_only(&self,
-                                 image_base: usize,
-                                 sections: &[SectionTable],
-                                 executable: &[u8]) -> Result<HashMap<usize, Vec<(usize, usize)>>, Error> {
+    pub fn find_all_partial_only(&self, image: &PeImage) -> Result<HashMap<usize, Vec<(usize, usize)>>, Error> {
         find_all_patterns(
-            image_base,
-            sections,
-            executable,
+            image.image_base() as usize,
+            image.sections(),
+            image.data(),
             &self.partial_match,
             self.name,
         )
     }
-}
\ No newline at end of file
+}
+
+/// Resolves the RIP-relative operand of the instruction at file offset `offset`: the signed 32-bit
+/// displacement `displacement` bytes into it is relative to its end, `length` bytes further.
+/// Returns the virtual address it points to
+pub fn resolve_rip_relative(image: &PeImage, offset: usize, displacement: usize, length: usize) -> Result<usize, Error> {
+    let bytes = image.data()
+        .get(offset + displacement..offset + displacement + 4)
+        .ok_or(Error::OutOfBounds(offset + displacement))?;
+    let displacement = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let next_instruction = image.resolve_symbol(offset)? + length;
+    Ok(next_instruction.wrapping_add_signed(displacement as isize))
+}
@@ -0,0 +1,171 @@
+use log::debug;
+use patternscanner::PatternScannerBuilder;
+use pe_utils::{Detection, PeImage, Protector, HIGH_ENTROPY};
+
+use crate::{resolve_rip_relative, Error};
+
+/// Code a protector puts at the entry point of the executables it wraps
+struct Stub {
+    /// `None` when the stub only says where control goes, the protector then comes from the
+    /// section it jumps into
+    protector: Option<Protector>,
+    pattern: &'static str,
+    description: &'static str,
+    /// The stub is too generic on its own, it ends with a `call`/`jmp rel32` that has to land in
+    /// a section of the protector or in packed data
+    needs_target: bool,
+}
+
+// Every stub is matched at the entry point only, most of them are too short to mean anything elsewhere
+const STUBS: [Stub; 7] = [
+    // mov eax, imm32; pushad; or eax, eax; jz; call $+5; pop eax; add eax, imm32
+    Stub { protector: Some(Protector::Themida), pattern: "b8 ?? ?? ?? ?? 60 0b c0 74 ?? e8 00 00 00 00 58 05", description: "Themida loader stub", needs_target: false },
+    // push ebp; mov ebp, esp; add esp, -28h; pushad; call $+5; pop edx; sub edx, imm32; mov ebx, edx
+    Stub { protector: Some(Protector::Themida), pattern: "55 8b ec 83 c4 d8 60 e8 00 00 00 00 5a 81 ea ?? ?? ?? ?? 8b da", description: "WinLicense loader stub", needs_target: false },
+    // push imm32; call vm_entry
+    Stub { protector: Some(Protector::VMProtect), pattern: "68 ?? ?? ?? ?? e8 ?? ?? ?? ??", description: "VMProtect virtualized entry into", needs_target: true },
+    // push imm32; jmp vm_entry
+    Stub { protector: Some(Protector::VMProtect), pattern: "68 ?? ?? ?? ?? e9 ?? ?? ?? ??", description: "VMProtect virtualized entry into", needs_target: true },
+    // pushad; call $+5; pop ebp; sub ebp, 6; sub ebp, imm32
+    Stub { protector: Some(Protector::Enigma), pattern: "60 e8 00 00 00 00 5d 83 ed 06 81 ed ?? ?? ?? ??", description: "Enigma loader stub", needs_target: false },
+    // push ebp; mov ebp, esp; add esp, -10h; mov eax, 401000h; call $+6; db 9Ah; add esp, 10h; mov esp, ebp; pop ebp; jmp
+    Stub { protector: Some(Protector::Enigma), pattern: "55 8b ec 83 c4 f0 b8 00 10 40 00 e8 01 00 00 00 9a 83 c4 10 8b e5 5d e9", description: "Enigma loader stub", needs_target: false },
+    // jmp rel32, Denuvo and the other protectors moving the entry point into their own section
+    Stub { protector: None, pattern: "e9 ?? ?? ?? ??", description: "entry point jumps into", needs_target: true },
+];
+
+// Bytes read at the entry point, enough for the longest stub
+const STUB_WINDOW: usize = 32;
+// Opcode and rel32 operand
+const REL32_LENGTH: usize = 5;
+
+/// Looks for the loader stubs of Themida/WinLicense, VMProtect and Enigma at the entry point of an
+/// executable (file layout), and for an entry point jumping straight into a section a protector is
+/// known to add, which is how Denuvo shows up. Meant to complement `pe_utils::analyze_packing`
+pub fn find_protector_stubs(image: &PeImage) -> Result<Vec<Detection>, Error> {
+    let entry_point = image.entry_point();
+    if entry_point == 0 {
+        return Ok(vec![]);
+    }
+    // An entry point without raw data has nothing to match
+    let Ok(offset) = image.rva_to_offset(entry_point as usize) else {
+        return Ok(vec![]);
+    };
+    let Some(window) = image.data().get(offset..(offset + STUB_WINDOW).min(image.data().len())) else {
+        return Ok(vec![]);
+    };
+    let scanner = PatternScannerBuilder::builder().with_bytes(window).build();
+
+    let mut detections: Vec<Detection> = vec![];
+    for stub in &STUBS {
+        if !scanner.scan_all(stub.pattern)?.contains(&0) {
+            continue;
+        }
+        // The call or jmp closes the stub
+        let branch = offset + stub.pattern.split_whitespace().count() - REL32_LENGTH;
+        let detection = match (stub.protector, stub.needs_target) {
+            (Some(protector), false) => Detection { protector, evidence: stub.description.to_string() },
+            (protector, true) => match branch_target(image, branch, protector)? {
+                Some((protector, section)) => Detection { protector, evidence: format!("{} {}", stub.description, section) },
+                None => continue,
+            },
+            (None, false) => continue,
+        };
+        debug!("{} stub at the entry point 0x{:X}", detection.protector.name(), entry_point);
+        if !detections.iter().any(|known| known.protector == detection.protector) {
+            detections.push(detection);
+        }
+    }
+    Ok(detections)
+}
+
+/// Where the `call`/`jmp rel32` at `offset` lands: a section named after a protector, or for an
+/// `expected` protector also a high entropy section since virtualized code is packed too. Returns
+/// the protector and the section name
+fn branch_target(image: &PeImage, offset: usize, expected: Option<Protector>) -> Result<Option<(Protector, String)>, Error> {
+    let target = resolve_rip_relative(image, offset, 1, REL32_LENGTH)?;
+    let Some(rva) = target.checked_sub(image.image_base() as usize) else {
+        return Ok(None);
+    };
+    let section = image.sections().iter().find(|section| {
+        let size = section.virtual_size.max(section.size_of_raw_data) as usize;
+        rva >= section.virtual_address as usize && rva - (section.virtual_address as usize) < size
+    });
+    Ok(section.and_then(|section| {
+        let name = String::from_utf8_lossy(&section.name).trim_end_matches('\0').to_string();
+        match (Protector::from_section_name(&name), expected) {
+            (Some(protector), None) => Some((protector, name)),
+            (Some(protector), Some(expected)) if protector == expected => Some((protector, name)),
+            (_, Some(expected)) if pe_utils::entropy(image.section_data(section)) >= HIGH_ENTROPY => Some((expected, name)),
+            _ => None,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_CODE;
+    use pe_utils::PeBuilder;
+
+    use super::*;
+
+    /// `.text` at 0x1000 followed by `extra` sections from 0x2000 on
+    fn build_image(code: Vec<u8>, entry_point: u32, extra: &[(&str, Vec<u8>)]) -> PeImage {
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", code, IMAGE_SCN_CNT_CODE).unwrap();
+        for (name, data) in extra {
+            builder.add_section(name, data.clone(), IMAGE_SCN_CNT_CODE).unwrap();
+        }
+        builder.set_entry_point(entry_point).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    fn random_bytes(length: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        (0..length).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn matches_stubs_at_the_entry_point_only() {
+        // push imm32; call 0x2000 at 0x1010
+        let mut code = vec![0xCC; 0x100];
+        code[0x10..0x1A].copy_from_slice(&[0x68, 0x78, 0x56, 0x34, 0x12, 0xE8, 0xE6, 0x0F, 0x00, 0x00]);
+        let vmp = [(".vmp0", vec![0xC3; 0x10])];
+
+        let detections = find_protector_stubs(&build_image(code.clone(), 0x1010, &vmp)).unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].protector, Protector::VMProtect);
+        assert_eq!(detections[0].evidence, "VMProtect virtualized entry into .vmp0");
+        assert!(find_protector_stubs(&build_image(code, 0x1000, &vmp)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn requires_a_second_signal_for_push_call_stubs() {
+        let mut code = vec![0xCC; 0x100];
+        code[0..10].copy_from_slice(&[0x68, 0x78, 0x56, 0x34, 0x12, 0xE8, 0xF6, 0x0F, 0x00, 0x00]);
+
+        // Plain code calling plain code is just a function taking an argument
+        assert!(find_protector_stubs(&build_image(code.clone(), 0x1000, &[(".text2", vec![0xC3; 0x10])])).unwrap().is_empty());
+        // Calling into a renamed section full of packed data is not
+        let detections = find_protector_stubs(&build_image(code, 0x1000, &[(".text2", random_bytes(0x1000))])).unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].protector, Protector::VMProtect);
+    }
+
+    #[test]
+    fn follows_entry_point_jumps_into_protector_sections() {
+        // jmp .arch from the entry point, jmp +0 elsewhere
+        let mut code = vec![0xE9, 0xFB, 0x0F, 0x00, 0x00];
+        code.extend([0xE9, 0x00, 0x00, 0x00, 0x00]);
+        let arch = [(".arch", vec![0xC3; 0x10])];
+
+        let detections = find_protector_stubs(&build_image(code.clone(), 0x1000, &arch)).unwrap();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].protector, Protector::Denuvo);
+        assert_eq!(detections[0].evidence, "entry point jumps into .arch");
+        assert!(find_protector_stubs(&build_image(code, 0x1005, &arch)).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use log::{debug, warn};
+use pe_utils::PeImage;
+
+use crate::engine::{select_profile, EngineVersion, OffsetProfile};
+use crate::{Error, OffsetLocator};
+
+/// The globals an SDK dumper starts from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnrealGlobal {
+    /// `FNamePool NamePoolData` since 4.23, the `TNameEntryArray*` before
+    GNames,
+    /// `FUObjectArray GUObjectArray`
+    GObjects,
+    /// `UWorld* GWorld`
+    GWorld,
+}
+
+impl UnrealGlobal {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnrealGlobal::GNames => "GNames",
+            UnrealGlobal::GObjects => "GObjects",
+            UnrealGlobal::GWorld => "GWorld",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [UnrealGlobal::GNames, UnrealGlobal::GObjects, UnrealGlobal::GWorld].into_iter()
+            .find(|global| global.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalLocation {
+    pub global: UnrealGlobal,
+    pub rva: u32,
+    pub address: usize,
+    /// Matched instructions pointing at this address
+    pub references: usize,
+}
+
+/// A code shape referencing a global through a RIP-relative operand
+struct Shape {
+    pattern: &'static str,
+    displacement: usize,
+    length: usize,
+    /// Offset of the referenced field inside the global
+    field: usize,
+}
+
+const NAME_POOL: [Shape; 2] = [
+    // lea rcx, NamePoolData; call FNamePool::FNamePool; mov byte ptr [bNamePoolInitialized], 1
+    Shape { pattern: "48 8d 0d ?? ?? ?? ?? e8 ?? ?? ?? ?? c6 05 ?? ?? ?? ?? 01", displacement: 3, length: 7, field: 0 },
+    // lea r8, NamePoolData; jmp; lea rcx, NamePoolData; call FNamePool::FNamePool
+    Shape { pattern: "4c 8d 05 ?? ?? ?? ?? eb ?? 48 8d 0d ?? ?? ?? ?? e8", displacement: 3, length: 7, field: 0 },
+];
+
+const NAME_ENTRY_ARRAY: [Shape; 1] = [
+    // mov rax, [GNames]; test rax, rax; jnz; mov ecx, sizeof(TNameEntryArray); call FMemory::Malloc
+    Shape { pattern: "48 8b 05 ?? ?? ?? ?? 48 85 c0 75 ?? b9 08 04 00 00", displacement: 3, length: 7, field: 0 },
+];
+
+const OBJECT_ARRAY: [Shape; 1] = [
+    // mov rax, [GUObjectArray.ObjObjects.Objects]; mov rcx, [rax + rcx * 8]; lea reg, [rcx + rdx * 8]
+    Shape { pattern: "48 8b 05 ?? ?? ?? ?? 48 8b 0c c8 ?? 8d 04 d1", displacement: 3, length: 7, field: 0x10 },
+];
+
+const WORLD: [Shape; 2] = [
+    // mov rbx, [GWorld]; test rbx, rbx; jz; mov r8b, 1
+    Shape { pattern: "48 8b 1d ?? ?? ?? ?? 48 85 db 74 ?? 41 b0 01", displacement: 3, length: 7, field: 0 },
+    // mov rax, [GWorld]; cmp rax, reg; cmove rax, reg; mov [GWorld], rax
+    Shape { pattern: "48 8b 05 ?? ?? ?? ?? 48 3b ?? 48 0f 44 ?? 48 89 05", displacement: 3, length: 7, field: 0 },
+];
+
+const SHAPES: [&[Shape]; 4] = [&NAME_POOL, &NAME_ENTRY_ARRAY, &OBJECT_ARRAY, &WORLD];
+
+fn locator(global: UnrealGlobal, shapes: &[&'static [Shape]]) -> OffsetLocator<'static> {
+    OffsetLocator {
+        name: global.name(),
+        partial_match: shapes.iter().flat_map(|shapes| shapes.iter().map(|shape| shape.pattern)).collect(),
+        full_match: "",
+        skip_offset_print: true,
+        allow_multiple_matches: true,
+    }
+}
+
+fn get_profiles() -> &'static [OffsetProfile<'static>] {
+    static PROFILES: OnceLock<Vec<OffsetProfile<'static>>> = OnceLock::new();
+    PROFILES.get_or_init(|| vec![
+        OffsetProfile {
+            name: "TNameEntryArray",
+            min_engine_version: None,
+            max_engine_version: Some((4, 22)),
+            locators: vec![
+                locator(UnrealGlobal::GNames, &[&NAME_ENTRY_ARRAY]),
+                locator(UnrealGlobal::GObjects, &[&OBJECT_ARRAY]),
+                locator(UnrealGlobal::GWorld, &[&WORLD]),
+            ],
+        },
+        OffsetProfile {
+            name: "FNamePool",
+            min_engine_version: Some((4, 23)),
+            max_engine_version: None,
+            locators: vec![
+                locator(UnrealGlobal::GNames, &[&NAME_POOL]),
+                locator(UnrealGlobal::GObjects, &[&OBJECT_ARRAY]),
+                locator(UnrealGlobal::GWorld, &[&WORLD]),
+            ],
+        },
+        // Unknown engine version, both name table shapes
+        OffsetProfile {
+            name: "Any",
+            min_engine_version: None,
+            max_engine_version: None,
+            locators: vec![
+                locator(UnrealGlobal::GNames, &[&NAME_POOL, &NAME_ENTRY_ARRAY]),
+                locator(UnrealGlobal::GObjects, &[&OBJECT_ARRAY]),
+                locator(UnrealGlobal::GWorld, &[&WORLD]),
+            ],
+        },
+    ])
+}
+
+fn find_shape(pattern: &str) -> Option<&'static Shape> {
+    SHAPES.iter().flat_map(|shapes| shapes.iter()).find(|shape| shape.pattern == pattern)
+}
+
+/// Locates `GNames`, `GObjects` and `GWorld` with the code shapes of the profile matching
+/// `engine`. Every match is resolved and the address referenced most often wins, globals that
+/// were not found or whose locator failed are left out
+pub fn find_unreal_globals(image: &PeImage, engine: Option<&EngineVersion>) -> Vec<GlobalLocation> {
+    let Some(profile) = select_profile(get_profiles(), engine) else { return vec![] };
+    debug!("Unreal globals profile: {}", profile.name);
+
+    let image_base = image.image_base() as usize;
+    let mut output = vec![];
+    for locator in &profile.locators {
+        let Some(global) = UnrealGlobal::from_name(locator.name) else { continue };
+        // A failing locator only leaves its own global not found
+        let matches = match locator.find_all_partial_only(image) {
+            Ok(matches) => matches,
+            Err(Error::NotFound(_)) => continue,
+            Err(err) => {
+                warn!("Skipping {}: {}", global.name(), err);
+                continue;
+            }
+        };
+
+        let mut references: HashMap<usize, usize> = HashMap::new();
+        for (pattern, items) in matches {
+            let Some(shape) = find_shape(locator.partial_match[pattern]) else { continue };
+            for (offset, _) in items {
+                match resolve_global(image, offset, shape, image_base) {
+                    Ok(Some(address)) => *references.entry(address).or_default() += 1,
+                    Ok(None) => {}
+                    Err(err) => debug!("Skipping {} match at 0x{:X}: {}", global.name(), offset, err),
+                }
+            }
+        }
+        // Ties go to the lowest address so the result does not depend on the map order
+        let best = references.into_iter()
+            .max_by(|(left, left_count), (right, right_count)| left_count.cmp(right_count).then(right.cmp(left)));
+        if let Some((address, references)) = best {
+            output.push(GlobalLocation { global, rva: (address - image_base) as u32, address, references });
+        }
+    }
+    output
+}
+
+/// Address of the global itself, `None` when the operand points outside the image
+fn resolve_global(image: &PeImage, offset: usize, shape: &Shape, image_base: usize) -> Result<Option<usize>, Error> {
+    let field = crate::resolve_rip_relative(image, offset, shape.displacement, shape.length)?;
+    let Some(address) = field.checked_sub(shape.field) else { return Ok(None) };
+    let Some(rva) = address.checked_sub(image_base) else { return Ok(None) };
+    let in_image = image.sections().iter().any(|section| {
+        let start = section.virtual_address as usize;
+        rva >= start && rva < start + section.virtual_size.max(section.size_of_raw_data) as usize
+    });
+    Ok(in_image.then_some(address))
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA};
+    use pe_utils::PeBuilder;
+
+    use super::*;
+
+    const IMAGE_BASE: u64 = 0x1_4000_0000;
+    // mov rax, [rip+disp]; test rax, rax; jnz; mov ecx, sizeof(TNameEntryArray)
+    const NAMES: [u8; 17] = [0x48, 0x8B, 0x05, 0, 0, 0, 0, 0x48, 0x85, 0xC0, 0x75, 0x10, 0xB9, 0x08, 0x04, 0x00, 0x00];
+    // mov rax, [rip+disp]; mov rcx, [rax + rcx * 8]; lea rax, [rcx + rdx * 8]
+    const OBJECTS: [u8; 15] = [0x48, 0x8B, 0x05, 0, 0, 0, 0, 0x48, 0x8B, 0x0C, 0xC8, 0x48, 0x8D, 0x04, 0xD1];
+
+    /// Copies `code` to `rva` in `text` (loaded at 0x1000) with its rel32 pointing at `target`
+    fn place(text: &mut [u8], rva: u32, code: &[u8], target: u32) {
+        let offset = rva as usize - 0x1000;
+        text[offset..offset + code.len()].copy_from_slice(code);
+        let displacement = target as i32 - (rva as i32 + 7);
+        text[offset + 3..offset + 7].copy_from_slice(&displacement.to_le_bytes());
+    }
+
+    #[test]
+    fn resolves_rip_relative_references() {
+        let mut text = vec![0xCC; 0x200];
+        place(&mut text, 0x1010, &NAMES, 0x2040);
+        place(&mut text, 0x1080, &NAMES, 0x2040);
+        // A third reference pointing past the image is dropped
+        place(&mut text, 0x1100, &NAMES, 0x9000);
+        // GObjects is found through ObjObjects.Objects, 0x10 into FUObjectArray
+        place(&mut text, 0x1180, &OBJECTS, 0x2090);
+
+        let mut builder = PeBuilder::new(true, IMAGE_BASE).unwrap();
+        builder.add_section(".text", text, IMAGE_SCN_CNT_CODE).unwrap();
+        builder.add_section(".data", vec![0; 0x100], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        let globals = find_unreal_globals(&image, None);
+        assert_eq!(globals.len(), 2);
+        assert_eq!(globals[0].global, UnrealGlobal::GNames);
+        assert_eq!((globals[0].rva, globals[0].address), (0x2040, IMAGE_BASE as usize + 0x2040));
+        assert_eq!(globals[0].references, 2);
+        assert_eq!(globals[1].global, UnrealGlobal::GObjects);
+        assert_eq!((globals[1].rva, globals[1].references), (0x2080, 1));
+    }
+}
@@ -0,0 +1,252 @@
+use scroll::{Pread, LE};
+
+use crate::index::{compression_name, PakEntry};
+use crate::info::PakInfo;
+use crate::Error;
+
+// `TMemoryImagePtr`: bit 0 marks a frozen pointer, the signed offset from the pointer itself is
+// stored above the type index
+const FROZEN_POINTER_FLAG: u64 = 1;
+const FROZEN_OFFSET_SHIFT: u32 = 24;
+// `TMemoryImageArray` and `FMemoryImageString`: pointer, item count and capacity
+const SIZEOF_ARRAY: usize = 16;
+const SIZEOF_TCHAR: usize = 2;
+// `FPakFileData`: mount point, files and the directory index, a `TMap` of directory name to
+// `FPakDirectory`, itself a `TMap` of file name to file index
+const DATA_MOUNT_POINT: usize = 0;
+const DATA_FILES: usize = SIZEOF_ARRAY;
+const DATA_INDEX: usize = SIZEOF_ARRAY * 2;
+// A frozen `TMap` starts with the element array of its `TSparseArray`, the allocation flags follow
+const SPARSE_ARRAY_ALLOCATION_FLAGS: usize = SIZEOF_ARRAY;
+// `TSetElement` holding a directory name and its `FPakDirectory`, and a file name and its index
+const SIZEOF_DIRECTORY_ELEMENT: usize = 80;
+const SIZEOF_FILE_ELEMENT: usize = 32;
+// Frozen `FPakEntry`: offset, size, uncompressed size, hash, compression blocks, block size,
+// compression method index and flags
+const SIZEOF_FROZEN_ENTRY: usize = 80;
+const ENTRY_COMPRESSION_BLOCKS: usize = 48;
+const ENTRY_COMPRESSION_BLOCK_SIZE: usize = 64;
+const ENTRY_COMPRESSION_METHOD: usize = 68;
+const ENTRY_FLAGS: usize = 72;
+
+/// Reads the frozen index of version 9, a memory image of `FPakFileData` where every pointer is
+/// an offset from itself. Returns the mount point and the entries
+pub fn read_frozen_index(data: &[u8], info: &PakInfo, key: Option<&[u8; 32]>) -> Result<(String, Vec<PakEntry>), Error> {
+    let index = crate::read_block(data, info.index_offset, info.index_size, &info.index_hash, info.encrypted_index, key)?;
+    let mount_point = read_string(&index, DATA_MOUNT_POINT)?;
+
+    let (files_start, file_count) = read_array(&index, DATA_FILES, SIZEOF_FROZEN_ENTRY)?;
+    let files = (0..file_count)
+        .map(|file| read_entry(&index, files_start + file * SIZEOF_FROZEN_ENTRY, info))
+        .collect::<Result<Vec<PakEntry>, Error>>()?;
+
+    let mut entries = Vec::with_capacity(file_count);
+    for directory in map_elements(&index, DATA_INDEX, SIZEOF_DIRECTORY_ELEMENT)? {
+        let directory_name = read_string(&index, directory)?;
+        let directory_name = directory_name.trim_start_matches('/');
+        for file in map_elements(&index, directory + SIZEOF_ARRAY, SIZEOF_FILE_ELEMENT)? {
+            // Every file is listed once, more names than files means the maps overlap
+            if entries.len() == files.len() {
+                return Err(Error::InvalidIndex("more directory entries than files".to_string()));
+            }
+            let name = read_string(&index, file)?;
+            let file_index: i32 = index.pread_with(file + SIZEOF_ARRAY, LE)?;
+            let entry = usize::try_from(file_index).ok().and_then(|file_index| files.get(file_index))
+                .ok_or_else(|| Error::InvalidIndex(format!("file {} out of range", file_index)))?;
+            entries.push(PakEntry { path: format!("{}{}", directory_name, name), ..entry.clone() });
+        }
+    }
+    Ok((mount_point, entries))
+}
+
+/// Where the `size` bytes a frozen pointer at `offset` points to start
+fn read_pointer(index: &[u8], offset: usize, size: usize) -> Result<usize, Error> {
+    let packed: u64 = index.pread_with(offset, LE)?;
+    if packed & FROZEN_POINTER_FLAG == 0 {
+        return Err(Error::InvalidIndex(format!("pointer at 0x{:X} is not frozen", offset)));
+    }
+    let target = (offset as i64).checked_add(packed as i64 >> FROZEN_OFFSET_SHIFT)
+        .and_then(|target| usize::try_from(target).ok())
+        .filter(|target| target.checked_add(size).is_some_and(|end| end <= index.len()))
+        .ok_or_else(|| Error::InvalidIndex(format!("pointer at 0x{:X} past the end of the index", offset)))?;
+    Ok(target)
+}
+
+/// Start and item count of a `TMemoryImageArray` at `offset`
+fn read_array(index: &[u8], offset: usize, element_size: usize) -> Result<(usize, usize), Error> {
+    let count: i32 = index.pread_with(offset + 8, LE)?;
+    if count < 0 {
+        return Err(Error::InvalidIndex(format!("{} items in the array at 0x{:X}", count, offset)));
+    }
+    if count == 0 {
+        return Ok((0, 0));
+    }
+    let size = (count as usize).checked_mul(element_size)
+        .ok_or_else(|| Error::InvalidIndex(format!("{} items in the array at 0x{:X}", count, offset)))?;
+    Ok((read_pointer(index, offset, size)?, count as usize))
+}
+
+/// Offsets of the elements of a frozen `TMap` at `offset`, skipping the free slots of its sparse
+/// array
+fn map_elements(index: &[u8], offset: usize, element_size: usize) -> Result<Vec<usize>, Error> {
+    let (elements, count) = read_array(index, offset, element_size)?;
+    let flags_offset = offset + SPARSE_ARRAY_ALLOCATION_FLAGS;
+    let bits: i32 = index.pread_with(flags_offset + 8, LE)?;
+    let bits = (bits.max(0) as usize).min(count);
+    if bits == 0 {
+        return Ok(vec![]);
+    }
+    let flags = read_pointer(index, flags_offset, bits.div_ceil(32) * 4)?;
+    let allocated = |element: usize| -> Result<bool, Error> {
+        let word: u32 = index.pread_with(flags + element / 32 * 4, LE)?;
+        Ok(word & (1 << (element % 32)) != 0)
+    };
+    let mut output = vec![];
+    for element in 0..bits {
+        if allocated(element)? {
+            output.push(elements + element * element_size);
+        }
+    }
+    Ok(output)
+}
+
+/// `FMemoryImageString`, `TCHAR` is UTF-16 and the count includes the terminator
+fn read_string(index: &[u8], offset: usize) -> Result<String, Error> {
+    let (start, count) = read_array(index, offset, SIZEOF_TCHAR)?;
+    let units = (0..count)
+        .map(|unit| index.pread_with::<u16>(start + unit * SIZEOF_TCHAR, LE))
+        .collect::<Result<Vec<u16>, scroll::Error>>()?;
+    let end = units.iter().position(|unit| *unit == 0).unwrap_or(units.len());
+    Ok(String::from_utf16_lossy(&units[0..end]))
+}
+
+/// A frozen `FPakEntry`, the path comes from the directory index
+fn read_entry(index: &[u8], offset: usize, info: &PakInfo) -> Result<PakEntry, Error> {
+    let compression_blocks: i32 = index.pread_with(offset + ENTRY_COMPRESSION_BLOCKS + 8, LE)?;
+    let flags: u8 = index.pread_with(offset + ENTRY_FLAGS, LE)?;
+    Ok(PakEntry {
+        path: String::new(),
+        offset: index.pread_with(offset, LE)?,
+        size: index.pread_with(offset + 8, LE)?,
+        uncompressed_size: index.pread_with(offset + 16, LE)?,
+        compression: compression_name(info, index.pread_with(offset + ENTRY_COMPRESSION_METHOD, LE)?),
+        encrypted: flags & 1 != 0,
+        compression_blocks: compression_blocks.max(0) as u32,
+        compression_block_size: index.pread_with(offset + ENTRY_COMPRESSION_BLOCK_SIZE, LE)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use scroll::Pwrite;
+
+    use super::*;
+    use crate::tests::{write_pak, Directory, DATA, KEY};
+    use crate::PakFile;
+
+    /// A memory image being laid out, allocations are 8 byte aligned and appended at the end
+    struct Image(Vec<u8>);
+
+    impl Image {
+        fn alloc(&mut self, size: usize) -> usize {
+            let offset = self.0.len().next_multiple_of(8);
+            self.0.resize(offset + size, 0);
+            offset
+        }
+
+        fn pointer(&mut self, field: usize, target: usize) {
+            let packed = (target as i64 - field as i64) << FROZEN_OFFSET_SHIFT | FROZEN_POINTER_FLAG as i64;
+            self.0.pwrite_with(packed, field, LE).unwrap();
+        }
+
+        /// Array of `count` items of `size` bytes, returns where the items start
+        fn array(&mut self, field: usize, count: usize, size: usize) -> usize {
+            self.0.pwrite_with(count as i32, field + 8, LE).unwrap();
+            self.0.pwrite_with(count as i32, field + 12, LE).unwrap();
+            let items = self.alloc(count * size);
+            self.pointer(field, items);
+            items
+        }
+
+        fn string(&mut self, field: usize, text: &str) {
+            let units = text.encode_utf16().chain([0]).collect::<Vec<u16>>();
+            let start = self.array(field, units.len(), SIZEOF_TCHAR);
+            for (index, unit) in units.iter().enumerate() {
+                self.0.pwrite_with(*unit, start + index * SIZEOF_TCHAR, LE).unwrap();
+            }
+        }
+
+        /// `TMap` whose sparse array has a slot for every item of `allocated`
+        fn map(&mut self, field: usize, allocated: &[bool], size: usize) -> usize {
+            let elements = self.array(field, allocated.len(), size);
+            let flags = self.array(field + SPARSE_ARRAY_ALLOCATION_FLAGS, 1, 4);
+            let bits = allocated.iter().enumerate().fold(0u32, |bits, (index, set)| bits | (*set as u32) << index);
+            self.0.pwrite_with(allocated.len() as i32, field + SPARSE_ARRAY_ALLOCATION_FLAGS + 8, LE).unwrap();
+            self.0.pwrite_with(bits, flags, LE).unwrap();
+            elements
+        }
+    }
+
+    /// `FPakFileData` with three files, a `None` directory leaves a free slot in the directory map
+    fn build_index(directories: &[Option<Directory>]) -> Vec<u8> {
+        let mut image = Image(vec![0; DATA_INDEX + SIZEOF_ARRAY * 2]);
+        image.string(DATA_MOUNT_POINT, "../../../");
+        let files = image.array(DATA_FILES, 3, SIZEOF_FROZEN_ENTRY);
+        for (index, (offset, size, method, flags)) in [(0i64, 10i64, 0u32, 0u8), (10, 5, 1, 1), (15, 3, 0, 0)].into_iter().enumerate() {
+            let entry = files + index * SIZEOF_FROZEN_ENTRY;
+            image.0.pwrite_with(offset, entry, LE).unwrap();
+            image.0.pwrite_with(size, entry + 8, LE).unwrap();
+            image.0.pwrite_with(size * 8, entry + 16, LE).unwrap();
+            image.0.pwrite_with(method, entry + ENTRY_COMPRESSION_METHOD, LE).unwrap();
+            image.0.pwrite_with(flags, entry + ENTRY_FLAGS, LE).unwrap();
+            image.0.pwrite_with(0x10000u32, entry + ENTRY_COMPRESSION_BLOCK_SIZE, LE).unwrap();
+        }
+
+        let allocated = directories.iter().map(Option::is_some).collect::<Vec<bool>>();
+        let elements = image.map(DATA_INDEX, &allocated, SIZEOF_DIRECTORY_ELEMENT);
+        for (index, (name, files)) in directories.iter().enumerate().filter_map(|(index, directory)| Some((index, (*directory)?))) {
+            let element = elements + index * SIZEOF_DIRECTORY_ELEMENT;
+            image.string(element, name);
+            let file_elements = image.map(element + SIZEOF_ARRAY, &vec![true; files.len()], SIZEOF_FILE_ELEMENT);
+            for (file, (name, file_index)) in files.iter().enumerate() {
+                image.string(file_elements + file * SIZEOF_FILE_ELEMENT, name);
+                image.0.pwrite_with(*file_index, file_elements + file * SIZEOF_FILE_ELEMENT + SIZEOF_ARRAY, LE).unwrap();
+            }
+        }
+        image.0
+    }
+
+    #[test]
+    fn reads_the_frozen_index() {
+        let index = build_index(&[Some(("/Game/", &[("A.uasset", 0), ("Ä.bin", 1)])), None, Some(("/Game/Maps/", &[("M.umap", 2)]))]);
+        let data = write_pak(9, &DATA, &index, Some(&KEY), &["Zlib"], 5, Some(true));
+        let pak = PakFile::parse(&data, Some(&KEY)).unwrap();
+
+        assert!(pak.info().index_is_frozen);
+        assert_eq!(pak.mount_point(), "../../../");
+        let entries = pak.entries();
+        let paths = entries.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["Game/A.uasset", "Game/Ä.bin", "Game/Maps/M.umap"]);
+        assert_eq!((entries[1].offset, entries[1].size, entries[1].uncompressed_size), (10, 5, 40));
+        assert_eq!((entries[1].compression.as_deref(), entries[1].encrypted), (Some("Zlib"), true));
+        assert_eq!(entries[1].compression_block_size, 0x10000);
+        assert_eq!((entries[2].offset, entries[2].compression.as_deref()), (15, None));
+    }
+
+    #[test]
+    fn rejects_broken_frozen_indices() {
+        let read = |index: &[u8]| PakFile::parse(&write_pak(9, &DATA, index, None, &[], 5, Some(true)), None);
+
+        let index = build_index(&[Some(("/", &[("A", 0), ("B", 1), ("C", 2), ("D", 0)]))]);
+        assert!(matches!(read(&index), Err(Error::InvalidIndex(_))));
+        let index = build_index(&[Some(("/", &[("A", 3)]))]);
+        assert!(matches!(read(&index), Err(Error::InvalidIndex(_))));
+
+        // Mount point pointing past the end, then not frozen at all
+        let mut index = build_index(&[]);
+        index.pwrite_with(0x7FFF_i64 << FROZEN_OFFSET_SHIFT | 1, DATA_MOUNT_POINT, LE).unwrap();
+        assert!(matches!(read(&index), Err(Error::InvalidIndex(_))));
+        index.pwrite_with(0x40_i64 << FROZEN_OFFSET_SHIFT, DATA_MOUNT_POINT, LE).unwrap();
+        assert!(matches!(read(&index), Err(Error::InvalidIndex(_))));
+    }
+}
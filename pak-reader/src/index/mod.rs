@@ -0,0 +1,238 @@
+use scroll::{Pread, LE};
+
+use crate::info::{
+    PakInfo, PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD, PAK_FILE_VERSION_PATH_HASH_INDEX,
+    PAK_FILE_VERSION_UTF8_PAK_DIRECTORY,
+};
+use crate::Error;
+
+const COMPRESS_NONE: u32 = 0;
+// Entry locations in the directory index, negative ones index the unencoded entries
+const LOCATION_INVALID: i32 = i32::MIN;
+const ENCODED_CUSTOM_BLOCK_SIZE: u32 = 0x3F;
+// Sanity limit on string lengths read from the index
+const MAX_STRING_LENGTH: i32 = 0x1_0000;
+// Empty path, offset, sizes, compression method and hash, the smallest entry of the legacy index.
+// Bounds the capacity reserved for the untrusted entry count
+const MIN_LEGACY_ENTRY_SIZE: usize = 4 + 3 * 8 + 4 + 20;
+
+/// A file in the pak
+#[derive(Debug, Clone)]
+pub struct PakEntry {
+    /// Relative to the mount point. Paks without a directory index only carry path hashes, their
+    /// entries are named after the hash
+    pub path: String,
+    pub offset: u64,
+    /// Size inside the pak, compressed
+    pub size: u64,
+    pub uncompressed_size: u64,
+    /// `None` for stored files
+    pub compression: Option<String>,
+    pub encrypted: bool,
+    pub compression_blocks: u32,
+    pub compression_block_size: u32,
+}
+
+/// Reads the primary index, and the full directory index for version 10 and up. Returns the mount
+/// point and the entries
+pub fn read_index(data: &[u8], info: &PakInfo, key: Option<&[u8; 32]>) -> Result<(String, Vec<PakEntry>), Error> {
+    let index = crate::read_block(data, info.index_offset, info.index_size, &info.index_hash, info.encrypted_index, key)?;
+    let mut offset = 0;
+    let mount_point = read_string(&index, &mut offset, false)?;
+    let count: i32 = index.gread_with(&mut offset, LE)?;
+    if count < 0 {
+        return Err(Error::InvalidIndex(format!("{} entries", count)));
+    }
+
+    if info.version < PAK_FILE_VERSION_PATH_HASH_INDEX {
+        let mut entries = Vec::with_capacity((count as usize).min(index.len() / MIN_LEGACY_ENTRY_SIZE));
+        for _ in 0..count {
+            let path = read_string(&index, &mut offset, false)?;
+            entries.push(read_entry(&index, &mut offset, info, path)?);
+        }
+        return Ok((mount_point, entries));
+    }
+
+    let _path_hash_seed: u64 = index.gread_with(&mut offset, LE)?;
+    let path_hash_index = read_secondary_index_info(&index, &mut offset)?;
+    let full_directory_index = read_secondary_index_info(&index, &mut offset)?;
+    let encoded_size: i32 = index.gread_with(&mut offset, LE)?;
+    let encoded = index.get(offset..offset + encoded_size.max(0) as usize)
+        .ok_or_else(|| Error::InvalidIndex(format!("{} bytes of encoded entries", encoded_size)))?;
+    offset += encoded.len();
+    let files: i32 = index.gread_with(&mut offset, LE)?;
+    let files = (0..files.max(0))
+        .map(|_| read_entry(&index, &mut offset, info, String::new()))
+        .collect::<Result<Vec<PakEntry>, Error>>()?;
+
+    let entry_at = |location: i32, path: String| -> Result<Option<PakEntry>, Error> {
+        match location {
+            LOCATION_INVALID => Ok(None),
+            location if location < 0 => {
+                let file = files.get((-(location as i64) - 1) as usize)
+                    .ok_or_else(|| Error::InvalidIndex(format!("file {} out of range", -(location as i64) - 1)))?;
+                Ok(Some(PakEntry { path, ..file.clone() }))
+            }
+            location => decode_entry(encoded, location as usize, info, path).map(Some),
+        }
+    };
+
+    // The entries come from the secondary indices, the count read above is not checked against them
+    let mut entries = Vec::new();
+    if let Some((directory_offset, directory_size, directory_hash)) = full_directory_index {
+        let directory_index = crate::read_block(data, directory_offset, directory_size, &directory_hash,
+                                                info.encrypted_index, key)?;
+        let mut offset = 0;
+        let utf8 = info.version >= PAK_FILE_VERSION_UTF8_PAK_DIRECTORY;
+        let directories: i32 = directory_index.gread_with(&mut offset, LE)?;
+        for _ in 0..directories {
+            let directory = read_string(&directory_index, &mut offset, utf8)?;
+            let directory = directory.trim_start_matches('/');
+            let directory_files: i32 = directory_index.gread_with(&mut offset, LE)?;
+            for _ in 0..directory_files {
+                let name = read_string(&directory_index, &mut offset, utf8)?;
+                let location: i32 = directory_index.gread_with(&mut offset, LE)?;
+                entries.extend(entry_at(location, format!("{}{}", directory, name))?);
+            }
+        }
+    } else if let Some((hash_offset, hash_size, hash)) = path_hash_index {
+        let path_hash_index = crate::read_block(data, hash_offset, hash_size, &hash, info.encrypted_index, key)?;
+        let mut offset = 0;
+        let hashes: i32 = path_hash_index.gread_with(&mut offset, LE)?;
+        for _ in 0..hashes {
+            let path_hash: u64 = path_hash_index.gread_with(&mut offset, LE)?;
+            let location: i32 = path_hash_index.gread_with(&mut offset, LE)?;
+            entries.extend(entry_at(location, format!("{:016X}", path_hash))?);
+        }
+    }
+    Ok((mount_point, entries))
+}
+
+/// Offset, size and hash of the path hash or directory index, when the pak has it
+fn read_secondary_index_info(index: &[u8], offset: &mut usize) -> Result<Option<(u64, u64, [u8; 20])>, Error> {
+    let present: u32 = index.gread_with(offset, LE)?;
+    if present == 0 {
+        return Ok(None);
+    }
+    let index_offset: u64 = index.gread_with(offset, LE)?;
+    let index_size: u64 = index.gread_with(offset, LE)?;
+    let hash = read_hash(index, offset)?;
+    Ok(Some((index_offset, index_size, hash)))
+}
+
+/// `FPakEntry` as serialized in the index
+fn read_entry(index: &[u8], offset: &mut usize, info: &PakInfo, path: String) -> Result<PakEntry, Error> {
+    let entry_offset: u64 = index.gread_with(offset, LE)?;
+    let size: u64 = index.gread_with(offset, LE)?;
+    let uncompressed_size: u64 = index.gread_with(offset, LE)?;
+    let compression = match info.version {
+        // 4.22 stored the method index in a byte
+        PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD if info.compression_method_slots == 4 => {
+            index.gread_with::<u8>(offset, LE)? as u32
+        }
+        _ => index.gread_with::<u32>(offset, LE)?,
+    };
+    let _hash = read_hash(index, offset)?;
+    let mut compression_blocks = 0;
+    if compression != COMPRESS_NONE {
+        compression_blocks = index.gread_with::<u32>(offset, LE)?;
+        *offset += compression_blocks as usize * 16;
+    }
+    let flags: u8 = index.gread_with(offset, LE)?;
+    let compression_block_size: u32 = index.gread_with(offset, LE)?;
+    Ok(PakEntry {
+        path,
+        offset: entry_offset,
+        size,
+        uncompressed_size,
+        compression: compression_name(info, compression),
+        encrypted: flags & 1 != 0,
+        compression_blocks,
+        compression_block_size,
+    })
+}
+
+/// The bit packed entries of version 10 and up (`FPakFile::DecodePakEntry`)
+fn decode_entry(encoded: &[u8], mut offset: usize, info: &PakInfo, path: String) -> Result<PakEntry, Error> {
+    let value: u32 = encoded.gread_with(&mut offset, LE)?;
+    let compression_block_size = match value & ENCODED_CUSTOM_BLOCK_SIZE {
+        ENCODED_CUSTOM_BLOCK_SIZE => encoded.gread_with::<u32>(&mut offset, LE)?,
+        size => size << 11,
+    };
+    let compression = (value >> 23) & 0x3F;
+    let mut read_size = |is_32_bit: bool| -> Result<u64, Error> {
+        Ok(match is_32_bit {
+            true => encoded.gread_with::<u32>(&mut offset, LE)? as u64,
+            false => encoded.gread_with::<u64>(&mut offset, LE)?,
+        })
+    };
+    let entry_offset = read_size(value & (1 << 31) != 0)?;
+    let uncompressed_size = read_size(value & (1 << 30) != 0)?;
+    let size = match compression {
+        COMPRESS_NONE => uncompressed_size,
+        _ => read_size(value & (1 << 29) != 0)?,
+    };
+    Ok(PakEntry {
+        path,
+        offset: entry_offset,
+        size,
+        uncompressed_size,
+        compression: compression_name(info, compression),
+        encrypted: value & (1 << 22) != 0,
+        compression_blocks: (value >> 6) & 0xFFFF,
+        compression_block_size,
+    })
+}
+
+pub(crate) fn compression_name(info: &PakInfo, compression: u32) -> Option<String> {
+    if compression == COMPRESS_NONE {
+        return None;
+    }
+    if info.version >= PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD {
+        return Some(info.compression_methods.get(compression as usize - 1).cloned()
+            .unwrap_or_else(|| format!("Unknown({})", compression)));
+    }
+    // ECompressionFlags before method names were stored in the footer
+    Some(match compression & 0x0F {
+        0x01 => "Zlib".to_string(),
+        0x02 => "Gzip".to_string(),
+        0x04 => "Custom".to_string(),
+        flags => format!("Unknown(0x{:X})", flags),
+    })
+}
+
+fn read_hash(data: &[u8], offset: &mut usize) -> Result<[u8; 20], Error> {
+    let hash = data.get(*offset..*offset + 20).ok_or(scroll::Error::TooBig { size: 20, len: data.len() - *offset })?;
+    *offset += 20;
+    Ok(hash.try_into().unwrap())
+}
+
+/// `FString`: a length including the terminator, negative for UTF-16. Positive lengths are ANSI,
+/// or UTF-8 with `utf8`, what the directory index holds from version 12
+fn read_string(data: &[u8], offset: &mut usize, utf8: bool) -> Result<String, Error> {
+    let length: i32 = data.gread_with(offset, LE)?;
+    if !(-MAX_STRING_LENGTH..=MAX_STRING_LENGTH).contains(&length) {
+        return Err(Error::InvalidIndex(format!("string of length {} at 0x{:X}", length, *offset - 4)));
+    }
+    let string = match length {
+        0 => String::new(),
+        length if length > 0 => {
+            let bytes = data.get(*offset..*offset + length as usize)
+                .ok_or_else(|| Error::InvalidIndex(format!("string past the end at 0x{:X}", *offset)))?;
+            *offset += bytes.len();
+            let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+            match utf8 {
+                true => String::from_utf8_lossy(&bytes[0..end]).into_owned(),
+                false => bytes[0..end].iter().map(|byte| *byte as char).collect(),
+            }
+        }
+        length => {
+            let units = (0..-length)
+                .map(|_| data.gread_with::<u16>(offset, LE))
+                .collect::<Result<Vec<u16>, scroll::Error>>()?;
+            let end = units.iter().position(|unit| *unit == 0).unwrap_or(units.len());
+            String::from_utf16_lossy(&units[0..end])
+        }
+    };
+    Ok(string)
+}
@@ -0,0 +1,130 @@
+use scroll::{Pread, LE};
+
+use crate::Error;
+
+pub const PAK_FILE_MAGIC: u32 = 0x5A6F_12E1;
+
+pub const PAK_FILE_VERSION_COMPRESSION_ENCRYPTION: u32 = 3;
+pub const PAK_FILE_VERSION_INDEX_ENCRYPTION: u32 = 4;
+pub const PAK_FILE_VERSION_ENCRYPTION_KEY_GUID: u32 = 7;
+pub const PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD: u32 = 8;
+pub const PAK_FILE_VERSION_FROZEN_INDEX: u32 = 9;
+pub const PAK_FILE_VERSION_PATH_HASH_INDEX: u32 = 10;
+pub const PAK_FILE_VERSION_FNV64_BUG_FIX: u32 = 11;
+pub const PAK_FILE_VERSION_UTF8_PAK_DIRECTORY: u32 = 12;
+
+const COMPRESSION_METHOD_NAME_LENGTH: usize = 32;
+// Encrypted index flag, magic, version, index offset, index size and index hash. Versions before 4
+// have no flag, the byte read in its place is the end of the index
+const SIZEOF_PAK_INFO_BASE: usize = 1 + 4 + 4 + 8 + 8 + 20;
+
+/// The footer at the end of every pak (`FPakInfo`)
+#[derive(Debug, Clone)]
+pub struct PakInfo {
+    pub version: u32,
+    pub index_offset: u64,
+    pub index_size: u64,
+    pub index_hash: [u8; 20],
+    pub encrypted_index: bool,
+    /// All zero for the default key
+    pub encryption_key_guid: [u8; 16],
+    pub index_is_frozen: bool,
+    /// Methods referenced by entry compression indices from version 8, index 1 is the first one
+    pub compression_methods: Vec<String>,
+    /// Compression method names the footer has room for, four in the version 8 written by 4.22
+    pub compression_method_slots: usize,
+}
+
+/// Where the footer fields are for one family of versions
+struct Layout {
+    guid: bool,
+    frozen_flag: bool,
+    compression_methods: usize,
+    versions: std::ops::RangeInclusive<u32>,
+}
+
+// Newest first, 4.22 wrote version 8 with room for four compression methods instead of five
+const LAYOUTS: [Layout; 6] = [
+    Layout { guid: true, frozen_flag: false, compression_methods: 5,
+             versions: PAK_FILE_VERSION_PATH_HASH_INDEX..=PAK_FILE_VERSION_UTF8_PAK_DIRECTORY },
+    Layout { guid: true, frozen_flag: true, compression_methods: 5,
+             versions: PAK_FILE_VERSION_FROZEN_INDEX..=PAK_FILE_VERSION_FROZEN_INDEX },
+    Layout { guid: true, frozen_flag: false, compression_methods: 5,
+             versions: PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD..=PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD },
+    Layout { guid: true, frozen_flag: false, compression_methods: 4,
+             versions: PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD..=PAK_FILE_VERSION_FNAME_BASED_COMPRESSION_METHOD },
+    Layout { guid: true, frozen_flag: false, compression_methods: 0,
+             versions: PAK_FILE_VERSION_ENCRYPTION_KEY_GUID..=PAK_FILE_VERSION_ENCRYPTION_KEY_GUID },
+    Layout { guid: false, frozen_flag: false, compression_methods: 0,
+             versions: 1..=PAK_FILE_VERSION_ENCRYPTION_KEY_GUID - 1 },
+];
+
+impl Layout {
+    fn size(&self) -> usize {
+        SIZEOF_PAK_INFO_BASE
+            + if self.guid { 16 } else { 0 }
+            + if self.frozen_flag { 1 } else { 0 }
+            + self.compression_methods * COMPRESSION_METHOD_NAME_LENGTH
+    }
+}
+
+/// Finds the footer by trying every known layout from the end of the file
+pub fn read_info(data: &[u8]) -> Result<PakInfo, Error> {
+    // The magic was found but with a version no layout knows, most likely a newer engine
+    let mut unknown_version = None;
+    for layout in &LAYOUTS {
+        let Some(start) = data.len().checked_sub(layout.size()) else { continue };
+        let mut offset = start;
+        let mut encryption_key_guid = [0; 16];
+        if layout.guid {
+            encryption_key_guid.copy_from_slice(&data[offset..offset + 16]);
+            offset += 16;
+        }
+        let encrypted_index = data[offset] != 0;
+        offset += 1;
+
+        let magic: u32 = data.gread_with(&mut offset, LE)?;
+        let version: u32 = data.gread_with(&mut offset, LE)?;
+        if magic != PAK_FILE_MAGIC {
+            continue;
+        }
+        if !layout.versions.contains(&version) {
+            unknown_version = unknown_version.or(Some(version));
+            continue;
+        }
+        let index_offset: u64 = data.gread_with(&mut offset, LE)?;
+        let index_size: u64 = data.gread_with(&mut offset, LE)?;
+        let mut index_hash = [0; 20];
+        index_hash.copy_from_slice(&data[offset..offset + 20]);
+        offset += 20;
+        let index_is_frozen = layout.frozen_flag && data[offset] != 0;
+        if layout.frozen_flag {
+            offset += 1;
+        }
+        let compression_methods = (0..layout.compression_methods)
+            .map(|index| {
+                let name = &data[offset + index * COMPRESSION_METHOD_NAME_LENGTH..][..COMPRESSION_METHOD_NAME_LENGTH];
+                let end = name.iter().position(|byte| *byte == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[0..end]).into_owned()
+            })
+            .take_while(|name| !name.is_empty())
+            .collect();
+
+        let encrypted_index = encrypted_index && version >= PAK_FILE_VERSION_INDEX_ENCRYPTION;
+        if version < PAK_FILE_VERSION_COMPRESSION_ENCRYPTION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        return Ok(PakInfo {
+            version,
+            index_offset,
+            index_size,
+            index_hash,
+            encrypted_index,
+            encryption_key_guid,
+            index_is_frozen,
+            compression_methods,
+            compression_method_slots: layout.compression_methods,
+        });
+    }
+    Err(unknown_version.map_or(Error::NotAPak, Error::UnsupportedVersion))
+}
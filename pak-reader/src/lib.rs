@@ -0,0 +1,359 @@
+use std::fs::File;
+use std::path::Path;
+
+use aes::cipher::{BlockDecrypt, KeyInit};
+use aes::Aes256;
+use log::info;
+use memmap2::Mmap;
+
+pub mod frozen;
+pub mod index;
+pub mod info;
+
+pub use index::PakEntry;
+pub use info::PakInfo;
+
+pub const AES_BLOCK_SIZE: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("IO Error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Scroll Error: {0}")]
+    Scroll(#[from] scroll::Error),
+    #[error("Not a pak file, no footer found")]
+    NotAPak,
+    #[error("Unsupported pak version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("The index is encrypted, a key is required")]
+    KeyRequired,
+    #[error("Wrong key, the decrypted index does not match its hash")]
+    InvalidKey,
+    #[error("Invalid key: {0}, expected 32 bytes in hex")]
+    InvalidKeyFormat(String),
+    #[error("Invalid index: {0}")]
+    InvalidIndex(String),
+}
+
+/// A pak file with its index read and decrypted
+pub struct PakFile {
+    info: PakInfo,
+    mount_point: String,
+    entries: Vec<PakEntry>,
+}
+
+impl PakFile {
+    /// Maps the pak at `path` and reads its index, `key` is only needed for encrypted indices
+    pub fn open<P: AsRef<Path>>(path: P, key: Option<&[u8; 32]>) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // Safety: the pak is only read while the map is alive and nothing else is expected to
+        // modify it
+        let map = unsafe { Mmap::map(&file)? };
+        Self::parse(&map, key)
+    }
+
+    pub fn parse(data: &[u8], key: Option<&[u8; 32]>) -> Result<Self, Error> {
+        let info = info::read_info(data)?;
+        info!("Pak version {}, index at 0x{:X} ({} bytes), encrypted: {}", info.version, info.index_offset,
+              info.index_size, info.encrypted_index);
+        let (mount_point, entries) = match info.index_is_frozen {
+            true => frozen::read_frozen_index(data, &info, key)?,
+            false => index::read_index(data, &info, key)?,
+        };
+        Ok(PakFile { info, mount_point, entries })
+    }
+
+    pub fn info(&self) -> &PakInfo {
+        &self.info
+    }
+
+    /// Prefix of every path, usually `../../../`
+    pub fn mount_point(&self) -> &str {
+        &self.mount_point
+    }
+
+    pub fn entries(&self) -> &[PakEntry] {
+        &self.entries
+    }
+}
+
+/// Parses a 256-bit AES key written in hex, with or without a `0x` prefix
+pub fn parse_key(key: &str) -> Result<[u8; 32], Error> {
+    let hex = key.trim();
+    let hex = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X")).unwrap_or(hex);
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(Error::InvalidKeyFormat(key.to_string()));
+    }
+    let mut output = [0; 32];
+    for (index, byte) in output.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16)
+            .map_err(|_| Error::InvalidKeyFormat(key.to_string()))?;
+    }
+    Ok(output)
+}
+
+/// Reads a block of the pak that may be encrypted and checks it against its SHA-1, a mismatch
+/// after decryption means the key is wrong
+pub(crate) fn read_block(data: &[u8], offset: u64, size: u64, hash: &[u8; 20], encrypted: bool, key: Option<&[u8; 32]>) -> Result<Vec<u8>, Error> {
+    let end = offset.checked_add(size).filter(|end| *end <= data.len() as u64)
+        .ok_or_else(|| Error::InvalidIndex(format!("0x{:X} bytes at 0x{:X} past the end of the file", size, offset)))?;
+    let mut block = data[offset as usize..end as usize].to_vec();
+    if encrypted {
+        let key = key.ok_or(Error::KeyRequired)?;
+        if !block.len().is_multiple_of(AES_BLOCK_SIZE) {
+            return Err(Error::InvalidIndex(format!("encrypted block of 0x{:X} bytes is not aligned", size)));
+        }
+        // Unreal encrypts with AES-256 in ECB mode
+        let cipher = Aes256::new(key.into());
+        for chunk in block.chunks_exact_mut(AES_BLOCK_SIZE) {
+            cipher.decrypt_block(chunk.into());
+        }
+    }
+    if sha1_smol::Sha1::from(&block).digest().bytes() != *hash {
+        return Err(match encrypted {
+            true => Error::InvalidKey,
+            false => Error::InvalidIndex("hash mismatch".to_string()),
+        });
+    }
+    Ok(block)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use aes::cipher::BlockEncrypt;
+
+    use super::*;
+    use crate::info::PAK_FILE_MAGIC;
+
+    pub(crate) const KEY: [u8; 32] = [0x42; 32];
+    // Fake file data before the indices
+    pub(crate) const DATA: [u8; 0x100] = [0xCC; 0x100];
+
+    /// A directory name and its files with their entry location (or file index when frozen)
+    pub(crate) type Directory<'a> = (&'a str, &'a [(&'a str, i32)]);
+
+    /// ANSI `FString`, or UTF-8 in the directory index of version 12
+    pub(crate) fn fstring(text: &str) -> Vec<u8> {
+        let mut bytes = ((text.len() + 1) as i32).to_le_bytes().to_vec();
+        bytes.extend(text.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    fn wide_fstring(text: &str) -> Vec<u8> {
+        let units = text.encode_utf16().chain([0]).collect::<Vec<u16>>();
+        let mut bytes = (-(units.len() as i32)).to_le_bytes().to_vec();
+        bytes.extend(units.iter().flat_map(|unit| unit.to_le_bytes()));
+        bytes
+    }
+
+    /// `FPakEntry`, `method_byte` for the 4.22 flavour of version 8
+    fn entry(offset: u64, size: u64, compression: u32, blocks: u32, flags: u8, method_byte: bool) -> Vec<u8> {
+        let mut bytes = [offset, size, size * 2].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>();
+        match method_byte {
+            true => bytes.push(compression as u8),
+            false => bytes.extend(compression.to_le_bytes()),
+        }
+        bytes.extend([0x11; 20]);
+        if compression != 0 {
+            bytes.extend(blocks.to_le_bytes());
+            bytes.extend(std::iter::repeat_n(0, blocks as usize * 16));
+        }
+        bytes.push(flags);
+        bytes.extend(0x10000u32.to_le_bytes());
+        bytes
+    }
+
+    /// A block as stored in the pak: padded and encrypted with `key` when given, and the hash of
+    /// its plain text
+    pub(crate) fn seal(data: &[u8], key: Option<&[u8; 32]>) -> (Vec<u8>, [u8; 20]) {
+        let mut data = data.to_vec();
+        let Some(key) = key else {
+            let hash = sha1_smol::Sha1::from(&data).digest().bytes();
+            return (data, hash);
+        };
+        data.resize(data.len().next_multiple_of(AES_BLOCK_SIZE), 0);
+        let hash = sha1_smol::Sha1::from(&data).digest().bytes();
+        let cipher = Aes256::new(key.into());
+        for chunk in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+            cipher.encrypt_block(chunk.into());
+        }
+        (data, hash)
+    }
+
+    /// `body`, the index and the footer of `version` with `slots` compression method names
+    pub(crate) fn write_pak(version: u32, body: &[u8], index: &[u8], key: Option<&[u8; 32]>,
+                            methods: &[&str], slots: usize, frozen: Option<bool>) -> Vec<u8> {
+        let (index, hash) = seal(index, key);
+        let mut pak = body.to_vec();
+        pak.extend(&index);
+        if version >= 7 {
+            pak.extend([0xAB; 16]);
+        }
+        if version >= 4 {
+            pak.push(key.is_some() as u8);
+        }
+        pak.extend(PAK_FILE_MAGIC.to_le_bytes());
+        pak.extend(version.to_le_bytes());
+        pak.extend((body.len() as u64).to_le_bytes());
+        pak.extend((index.len() as u64).to_le_bytes());
+        pak.extend(hash);
+        if let Some(frozen) = frozen {
+            pak.push(frozen as u8);
+        }
+        for slot in 0..slots {
+            let mut name = methods.get(slot).map(|name| name.as_bytes().to_vec()).unwrap_or_default();
+            name.resize(32, 0);
+            pak.extend(name);
+        }
+        pak
+    }
+
+    /// Version 10+: the directory and path hash indices after `DATA`, then the primary index with
+    /// two encoded entries and one unencoded file. Directory locations of 1 point at the second
+    /// encoded entry
+    fn write_path_hash_pak(version: u32, directories: &[Directory], key: Option<&[u8; 32]>) -> Vec<u8> {
+        // Offset and sizes in 32 bits, stored, default block size
+        let mut encoded = ((1u32 << 31) | (1 << 30)).to_le_bytes().to_vec();
+        encoded.extend([0u32, 100].iter().flat_map(|value| value.to_le_bytes()));
+        let second = encoded.len() as i32;
+        // Method 1 in two blocks of 0x20 << 11 bytes, encrypted, followed by the block sizes
+        encoded.extend(((1u32 << 31) | (1 << 30) | (1 << 29) | (1 << 23) | (1 << 22) | (2 << 6) | 0x20).to_le_bytes());
+        encoded.extend([100u32, 500, 80, 40, 40].iter().flat_map(|value| value.to_le_bytes()));
+
+        let mut directory_index = (directories.len() as i32).to_le_bytes().to_vec();
+        for (directory, files) in directories {
+            directory_index.extend(fstring(directory));
+            directory_index.extend((files.len() as i32).to_le_bytes());
+            for (name, location) in *files {
+                directory_index.extend(fstring(name));
+                directory_index.extend(if *location == 1 { second } else { *location }.to_le_bytes());
+            }
+        }
+        let mut path_hash_index = 2i32.to_le_bytes().to_vec();
+        path_hash_index.extend(0x1122_3344_5566_7788u64.to_le_bytes());
+        path_hash_index.extend(0i32.to_le_bytes());
+        path_hash_index.extend(0xAABBu64.to_le_bytes());
+        path_hash_index.extend((-1i32).to_le_bytes());
+
+        let mut body = DATA.to_vec();
+        let mut primary = fstring("../../../");
+        primary.extend(3i32.to_le_bytes());
+        primary.extend(0u64.to_le_bytes());
+        // Path hash index first, then the directory index when there is one
+        for secondary in [Some(path_hash_index), (!directories.is_empty()).then_some(directory_index)] {
+            primary.extend((secondary.is_some() as u32).to_le_bytes());
+            let Some(secondary) = secondary else { continue };
+            let (secondary, hash) = seal(&secondary, key);
+            primary.extend((body.len() as u64).to_le_bytes());
+            primary.extend((secondary.len() as u64).to_le_bytes());
+            primary.extend(hash);
+            body.extend(secondary);
+        }
+        primary.extend((encoded.len() as i32).to_le_bytes());
+        primary.extend(encoded);
+        primary.extend(1i32.to_le_bytes());
+        primary.extend(entry(0x200, 3, 0, 0, 0, false));
+        write_pak(version, &body, &primary, key, &["Oodle"], 5, None)
+    }
+
+    #[test]
+    fn reads_version_3_index() {
+        let mut index = fstring("../../../");
+        index.extend(2i32.to_le_bytes());
+        index.extend(fstring("Game/A.uasset"));
+        index.extend(entry(0, 10, 0, 0, 0, false));
+        index.extend(fstring("Game/B.bin"));
+        index.extend(entry(10, 5, 1, 2, 0, false));
+        let pak = PakFile::parse(&write_pak(3, &DATA, &index, None, &[], 0, None), None).unwrap();
+
+        assert_eq!(pak.info().version, 3);
+        assert!(!pak.info().encrypted_index);
+        assert_eq!(pak.mount_point(), "../../../");
+        let entries = pak.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].path.as_str(), entries[0].offset, entries[0].size), ("Game/A.uasset", 0, 10));
+        assert_eq!(entries[0].compression, None);
+        assert_eq!((entries[1].path.as_str(), entries[1].uncompressed_size), ("Game/B.bin", 10));
+        assert_eq!((entries[1].compression.as_deref(), entries[1].compression_blocks), (Some("Zlib"), 2));
+    }
+
+    #[test]
+    fn decrypts_the_index_and_rejects_a_wrong_key() {
+        let mut index = fstring("../../../");
+        index.extend(1i32.to_le_bytes());
+        index.extend(wide_fstring("Game/Ü.uasset"));
+        index.extend(entry(0, 7, 1, 1, 1, true));
+        let data = write_pak(8, &DATA, &index, Some(&KEY), &["Oodle"], 4, None);
+
+        let pak = PakFile::parse(&data, Some(&KEY)).unwrap();
+        assert!(pak.info().encrypted_index);
+        assert_eq!(pak.info().compression_method_slots, 4);
+        assert_eq!(pak.info().compression_methods, ["Oodle"]);
+        let entry = &pak.entries()[0];
+        assert_eq!(entry.path, "Game/Ü.uasset");
+        assert_eq!((entry.compression.as_deref(), entry.encrypted, entry.compression_blocks), (Some("Oodle"), true, 1));
+
+        assert!(matches!(PakFile::parse(&data, None), Err(Error::KeyRequired)));
+        assert!(matches!(PakFile::parse(&data, Some(&[0x24; 32])), Err(Error::InvalidKey)));
+    }
+
+    #[test]
+    fn reads_the_directory_index() {
+        let directories: [Directory; 2] = [
+            ("/", &[("Root.txt", 0)]),
+            ("/Game/Content/", &[("Big.uasset", 1), ("Loose.bin", -1), ("Deleted.bin", i32::MIN)]),
+        ];
+        let pak = PakFile::parse(&write_path_hash_pak(11, &directories, Some(&KEY)), Some(&KEY)).unwrap();
+
+        let entries = pak.entries();
+        let paths = entries.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["Root.txt", "Game/Content/Big.uasset", "Game/Content/Loose.bin"]);
+        assert_eq!((entries[0].offset, entries[0].size, entries[0].compression_block_size), (0, 100, 0));
+        assert_eq!((entries[1].offset, entries[1].uncompressed_size, entries[1].size), (100, 500, 80));
+        assert_eq!((entries[1].compression.as_deref(), entries[1].encrypted), (Some("Oodle"), true));
+        assert_eq!((entries[1].compression_blocks, entries[1].compression_block_size), (2, 0x10000));
+        assert_eq!((entries[2].offset, entries[2].size), (0x200, 3));
+    }
+
+    #[test]
+    fn names_entries_after_their_hash_without_directory_index() {
+        let pak = PakFile::parse(&write_path_hash_pak(11, &[], None), None).unwrap();
+        let paths = pak.entries().iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["1122334455667788", "000000000000AABB"]);
+    }
+
+    #[test]
+    fn reads_utf8_directory_names_from_version_12() {
+        let directories: [Directory; 1] = [("/Game/Für/", &[("日本.uasset", 0)])];
+        let pak = PakFile::parse(&write_path_hash_pak(12, &directories, None), None).unwrap();
+        assert_eq!(pak.info().version, 12);
+        assert_eq!(pak.entries()[0].path, "Game/Für/日本.uasset");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(PakFile::parse(&[0; 0x400], None), Err(Error::NotAPak)));
+        assert!(matches!(PakFile::parse(&write_pak(2, &DATA, &[0; 8], None, &[], 0, None), None),
+                         Err(Error::UnsupportedVersion(2))));
+        assert!(matches!(PakFile::parse(&write_pak(13, &DATA, &[0; 8], None, &[], 5, None), None),
+                         Err(Error::UnsupportedVersion(13))));
+    }
+
+    #[test]
+    fn rejects_entry_counts_past_the_index() {
+        // A legacy index claiming i32::MAX entries and holding none must fail to read, not abort
+        let mut index = fstring("../../../");
+        index.extend(i32::MAX.to_le_bytes());
+        assert!(matches!(PakFile::parse(&write_pak(8, &DATA, &index, None, &[], 5, None), None), Err(Error::Scroll(_))));
+    }
+
+    #[test]
+    fn parses_hex_keys() {
+        let key = "0x4242424242424242424242424242424242424242424242424242424242424242";
+        assert_eq!(parse_key(key).unwrap(), KEY);
+        assert_eq!(parse_key(&key[2..]).unwrap(), KEY);
+        assert!(matches!(parse_key("0x42"), Err(Error::InvalidKeyFormat(_))));
+        assert!(matches!(parse_key(&key.replace("42", "4G")), Err(Error::InvalidKeyFormat(_))));
+    }
+}
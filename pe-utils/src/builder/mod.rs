@@ -0,0 +1,406 @@
+use goblin::pe::characteristic::{IMAGE_FILE_32BIT_MACHINE, IMAGE_FILE_EXECUTABLE_IMAGE, IMAGE_FILE_LARGE_ADDRESS_AWARE};
+use goblin::pe::header::{
+    COFF_MACHINE_X86, COFF_MACHINE_X86_64, DOS_MAGIC, PE_MAGIC, PE_POINTER_OFFSET, SIZEOF_COFF_HEADER, SIZEOF_PE_MAGIC,
+};
+use goblin::pe::optional_header::{MAGIC_32, MAGIC_64};
+use goblin::pe::section_table::{SectionTable, SIZEOF_SECTION_TABLE};
+use goblin::pe::subsystem::IMAGE_SUBSYSTEM_WINDOWS_GUI;
+use scroll::{Pread, Pwrite, LE};
+
+use crate::{Error, MemAlignedAddress};
+
+const PE_POINTER: usize = 0x80;
+const SECTION_ALIGNMENT: u32 = 0x1000;
+const FILE_ALIGNMENT: u32 = 0x200;
+const SIZEOF_OPTIONAL_HEADER_32: u16 = 0xE0;
+const SIZEOF_OPTIONAL_HEADER_64: u16 = 0xF0;
+const NUMBER_OF_RVA_AND_SIZES: u32 = 16;
+const SIZEOF_DATA_DIRECTORY: usize = 8;
+
+const COFF_HEADER_NUMBER_OF_SECTIONS: usize = 2;
+const COFF_HEADER_SIZE_OF_OPTIONAL_HEADER: usize = 16;
+const OPTIONAL_HEADER_ADDRESS_OF_ENTRY_POINT: usize = 16;
+const OPTIONAL_HEADER_SECTION_ALIGNMENT: usize = 32;
+const OPTIONAL_HEADER_FILE_ALIGNMENT: usize = 36;
+const OPTIONAL_HEADER_SIZE_OF_IMAGE: usize = 56;
+const OPTIONAL_HEADER_SIZE_OF_HEADERS: usize = 60;
+const OPTIONAL_HEADER_NUMBER_OF_RVA_AND_SIZES_32: usize = 92;
+const OPTIONAL_HEADER_NUMBER_OF_RVA_AND_SIZES_64: usize = 108;
+
+/// A section and its raw contents, `data` may be shorter than `VirtualSize`, the rest is zero
+/// filled by the loader
+#[derive(Debug, Clone)]
+pub struct BuilderSection {
+    pub header: SectionTable,
+    pub data: Vec<u8>,
+}
+
+impl BuilderSection {
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.header.name).trim_end_matches('\0').to_string()
+    }
+
+    fn virtual_end(&self) -> u32 {
+        self.header.virtual_address + self.header.virtual_size.max(self.data.len() as u32)
+    }
+}
+
+/// Edits a PE image at the section level and serializes it again with the raw data laid out at
+/// `FileAlignment`. Header fields the builder does not manage are kept as they are
+#[derive(Debug, Clone)]
+pub struct PeBuilder {
+    /// Everything up to `SizeOfHeaders`, the section table is rewritten on build
+    headers: Vec<u8>,
+    optional_header_offset: usize,
+    section_table_offset: usize,
+    data_directories_offset: usize,
+    number_of_data_directories: usize,
+    section_alignment: u32,
+    file_alignment: u32,
+    sections: Vec<BuilderSection>,
+    overlay: Vec<u8>,
+}
+
+impl PeBuilder {
+    /// Loads an image in the file layout, data past the last section is kept as overlay
+    pub fn from_image(image: &[u8]) -> Result<Self, Error> {
+        let mut builder = Self::from_headers(image)?;
+        let header = crate::parse_headers(image)?;
+        let mut raw_end = builder.headers.len();
+        for section in crate::get_sections(&header, image)? {
+            let start = (section.pointer_to_raw_data as usize).min(image.len());
+            let end = (start + section.size_of_raw_data as usize).min(image.len());
+            raw_end = raw_end.max(end);
+            builder.sections.push(BuilderSection { header: section, data: image[start..end].to_vec() });
+        }
+        builder.overlay = image.get(raw_end..).unwrap_or_default().to_vec();
+        Ok(builder)
+    }
+
+    /// Loads an image in the memory layout (RVA == offset), every section takes `VirtualSize`
+    /// bytes from its RVA
+    pub fn from_memory(dump: &[u8]) -> Result<Self, Error> {
+        let header = crate::parse_headers(dump)?;
+        let sections = crate::get_sections(&header, dump)?;
+        Self::from_memory_sections(dump, &sections)
+    }
+
+    /// Same as [`PeBuilder::from_memory`] with the section table supplied by the caller, for dumps
+    /// whose own table is damaged
+    pub fn from_memory_sections(dump: &[u8], sections: &[SectionTable]) -> Result<Self, Error> {
+        let mut builder = Self::from_headers(dump)?;
+        for section in sections.iter().cloned() {
+            let size = match section.virtual_size {
+                0 => section.size_of_raw_data,
+                virtual_size => virtual_size,
+            };
+            let start = (section.virtual_address as usize).min(dump.len());
+            let end = (start + size as usize).min(dump.len());
+            builder.sections.push(BuilderSection { header: section, data: dump[start..end].to_vec() });
+        }
+        Ok(builder)
+    }
+
+    /// Starts an empty image with minimal headers, x64 when `is_64` and x86 otherwise
+    pub fn new(is_64: bool, image_base: u64) -> Result<Self, Error> {
+        let size_of_optional_header = match is_64 {
+            true => SIZEOF_OPTIONAL_HEADER_64,
+            false => SIZEOF_OPTIONAL_HEADER_32,
+        };
+        let optional = PE_POINTER + SIZEOF_PE_MAGIC + SIZEOF_COFF_HEADER;
+        let mut headers = vec![0u8; FILE_ALIGNMENT as usize];
+
+        headers.pwrite_with(DOS_MAGIC, 0, LE)?;
+        headers.pwrite_with(PE_POINTER as u32, PE_POINTER_OFFSET as usize, LE)?;
+        headers.pwrite_with(PE_MAGIC, PE_POINTER, LE)?;
+
+        let coff = PE_POINTER + SIZEOF_PE_MAGIC;
+        let (machine, characteristics) = match is_64 {
+            true => (COFF_MACHINE_X86_64, IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE),
+            false => (COFF_MACHINE_X86, IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_32BIT_MACHINE),
+        };
+        headers.pwrite_with(machine, coff, LE)?;
+        headers.pwrite_with(size_of_optional_header, coff + COFF_HEADER_SIZE_OF_OPTIONAL_HEADER, LE)?;
+        headers.pwrite_with(characteristics, coff + 18, LE)?;
+
+        headers.pwrite_with(if is_64 { MAGIC_64 } else { MAGIC_32 }, optional, LE)?;
+        if is_64 {
+            headers.pwrite_with(image_base, optional + 24, LE)?;
+        } else {
+            headers.pwrite_with(image_base as u32, optional + 28, LE)?;
+        }
+        headers.pwrite_with(SECTION_ALIGNMENT, optional + OPTIONAL_HEADER_SECTION_ALIGNMENT, LE)?;
+        headers.pwrite_with(FILE_ALIGNMENT, optional + OPTIONAL_HEADER_FILE_ALIGNMENT, LE)?;
+        // Operating system and subsystem version 6.0
+        headers.pwrite_with(6u16, optional + 40, LE)?;
+        headers.pwrite_with(6u16, optional + 48, LE)?;
+        headers.pwrite_with(FILE_ALIGNMENT, optional + OPTIONAL_HEADER_SIZE_OF_HEADERS, LE)?;
+        headers.pwrite_with(IMAGE_SUBSYSTEM_WINDOWS_GUI, optional + 68, LE)?;
+        if is_64 {
+            headers.pwrite_with(0x10_0000u64, optional + 72, LE)?;
+            headers.pwrite_with(SECTION_ALIGNMENT as u64, optional + 80, LE)?;
+            headers.pwrite_with(0x10_0000u64, optional + 88, LE)?;
+            headers.pwrite_with(SECTION_ALIGNMENT as u64, optional + 96, LE)?;
+            headers.pwrite_with(NUMBER_OF_RVA_AND_SIZES, optional + OPTIONAL_HEADER_NUMBER_OF_RVA_AND_SIZES_64, LE)?;
+        } else {
+            headers.pwrite_with(0x10_0000u32, optional + 72, LE)?;
+            headers.pwrite_with(SECTION_ALIGNMENT, optional + 76, LE)?;
+            headers.pwrite_with(0x10_0000u32, optional + 80, LE)?;
+            headers.pwrite_with(SECTION_ALIGNMENT, optional + 84, LE)?;
+            headers.pwrite_with(NUMBER_OF_RVA_AND_SIZES, optional + OPTIONAL_HEADER_NUMBER_OF_RVA_AND_SIZES_32, LE)?;
+        }
+
+        Self::from_headers(&headers)
+    }
+
+    fn from_headers(image: &[u8]) -> Result<Self, Error> {
+        let header = crate::parse_headers(image)?;
+        let optional_header = crate::get_optional_headers(&header)?;
+        let optional_header_offset = header.dos_header.pe_pointer as usize + SIZEOF_PE_MAGIC + SIZEOF_COFF_HEADER;
+        let data_directories_offset = optional_header_offset + match optional_header.standard_fields.magic {
+            MAGIC_64 => OPTIONAL_HEADER_NUMBER_OF_RVA_AND_SIZES_64 + 4,
+            _ => OPTIONAL_HEADER_NUMBER_OF_RVA_AND_SIZES_32 + 4,
+        };
+        let size_of_headers = (optional_header.windows_fields.size_of_headers as usize).min(image.len());
+        Ok(PeBuilder {
+            headers: image[0..size_of_headers].to_vec(),
+            optional_header_offset,
+            section_table_offset: optional_header_offset + header.coff_header.size_of_optional_header as usize,
+            data_directories_offset,
+            number_of_data_directories: optional_header.windows_fields.number_of_rva_and_sizes as usize,
+            section_alignment: optional_header.windows_fields.section_alignment.max(1),
+            file_alignment: optional_header.windows_fields.file_alignment.max(1),
+            sections: vec![],
+            overlay: vec![],
+        })
+    }
+
+    pub fn sections(&self) -> &[BuilderSection] {
+        &self.sections
+    }
+
+    pub fn section_mut(&mut self, name: &str) -> Option<&mut BuilderSection> {
+        self.sections.iter_mut().find(|section| section.name() == name)
+    }
+
+    /// RVA the next added section will be placed at
+    pub fn next_section_rva(&self) -> u32 {
+        let end = self.sections.iter()
+            .map(BuilderSection::virtual_end)
+            .max()
+            .unwrap_or(self.headers.len() as u32);
+        u32::get_mem_aligned_address(end, self.section_alignment)
+    }
+
+    /// Appends a section after the last one, returning its RVA
+    pub fn add_section(&mut self, name: &str, data: Vec<u8>, characteristics: u32) -> Result<u32, Error> {
+        let mut section_name = [0u8; 8];
+        let length = name.len().min(8);
+        section_name[0..length].copy_from_slice(&name.as_bytes()[0..length]);
+
+        let virtual_address = self.next_section_rva();
+        self.sections.push(BuilderSection {
+            header: SectionTable {
+                name: section_name,
+                virtual_size: data.len() as u32,
+                virtual_address,
+                characteristics,
+                ..Default::default()
+            },
+            data,
+        });
+        Ok(virtual_address)
+    }
+
+    pub fn remove_section(&mut self, name: &str) -> Option<BuilderSection> {
+        let index = self.sections.iter().position(|section| section.name() == name)?;
+        Some(self.sections.remove(index))
+    }
+
+    /// Changes the `VirtualSize` of a section, cutting its data when it shrinks. Sections can not
+    /// grow into the next one since that would move code and data referenced by RVA
+    pub fn resize_section(&mut self, name: &str, virtual_size: u32) -> Result<(), Error> {
+        let index = self.sections.iter()
+            .position(|section| section.name() == name)
+            .ok_or_else(|| Error::SectionNotFound(name.to_string()))?;
+        let virtual_address = self.sections[index].header.virtual_address;
+        let next_section = self.sections.iter()
+            .map(|section| section.header.virtual_address)
+            .filter(|rva| *rva > virtual_address)
+            .min();
+        if let Some(next_section) = next_section {
+            if u32::get_mem_aligned_address(virtual_address + virtual_size, self.section_alignment) > next_section {
+                return Err(Error::SectionOverlap(name.to_string()));
+            }
+        }
+
+        let section = &mut self.sections[index];
+        section.header.virtual_size = virtual_size;
+        section.data.truncate(virtual_size as usize);
+        Ok(())
+    }
+
+    /// Copies `bytes` into the section containing `rva`, growing its data up to `VirtualSize`
+    pub fn write_at_rva(&mut self, rva: u32, bytes: &[u8]) -> Result<(), Error> {
+        let section = self.sections.iter_mut()
+            .find(|section| rva >= section.header.virtual_address && rva < section.virtual_end())
+            .ok_or(Error::RvaNotInSection(rva as usize))?;
+        let start = (rva - section.header.virtual_address) as usize;
+        let end = start + bytes.len();
+        if end as u32 > section.header.virtual_size.max(section.data.len() as u32) {
+            return Err(Error::RvaNotInSection(rva as usize + bytes.len()));
+        }
+        if section.data.len() < end {
+            section.data.resize(end, 0);
+        }
+        section.data[start..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn data_directory(&self, index: usize) -> Option<(u32, u32)> {
+        if index >= self.number_of_data_directories {
+            return None;
+        }
+        let offset = self.data_directories_offset + index * SIZEOF_DATA_DIRECTORY;
+        let virtual_address = self.headers.pread_with(offset, LE).ok()?;
+        let size = self.headers.pread_with(offset + 4, LE).ok()?;
+        Some((virtual_address, size))
+    }
+
+    pub fn set_data_directory(&mut self, index: usize, virtual_address: u32, size: u32) -> Result<(), Error> {
+        if index >= self.number_of_data_directories {
+            return Err(Error::NoDataDirectory(index));
+        }
+        let offset = self.data_directories_offset + index * SIZEOF_DATA_DIRECTORY;
+        self.headers.pwrite_with(virtual_address, offset, LE)?;
+        self.headers.pwrite_with(size, offset + 4, LE)?;
+        Ok(())
+    }
+
+    pub fn set_entry_point(&mut self, rva: u32) -> Result<(), Error> {
+        self.headers.pwrite_with(rva, self.optional_header_offset + OPTIONAL_HEADER_ADDRESS_OF_ENTRY_POINT, LE)?;
+        Ok(())
+    }
+
+    /// Serializes the image: sections sorted by RVA, raw data packed at `FileAlignment`, then the
+    /// section table, `NumberOfSections`, `SizeOfHeaders`, `SizeOfImage` and the fields handled by
+    /// [`crate::fix_headers`] are updated
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut sections = self.sections.clone();
+        sections.sort_by_key(|section| section.header.virtual_address);
+
+        let section_table_end = self.section_table_offset + sections.len() * SIZEOF_SECTION_TABLE;
+        let size_of_headers = u32::get_mem_aligned_address(
+            (self.headers.len().max(section_table_end)) as u32,
+            self.file_alignment,
+        );
+        let first_section = sections.first().map(|section| section.header.virtual_address).unwrap_or(u32::MAX);
+        if size_of_headers > first_section {
+            return Err(Error::NoRoomForSection);
+        }
+
+        let mut output = self.headers.clone();
+        output.resize(size_of_headers as usize, 0);
+        // Clear the old table, it may have held more sections than the new one
+        let old_table_end = self.headers.len().min(size_of_headers as usize);
+        if self.section_table_offset < old_table_end {
+            let old_sections: u16 = output.pread_with(self.optional_header_offset - SIZEOF_COFF_HEADER + COFF_HEADER_NUMBER_OF_SECTIONS, LE)?;
+            let old_end = (self.section_table_offset + old_sections as usize * SIZEOF_SECTION_TABLE).min(old_table_end);
+            output[self.section_table_offset..old_end.max(self.section_table_offset)].fill(0);
+        }
+
+        let mut size_of_image = size_of_headers;
+        for (index, section) in sections.iter_mut().enumerate() {
+            let raw_size = u32::get_mem_aligned_address(section.data.len() as u32, self.file_alignment);
+            section.header.pointer_to_raw_data = match raw_size {
+                0 => 0,
+                _ => output.len() as u32,
+            };
+            section.header.size_of_raw_data = raw_size;
+            output.extend_from_slice(&section.data);
+            output.resize(output.len() + (raw_size as usize - section.data.len()), 0);
+            size_of_image = size_of_image.max(section.virtual_end());
+
+            output.pwrite_with(&section.header, self.section_table_offset + index * SIZEOF_SECTION_TABLE, LE)?;
+        }
+        output.extend_from_slice(&self.overlay);
+
+        let coff = self.optional_header_offset - SIZEOF_COFF_HEADER;
+        output.pwrite_with(sections.len() as u16, coff + COFF_HEADER_NUMBER_OF_SECTIONS, LE)?;
+        output.pwrite_with(size_of_headers, self.optional_header_offset + OPTIONAL_HEADER_SIZE_OF_HEADERS, LE)?;
+        output.pwrite_with(
+            u32::get_mem_aligned_address(size_of_image, self.section_alignment),
+            self.optional_header_offset + OPTIONAL_HEADER_SIZE_OF_IMAGE,
+            LE,
+        )?;
+
+        crate::fix_headers(&mut output)?;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_MEM_EXECUTE};
+
+    use super::*;
+    use crate::PeImage;
+
+    const CODE: u32 = IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE;
+
+    #[test]
+    fn builds_parsable_image() {
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        let text = builder.add_section(".text", vec![0xCC; 0x1234], CODE).unwrap();
+        let data = builder.add_section(".data", vec![1, 2, 3], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_entry_point(text + 0x10).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        assert!(image.is_64());
+        assert_eq!(image.image_base(), 0x1_4000_0000);
+        assert_eq!(image.entry_point(), text + 0x10);
+        assert_eq!((text, data), (0x1000, 0x3000));
+        let sections = image.sections();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].pointer_to_raw_data, 0x200);
+        assert_eq!(sections[0].size_of_raw_data, 0x1400);
+        assert_eq!(sections[1].pointer_to_raw_data, 0x1600);
+        assert_eq!(&image.section_data(&sections[1])[0..3], &[1, 2, 3]);
+        assert_eq!(image.optional_header().windows_fields.size_of_image, 0x4000);
+        assert_eq!(image.optional_header().standard_fields.size_of_code, 0x1400);
+    }
+
+    #[test]
+    fn round_trips_through_from_image() {
+        let mut builder = PeBuilder::new(false, 0x40_0000).unwrap();
+        builder.add_section(".text", vec![0x90; 0x100], CODE).unwrap();
+        builder.add_section(".rdata", vec![0x55; 0x80], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(6, 0x2000, 0x1C).unwrap();
+        let built = builder.build().unwrap();
+
+        let mut loaded = PeBuilder::from_image(&built).unwrap();
+        assert_eq!(loaded.data_directory(6), Some((0x2000, 0x1C)));
+        assert_eq!(loaded.sections().iter().map(BuilderSection::name).collect::<Vec<_>>(), [".text", ".rdata"]);
+        assert_eq!(loaded.build().unwrap(), built);
+
+        loaded.remove_section(".rdata").unwrap();
+        let image = PeImage::parse(loaded.build().unwrap()).unwrap();
+        assert!(!image.is_64());
+        assert_eq!(image.sections().len(), 1);
+        assert_eq!(image.optional_header().windows_fields.size_of_image, 0x2000);
+    }
+
+    #[test]
+    fn refuses_to_grow_into_the_next_section() {
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", vec![0; 0x100], CODE).unwrap();
+        builder.add_section(".data", vec![0; 0x100], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+
+        assert!(matches!(builder.resize_section(".text", 0x1800), Err(Error::SectionOverlap(_))));
+        builder.resize_section(".text", 0x1000).unwrap();
+        builder.resize_section(".data", 0x10).unwrap();
+        assert_eq!(builder.section_mut(".data").unwrap().data.len(), 0x10);
+        assert!(matches!(builder.write_at_rva(0x2010, &[1]), Err(Error::RvaNotInSection(_))));
+        assert!(matches!(builder.resize_section(".bss", 0x10), Err(Error::SectionNotFound(_))));
+    }
+}
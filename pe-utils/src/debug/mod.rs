@@ -0,0 +1,304 @@
+use goblin::pe::optional_header::OptionalHeader;
+use goblin::pe::section_table::SectionTable;
+use scroll::{Pread, LE};
+
+use crate::Error;
+
+pub const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+pub const IMAGE_DEBUG_TYPE_VC_FEATURE: u32 = 12;
+pub const IMAGE_DEBUG_TYPE_POGO: u32 = 13;
+pub const IMAGE_DEBUG_TYPE_REPRO: u32 = 16;
+
+const DATA_DIRECTORY_DEBUG: usize = 6;
+const SIZEOF_DEBUG_DIRECTORY: usize = 28;
+const CODEVIEW_PDB70: &[u8; 4] = b"RSDS";
+const CODEVIEW_PDB20: &[u8; 4] = b"NB10";
+
+/// One `IMAGE_DEBUG_DIRECTORY` entry
+#[derive(Debug, Clone, Copy)]
+pub struct DebugEntry {
+    pub time_date_stamp: u32,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub kind: u32,
+    pub size_of_data: u32,
+    pub address_of_raw_data: u32,
+    pub pointer_to_raw_data: u32,
+}
+
+impl DebugEntry {
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            IMAGE_DEBUG_TYPE_CODEVIEW => "CodeView",
+            IMAGE_DEBUG_TYPE_VC_FEATURE => "VC Feature",
+            IMAGE_DEBUG_TYPE_POGO => "POGO",
+            IMAGE_DEBUG_TYPE_REPRO => "Repro",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// The CodeView record pointing at the PDB the image was linked with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeView {
+    /// `RSDS`, every linker since Visual Studio .NET
+    Pdb70 { guid: [u8; 16], age: u32, path: String },
+    /// `NB10`, identified by a timestamp instead of a GUID
+    Pdb20 { signature: u32, age: u32, path: String },
+}
+
+impl CodeView {
+    pub fn path(&self) -> &str {
+        match self {
+            CodeView::Pdb70 { path, .. } | CodeView::Pdb20 { path, .. } => path,
+        }
+    }
+
+    pub fn age(&self) -> u32 {
+        match self {
+            CodeView::Pdb70 { age, .. } | CodeView::Pdb20 { age, .. } => *age,
+        }
+    }
+
+    /// The GUID in registry format, `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}`, `None` for `NB10`
+    pub fn guid(&self) -> Option<String> {
+        let CodeView::Pdb70 { guid, .. } = self else { return None };
+        Some(format!("{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}}}",
+                     u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+                     u16::from_le_bytes([guid[4], guid[5]]),
+                     u16::from_le_bytes([guid[6], guid[7]]),
+                     guid[8], guid[9],
+                     guid[10..16].iter().map(|byte| format!("{:02X}", byte)).collect::<String>()))
+    }
+
+    /// The directory name a symbol server stores the PDB under, GUID (or signature) followed by
+    /// the age in hex
+    pub fn symbol_server_key(&self) -> String {
+        match self {
+            CodeView::Pdb70 { age, .. } => {
+                let guid = self.guid().unwrap_or_default().replace(['{', '}', '-'], "");
+                format!("{}{:X}", guid, age)
+            }
+            CodeView::Pdb20 { signature, age, .. } => format!("{:08X}{:X}", signature, age),
+        }
+    }
+}
+
+/// A contribution listed in the POGO (profile guided optimization) entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PogoEntry {
+    pub rva: u32,
+    pub size: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pogo {
+    /// `GCTL` for regular builds, `PGI\0`/`PGU\0` for instrumented and optimized PGO builds
+    pub signature: String,
+    pub entries: Vec<PogoEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub entries: Vec<DebugEntry>,
+    pub codeview: Option<CodeView>,
+    pub pogo: Option<Pogo>,
+    /// The linker's reproducible build hash, empty for a `/Brepro` build without one, `None` when
+    /// the image was not built deterministically
+    pub repro: Option<Vec<u8>>,
+}
+
+/// Parses the debug directory of a file layout image
+pub fn get_debug_info(bytes: &[u8],
+                      optional_header: &OptionalHeader,
+                      sections: &[SectionTable]) -> Result<Option<DebugInfo>, Error> {
+    let directory = match optional_header.data_directories.data_directories.get(DATA_DIRECTORY_DEBUG) {
+        Some(Some((_, directory))) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Ok(None),
+    };
+    let directory_offset = crate::rva_to_offset(sections, directory.virtual_address as usize)?;
+
+    let mut info = DebugInfo::default();
+    for index in 0..directory.size as usize / SIZEOF_DEBUG_DIRECTORY {
+        let offset = directory_offset + index * SIZEOF_DEBUG_DIRECTORY;
+        let entry = DebugEntry {
+            time_date_stamp: bytes.pread_with(offset + 4, LE)?,
+            major_version: bytes.pread_with(offset + 8, LE)?,
+            minor_version: bytes.pread_with(offset + 10, LE)?,
+            kind: bytes.pread_with(offset + 12, LE)?,
+            size_of_data: bytes.pread_with(offset + 16, LE)?,
+            address_of_raw_data: bytes.pread_with(offset + 20, LE)?,
+            pointer_to_raw_data: bytes.pread_with(offset + 24, LE)?,
+        };
+        info.entries.push(entry);
+
+        let data = entry_data(bytes, sections, &entry);
+        match entry.kind {
+            IMAGE_DEBUG_TYPE_CODEVIEW => info.codeview = data.and_then(parse_codeview),
+            IMAGE_DEBUG_TYPE_POGO => info.pogo = data.and_then(parse_pogo),
+            IMAGE_DEBUG_TYPE_REPRO => {
+                // The hash is prefixed by its length, older linkers only leave an empty entry
+                let hash = data
+                    .and_then(|data| {
+                        let length = data.pread_with::<u32>(0, LE).ok()? as usize;
+                        data.get(4..4 + length)
+                    })
+                    .unwrap_or_default();
+                info.repro = Some(hash.to_vec());
+            }
+            _ => {}
+        }
+    }
+    Ok(Some(info))
+}
+
+/// The entry contents, located through the RVA first since restored images may carry stale raw
+/// pointers
+fn entry_data<'a>(bytes: &'a [u8], sections: &[SectionTable], entry: &DebugEntry) -> Option<&'a [u8]> {
+    if entry.size_of_data == 0 {
+        return None;
+    }
+    let offset = match entry.address_of_raw_data {
+        0 => entry.pointer_to_raw_data as usize,
+        rva => crate::rva_to_offset(sections, rva as usize).ok()?,
+    };
+    bytes.get(offset..offset + entry.size_of_data as usize)
+}
+
+fn parse_codeview(data: &[u8]) -> Option<CodeView> {
+    let path = |start: usize| -> String {
+        let path = data.get(start..).unwrap_or_default();
+        let end = path.iter().position(|byte| *byte == 0).unwrap_or(path.len());
+        String::from_utf8_lossy(&path[0..end]).into_owned()
+    };
+    match data.get(0..4)? {
+        magic if magic == CODEVIEW_PDB70 => Some(CodeView::Pdb70 {
+            guid: data.get(4..20)?.try_into().ok()?,
+            age: data.pread_with(20, LE).ok()?,
+            path: path(24),
+        }),
+        magic if magic == CODEVIEW_PDB20 => Some(CodeView::Pdb20 {
+            signature: data.pread_with(8, LE).ok()?,
+            age: data.pread_with(12, LE).ok()?,
+            path: path(16),
+        }),
+        _ => None,
+    }
+}
+
+fn parse_pogo(data: &[u8]) -> Option<Pogo> {
+    let signature = data.get(0..4)?;
+    let signature = String::from_utf8_lossy(signature).trim_end_matches('\0').to_string();
+
+    let mut entries = vec![];
+    let mut offset = 4;
+    while offset + 8 < data.len() {
+        let rva: u32 = data.pread_with(offset, LE).ok()?;
+        let size: u32 = data.pread_with(offset + 4, LE).ok()?;
+        let name = &data[offset + 8..];
+        let length = name.iter().position(|byte| *byte == 0)?;
+        entries.push(PogoEntry { rva, size, name: String::from_utf8_lossy(&name[0..length]).into_owned() });
+        // Names are padded so the next entry starts 4 byte aligned
+        offset = (offset + 8 + length + 1 + 3) & !3;
+    }
+    Some(Pogo { signature, entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_INITIALIZED_DATA;
+    use scroll::Pwrite;
+
+    use super::*;
+    use crate::{PeBuilder, PeImage};
+
+    const GUID: [u8; 16] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Debug directory at 0x1000 with CodeView, POGO and repro entries pointing further into the
+    /// same section
+    fn build_image() -> PeImage {
+        let mut rdata = vec![0u8; 0x300];
+        for (index, (kind, rva, size)) in [
+            (IMAGE_DEBUG_TYPE_CODEVIEW, 0x1100u32, 0x30u32),
+            (IMAGE_DEBUG_TYPE_POGO, 0x1180, 0x2C),
+            (IMAGE_DEBUG_TYPE_REPRO, 0x1200, 0x24),
+        ].into_iter().enumerate() {
+            let offset = index * SIZEOF_DEBUG_DIRECTORY;
+            rdata.pwrite_with(0x6543_2100u32, offset + 4, LE).unwrap();
+            rdata.pwrite_with(kind, offset + 12, LE).unwrap();
+            rdata.pwrite_with(size, offset + 16, LE).unwrap();
+            rdata.pwrite_with(rva, offset + 20, LE).unwrap();
+            // Stale raw pointer, as left in a restored image
+            rdata.pwrite_with(0xDEADu32, offset + 24, LE).unwrap();
+        }
+
+        rdata[0x100..0x104].copy_from_slice(CODEVIEW_PDB70);
+        rdata[0x104..0x114].copy_from_slice(&GUID);
+        rdata.pwrite_with(3u32, 0x114, LE).unwrap();
+        rdata[0x118..0x129].copy_from_slice(b"C:\\build\\Game.pdb");
+
+        rdata[0x180..0x184].copy_from_slice(b"GCTL");
+        rdata.pwrite_with(0x1000u32, 0x184, LE).unwrap();
+        rdata.pwrite_with(0x20u32, 0x188, LE).unwrap();
+        rdata[0x18C..0x194].copy_from_slice(b".text$mn");
+        rdata.pwrite_with(0x1100u32, 0x198, LE).unwrap();
+        rdata.pwrite_with(0x40u32, 0x19C, LE).unwrap();
+        rdata[0x1A0..0x1A6].copy_from_slice(b".rdata");
+
+        rdata.pwrite_with(0x20u32, 0x200, LE).unwrap();
+        rdata[0x204..0x224].fill(0xAB);
+
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".rdata", rdata, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(DATA_DIRECTORY_DEBUG, 0x1000, 3 * SIZEOF_DEBUG_DIRECTORY as u32).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reads_codeview_pogo_and_repro_entries() {
+        let info = build_image().debug_info().unwrap().unwrap();
+
+        assert_eq!(info.entries.len(), 3);
+        assert_eq!(info.entries.iter().map(DebugEntry::kind_name).collect::<Vec<_>>(), ["CodeView", "POGO", "Repro"]);
+        assert_eq!(info.entries[0].time_date_stamp, 0x6543_2100);
+
+        let codeview = info.codeview.unwrap();
+        assert_eq!(codeview, CodeView::Pdb70 { guid: GUID, age: 3, path: "C:\\build\\Game.pdb".to_string() });
+        assert_eq!(codeview.guid().unwrap(), "{67452301-AB89-EFCD-0102-030405060708}");
+        assert_eq!(codeview.symbol_server_key(), "67452301AB89EFCD01020304050607083");
+
+        let pogo = info.pogo.unwrap();
+        assert_eq!(pogo.signature, "GCTL");
+        assert_eq!(pogo.entries, [
+            PogoEntry { rva: 0x1000, size: 0x20, name: ".text$mn".to_string() },
+            PogoEntry { rva: 0x1100, size: 0x40, name: ".rdata".to_string() },
+        ]);
+
+        assert_eq!(info.repro, Some(vec![0xAB; 0x20]));
+    }
+
+    #[test]
+    fn reads_nb10_records() {
+        let mut data = b"NB10".to_vec();
+        data.extend(0u32.to_le_bytes());
+        data.extend(0x3A2B_1C0Du32.to_le_bytes());
+        data.extend(0x1Fu32.to_le_bytes());
+        data.extend(b"old.pdb\0");
+
+        let codeview = parse_codeview(&data).unwrap();
+        assert_eq!(codeview.path(), "old.pdb");
+        assert_eq!(codeview.age(), 0x1F);
+        assert_eq!(codeview.guid(), None);
+        assert_eq!(codeview.symbol_server_key(), "3A2B1C0D1F");
+        assert_eq!(parse_codeview(b"RSDS\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn image_without_debug_directory() {
+        let mut builder = PeBuilder::new(false, 0x40_0000).unwrap();
+        builder.add_section(".rdata", vec![0; 0x10], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+        assert!(image.debug_info().unwrap().is_none());
+    }
+}
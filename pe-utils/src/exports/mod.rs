@@ -0,0 +1,78 @@
+use goblin::pe::export::{ExportAddressTableEntry, ExportData};
+use goblin::pe::optional_header::OptionalHeader;
+use goblin::pe::section_table::SectionTable;
+use goblin::pe::{options, utils};
+use scroll::Pread;
+
+use crate::Error;
+
+#[derive(Debug, Clone)]
+pub struct ExportedFunction<'a> {
+    pub ordinal: u32,
+    pub rva: u32,
+    pub name: Option<&'a str>,
+    /// Set when the export forwards to another module, e.g. `NTDLL.RtlAllocateHeap`
+    pub forwarder: Option<&'a str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Exports<'a> {
+    pub name: Option<&'a str>,
+    pub ordinal_base: u32,
+    pub functions: Vec<ExportedFunction<'a>>,
+}
+
+pub fn get_exports<'a>(bytes: &'a [u8],
+                       optional_header: &OptionalHeader,
+                       sections: &[SectionTable]) -> Result<Option<Exports<'a>>, Error> {
+    let opts = &options::ParseOptions::default();
+    let file_alignment = optional_header.windows_fields.file_alignment;
+
+    let export_table = match optional_header.data_directories.get_export_table() {
+        Some(&export_table) if export_table.virtual_address != 0 => export_table,
+        _ => return Ok(None),
+    };
+
+    let export_data = ExportData::parse_with_opts(bytes, export_table, sections, file_alignment, opts)?;
+    let ordinal_base = export_data.export_directory_table.ordinal_base;
+
+    // The name pointer table and the ordinal table run in parallel, the ordinal table holds
+    // the index into the address table for each named export
+    let mut names: Vec<Option<&'a str>> = vec![None; export_data.export_address_table.len()];
+    for (name_rva, index) in export_data.export_name_pointer_table.iter()
+        .zip(export_data.export_ordinal_table.iter()) {
+        let name = utils::find_offset(*name_rva as usize, sections, file_alignment, opts)
+            .and_then(|offset| bytes.pread::<&str>(offset).ok());
+        if let Some(slot) = names.get_mut(*index as usize) {
+            *slot = name;
+        }
+    }
+
+    let mut functions = Vec::with_capacity(export_data.export_address_table.len());
+    for (index, entry) in export_data.export_address_table.iter().enumerate() {
+        let (rva, forwarder) = match entry {
+            ExportAddressTableEntry::ExportRVA(rva) => (*rva, None),
+            ExportAddressTableEntry::ForwarderRVA(rva) => {
+                let forwarder = utils::find_offset(*rva as usize, sections, file_alignment, opts)
+                    .and_then(|offset| bytes.pread::<&str>(offset).ok());
+                (*rva, forwarder)
+            }
+        };
+        // Unused slots in the address table are zero and do not correspond to an export
+        if rva == 0 {
+            continue;
+        }
+        functions.push(ExportedFunction {
+            ordinal: ordinal_base + index as u32,
+            rva,
+            name: names[index],
+            forwarder,
+        });
+    }
+
+    Ok(Some(Exports {
+        name: export_data.name,
+        ordinal_base,
+        functions,
+    }))
+}
@@ -0,0 +1,243 @@
+use goblin::pe::optional_header::MAGIC_64;
+use goblin::pe::section_table::{
+    SectionTable, IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_CNT_UNINITIALIZED_DATA,
+};
+use scroll::{Pread, Pwrite, LE};
+
+use crate::{rva_to_offset, Error, MemAlignedAddress};
+
+const OPTIONAL_HEADER_SIZE_OF_CODE: usize = 4;
+const OPTIONAL_HEADER_SIZE_OF_INITIALIZED_DATA: usize = 8;
+const OPTIONAL_HEADER_SIZE_OF_UNINITIALIZED_DATA: usize = 12;
+const OPTIONAL_HEADER_SIZE_OF_IMAGE: usize = 56;
+const OPTIONAL_HEADER_CHECKSUM: usize = 64;
+const OPTIONAL_HEADER_DATA_DIRECTORIES_32: usize = 96;
+const OPTIONAL_HEADER_DATA_DIRECTORIES_64: usize = 112;
+const SIZEOF_DATA_DIRECTORY: usize = 8;
+
+const DIRECTORY_IMPORT: usize = 1;
+const DIRECTORY_BASE_RELOCATION: usize = 5;
+const DIRECTORY_CERTIFICATE: usize = 4;
+const DIRECTORY_DELAY_IMPORT: usize = 13;
+const SIZEOF_IMPORT_DESCRIPTOR: usize = 20;
+const SIZEOF_DELAY_IMPORT_DESCRIPTOR: usize = 32;
+
+/// A header field whose value was rewritten
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixup {
+    pub field: String,
+    pub old: u32,
+    pub new: u32,
+}
+
+/// Recomputes `SizeOfCode`, `SizeOfInitializedData`, `SizeOfUninitializedData`, `SizeOfImage`, the
+/// sizes of the import, delay-load and base relocation directories and finally `CheckSum` from the
+/// section table and contents of a file layout image. Directories reaching past the end of their
+/// section are clamped. Returns the fields that changed
+pub fn fix_headers(image: &mut [u8]) -> Result<Vec<Fixup>, Error> {
+    let header = crate::parse_headers(image)?;
+    let optional_header = crate::get_optional_headers(&header)?;
+    let sections = crate::get_sections(&header, image)?;
+    let optional_header_offset = header.dos_header.pe_pointer as usize
+        + goblin::pe::header::SIZEOF_PE_MAGIC
+        + goblin::pe::header::SIZEOF_COFF_HEADER;
+    let data_directories_offset = optional_header_offset + match optional_header.standard_fields.magic {
+        MAGIC_64 => OPTIONAL_HEADER_DATA_DIRECTORIES_64,
+        _ => OPTIONAL_HEADER_DATA_DIRECTORIES_32,
+    };
+    let number_of_directories = optional_header.windows_fields.number_of_rva_and_sizes as usize;
+    let section_alignment = optional_header.windows_fields.section_alignment.max(1);
+    let file_alignment = optional_header.windows_fields.file_alignment.max(1);
+
+    // Summed and aligned in 64 bits, crafted section sizes must not overflow the header fields
+    let sum_sizes = |flag: u32, size: fn(&SectionTable) -> u32| -> u32 {
+        let sum: u64 = sections.iter()
+            .filter(|section| section.characteristics & flag != 0)
+            .map(|section| u64::get_mem_aligned_address(size(section) as u64, file_alignment as u64))
+            .sum();
+        sum.min(u32::MAX as u64) as u32
+    };
+    let size_of_code = sum_sizes(IMAGE_SCN_CNT_CODE, |section| section.size_of_raw_data);
+    let size_of_initialized_data = sum_sizes(IMAGE_SCN_CNT_INITIALIZED_DATA, |section| section.size_of_raw_data);
+    let size_of_uninitialized_data = sum_sizes(IMAGE_SCN_CNT_UNINITIALIZED_DATA, |section| section.virtual_size);
+    let image_end = sections.iter()
+        .map(section_end)
+        .max()
+        .unwrap_or(optional_header.windows_fields.size_of_headers as u64);
+    let size_of_image = u64::get_mem_aligned_address(image_end, section_alignment as u64).min(u32::MAX as u64) as u32;
+
+    let mut fixups = vec![];
+    let mut write = |image: &mut [u8], field: String, offset: usize, new: u32| -> Result<(), Error> {
+        let old: u32 = image.pread_with(offset, LE)?;
+        if old != new {
+            image.pwrite_with(new, offset, LE)?;
+            fixups.push(Fixup { field, old, new });
+        }
+        Ok(())
+    };
+    write(image, "SizeOfCode".to_string(), optional_header_offset + OPTIONAL_HEADER_SIZE_OF_CODE, size_of_code)?;
+    write(image, "SizeOfInitializedData".to_string(),
+          optional_header_offset + OPTIONAL_HEADER_SIZE_OF_INITIALIZED_DATA, size_of_initialized_data)?;
+    write(image, "SizeOfUninitializedData".to_string(),
+          optional_header_offset + OPTIONAL_HEADER_SIZE_OF_UNINITIALIZED_DATA, size_of_uninitialized_data)?;
+    write(image, "SizeOfImage".to_string(), optional_header_offset + OPTIONAL_HEADER_SIZE_OF_IMAGE, size_of_image)?;
+
+    for index in 0..number_of_directories.min(16) {
+        // The certificate table is addressed by file offset and lives outside the sections
+        if index == DIRECTORY_CERTIFICATE {
+            continue;
+        }
+        let offset = data_directories_offset + index * SIZEOF_DATA_DIRECTORY;
+        let virtual_address: u32 = image.pread_with(offset, LE)?;
+        let size: u32 = image.pread_with(offset + 4, LE)?;
+        if virtual_address == 0 {
+            continue;
+        }
+        let Some(section) = containing_section(&sections, virtual_address) else { continue };
+        let section_end = section_end(section).min(u32::MAX as u64) as u32;
+
+        let measured = match index {
+            DIRECTORY_IMPORT => descriptor_table_size(image, &sections, virtual_address, SIZEOF_IMPORT_DESCRIPTOR),
+            DIRECTORY_DELAY_IMPORT => descriptor_table_size(image, &sections, virtual_address, SIZEOF_DELAY_IMPORT_DESCRIPTOR),
+            DIRECTORY_BASE_RELOCATION => base_relocation_size(image, &sections, virtual_address, section_end),
+            _ => None,
+        };
+        let new = measured.filter(|measured| *measured != 0).unwrap_or(size).min(section_end - virtual_address);
+        write(image, format!("DataDirectory[{}].Size", index), offset + 4, new)?;
+    }
+
+    let checksum = compute_checksum(image, optional_header_offset + OPTIONAL_HEADER_CHECKSUM);
+    write(image, "CheckSum".to_string(), optional_header_offset + OPTIONAL_HEADER_CHECKSUM, checksum)?;
+    Ok(fixups)
+}
+
+/// The PE image checksum as computed by `CheckSumMappedFile`, skipping the `CheckSum` field at
+/// `checksum_offset`
+pub fn compute_checksum(image: &[u8], checksum_offset: usize) -> u32 {
+    let mut checksum: u64 = 0;
+    for (index, word) in image.chunks(2).enumerate() {
+        if index * 2 == checksum_offset || index * 2 == checksum_offset + 2 {
+            continue;
+        }
+        let word = match word {
+            [low, high] => u16::from_le_bytes([*low, *high]),
+            [low] => *low as u16,
+            _ => 0,
+        };
+        checksum += word as u64;
+        checksum = (checksum & 0xFFFF) + (checksum >> 16);
+    }
+    checksum = (checksum & 0xFFFF) + (checksum >> 16);
+    (checksum as u32).wrapping_add(image.len() as u32)
+}
+
+/// End RVA of a section, in 64 bits since header values can add up past `u32::MAX`
+fn section_end(section: &SectionTable) -> u64 {
+    section.virtual_address as u64 + section.virtual_size.max(section.size_of_raw_data) as u64
+}
+
+fn containing_section(sections: &[SectionTable], rva: u32) -> Option<&SectionTable> {
+    sections.iter().find(|section| rva >= section.virtual_address && (rva as u64) < section_end(section))
+}
+
+/// Size of a descriptor table ending with an all zero descriptor, terminator included
+fn descriptor_table_size(image: &[u8], sections: &[SectionTable], rva: u32, descriptor_size: usize) -> Option<u32> {
+    let mut offset = rva_to_offset(sections, rva as usize).ok()?;
+    let mut size = 0;
+    loop {
+        let descriptor = image.get(offset..offset + descriptor_size)?;
+        size += descriptor_size;
+        if descriptor.iter().all(|byte| *byte == 0) {
+            return Some(size as u32);
+        }
+        offset += descriptor_size;
+    }
+}
+
+/// Size of the base relocation blocks, stopping at an empty block or the end of the section
+fn base_relocation_size(image: &[u8], sections: &[SectionTable], rva: u32, section_end: u32) -> Option<u32> {
+    let start = rva_to_offset(sections, rva as usize).ok()?;
+    let limit = (section_end - rva) as usize;
+    let mut size = 0;
+    while size + 8 <= limit {
+        let page_rva: u32 = image.pread_with(start + size, LE).ok()?;
+        let block_size: u32 = image.pread_with(start + size + 4, LE).ok()?;
+        if page_rva == 0 || block_size < 8 || size + block_size as usize > limit {
+            break;
+        }
+        size += block_size as usize;
+    }
+    Some(size as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_MEM_EXECUTE;
+
+    use super::*;
+    use crate::{PeBuilder, PeImage};
+
+    /// 64-bit image with code, an import descriptor table (one descriptor and its terminator) and
+    /// a single base relocation block, with every fixed field already consistent
+    fn build_image() -> (Vec<u8>, usize) {
+        let mut idata = vec![0u8; 0x100];
+        idata.pwrite_with(0x2080u32, 12, LE).unwrap();
+        idata.pwrite_with(0x2090u32, 16, LE).unwrap();
+        let mut reloc = vec![0u8; 0x20];
+        reloc.pwrite_with(0x1000u32, 0, LE).unwrap();
+        reloc.pwrite_with(12u32, 4, LE).unwrap();
+        reloc.pwrite_with(0xA010u16, 8, LE).unwrap();
+
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", vec![0xC3; 0x300], IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE).unwrap();
+        let idata = builder.add_section(".idata", idata, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let reloc = builder.add_section(".reloc", reloc, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(DIRECTORY_IMPORT, idata, 0x28).unwrap();
+        builder.set_data_directory(DIRECTORY_BASE_RELOCATION, reloc, 12).unwrap();
+        let image = builder.build().unwrap();
+        let optional_header_offset = PeImage::parse(image.clone()).unwrap().optional_header_offset();
+        (image, optional_header_offset)
+    }
+
+    #[test]
+    fn built_images_need_no_fixups() {
+        let (mut image, optional_header_offset) = build_image();
+        let checksum: u32 = image.pread_with(optional_header_offset + OPTIONAL_HEADER_CHECKSUM, LE).unwrap();
+        assert_ne!(checksum, 0);
+        assert_eq!(checksum, compute_checksum(&image, optional_header_offset + OPTIONAL_HEADER_CHECKSUM));
+        assert!(fix_headers(&mut image).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fixes_stale_sizes_and_checksum() {
+        let (original, optional_header_offset) = build_image();
+        let mut image = original.clone();
+        let directories = optional_header_offset + OPTIONAL_HEADER_DATA_DIRECTORIES_64;
+        image.pwrite_with(0x200u32, optional_header_offset + OPTIONAL_HEADER_SIZE_OF_CODE, LE).unwrap();
+        image.pwrite_with(0x1000u32, optional_header_offset + OPTIONAL_HEADER_SIZE_OF_IMAGE, LE).unwrap();
+        image.pwrite_with(0xFFFFu32, directories + DIRECTORY_IMPORT * SIZEOF_DATA_DIRECTORY + 4, LE).unwrap();
+        image.pwrite_with(0u32, directories + DIRECTORY_BASE_RELOCATION * SIZEOF_DATA_DIRECTORY + 4, LE).unwrap();
+        image.pwrite_with(0u32, optional_header_offset + OPTIONAL_HEADER_CHECKSUM, LE).unwrap();
+        let checksum: u32 = original.pread_with(optional_header_offset + OPTIONAL_HEADER_CHECKSUM, LE).unwrap();
+
+        let fixups = fix_headers(&mut image).unwrap();
+        let fields = fixups.iter().map(|fixup| (fixup.field.as_str(), fixup.old, fixup.new)).collect::<Vec<_>>();
+        assert_eq!(fields, [
+            ("SizeOfCode", 0x200, 0x400),
+            ("SizeOfImage", 0x1000, 0x4000),
+            ("DataDirectory[1].Size", 0xFFFF, 0x28),
+            ("DataDirectory[5].Size", 0, 12),
+            ("CheckSum", 0, checksum),
+        ]);
+        assert_eq!(image, original);
+        assert!(fix_headers(&mut image).unwrap().is_empty());
+    }
+
+    #[test]
+    fn checksum_skips_its_own_field_and_adds_the_length() {
+        assert_eq!(compute_checksum(&[1, 0, 2, 0, 0xFF], 0x100), 1 + 2 + 0xFF + 5);
+        assert_eq!(compute_checksum(&[1, 0, 2, 0, 0xAA, 0xBB, 0xCC, 0xDD], 4), 1 + 2 + 8);
+        // The carry folds back into the low word
+        assert_eq!(compute_checksum(&[0xFF, 0xFF, 0x02, 0x00], 0x100), 0x0002 + 4);
+    }
+}
@@ -0,0 +1,157 @@
+use std::ops::Deref;
+use std::path::Path;
+
+use goblin::pe::data_directories::DataDirectory;
+use goblin::pe::header::{CoffHeader, SIZEOF_COFF_HEADER, SIZEOF_PE_MAGIC};
+use goblin::pe::optional_header::{OptionalHeader, MAGIC_64};
+use goblin::pe::section_table::SectionTable;
+use memmap2::Mmap;
+
+use crate::{DebugInfo, Error, Exports, Imports, Resource, VersionInfo};
+
+#[derive(Debug)]
+enum ImageData {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for ImageData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ImageData::Owned(data) => data,
+            ImageData::Mapped(map) => map,
+        }
+    }
+}
+
+/// A PE image in the file layout together with its parsed headers and section table, so callers
+/// parse once and pass a single value around. The bytes are either owned or a read-only file
+/// mapping
+#[derive(Debug)]
+pub struct PeImage {
+    data: ImageData,
+    pe_pointer: u32,
+    coff_header: CoffHeader,
+    optional_header: OptionalHeader,
+    sections: Vec<SectionTable>,
+}
+
+impl PeImage {
+    pub fn parse(data: Vec<u8>) -> Result<Self, Error> {
+        Self::from_data(ImageData::Owned(data))
+    }
+
+    /// Maps the executable at `path` instead of reading it
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_data(ImageData::Mapped(crate::map_file(path)?))
+    }
+
+    fn from_data(data: ImageData) -> Result<Self, Error> {
+        let header = crate::parse_headers(&data)?;
+        let optional_header = crate::get_optional_headers(&header)?;
+        let sections = crate::get_sections(&header, &data)?;
+        let pe_pointer = header.dos_header.pe_pointer;
+        let coff_header = header.coff_header;
+        Ok(PeImage { data, pe_pointer, coff_header, optional_header, sections })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The bytes as an owned buffer, mapped images are copied
+    pub fn into_data(self) -> Vec<u8> {
+        match self.data {
+            ImageData::Owned(data) => data,
+            ImageData::Mapped(map) => map.to_vec(),
+        }
+    }
+
+    pub fn coff_header(&self) -> &CoffHeader {
+        &self.coff_header
+    }
+
+    pub fn optional_header(&self) -> &OptionalHeader {
+        &self.optional_header
+    }
+
+    pub fn sections(&self) -> &[SectionTable] {
+        &self.sections
+    }
+
+    pub fn is_64(&self) -> bool {
+        self.optional_header.standard_fields.magic == MAGIC_64
+    }
+
+    pub fn image_base(&self) -> u64 {
+        self.optional_header.windows_fields.image_base
+    }
+
+    pub fn entry_point(&self) -> u32 {
+        self.optional_header.standard_fields.address_of_entry_point as u32
+    }
+
+    /// File offset of the optional header, for patching fields goblin does not write back
+    pub fn optional_header_offset(&self) -> usize {
+        self.pe_pointer as usize + SIZEOF_PE_MAGIC + SIZEOF_COFF_HEADER
+    }
+
+    /// The data directory at `index`, `None` when it is absent or empty
+    pub fn data_directory(&self, index: usize) -> Option<DataDirectory> {
+        self.optional_header.data_directories.data_directories
+            .get(index)
+            .copied()
+            .flatten()
+            .map(|(_, directory)| directory)
+            .filter(|directory| directory.virtual_address != 0)
+    }
+
+    pub fn section_by_name(&self, name: &str) -> Option<&SectionTable> {
+        self.sections.iter().find(|section| section.name().is_ok_and(|section_name| section_name == name))
+    }
+
+    /// Raw data of a section, cut at the end of the file
+    pub fn section_data(&self, section: &SectionTable) -> &[u8] {
+        let start = (section.pointer_to_raw_data as usize).min(self.data.len());
+        let end = (start + section.size_of_raw_data as usize).min(self.data.len());
+        &self.data[start..end]
+    }
+
+    pub fn rva_to_offset(&self, rva: usize) -> Result<usize, Error> {
+        crate::rva_to_offset(&self.sections, rva)
+    }
+
+    /// Virtual address of a file offset, relative to `ImageBase`
+    pub fn resolve_symbol(&self, offset: usize) -> Result<usize, Error> {
+        crate::resolve_symbol(self.image_base() as usize, &self.sections, offset)
+    }
+
+    pub fn imports(&self) -> Result<Option<Imports<'_>>, Error> {
+        crate::get_imports(&self.data, &self.optional_header, &self.sections)
+    }
+
+    pub fn exports(&self) -> Result<Option<Exports<'_>>, Error> {
+        crate::get_exports(&self.data, &self.optional_header, &self.sections)
+    }
+
+    pub fn debug_info(&self) -> Result<Option<DebugInfo>, Error> {
+        crate::get_debug_info(&self.data, &self.optional_header, &self.sections)
+    }
+
+    pub fn resources(&self) -> Result<Option<Vec<Resource>>, Error> {
+        crate::get_resources(&self.data, &self.optional_header, &self.sections)
+    }
+
+    /// Contents of a resource returned by [`PeImage::resources`]
+    pub fn resource_data(&self, resource: &Resource) -> Result<&[u8], Error> {
+        let offset = self.rva_to_offset(resource.rva as usize)?;
+        self.data.get(offset..offset + resource.size as usize)
+            .ok_or(Error::RvaNotInSection(resource.rva as usize))
+    }
+
+    pub fn version_info(&self) -> Result<Option<VersionInfo>, Error> {
+        crate::get_version_info(&self.data, &self.optional_header, &self.sections)
+    }
+}
@@ -0,0 +1,315 @@
+use goblin::container;
+use goblin::pe::optional_header::OptionalHeader;
+use goblin::pe::section_table::SectionTable;
+use scroll::{Pread, LE};
+
+use crate::{rva_to_offset, Error};
+
+const SIZEOF_IMPORT_DESCRIPTOR: usize = 20;
+const SIZEOF_DELAY_IMPORT_DESCRIPTOR: usize = 32;
+const SIZEOF_BOUND_IMPORT_DESCRIPTOR: usize = 8;
+// Delay-load descriptors built by old toolchains store VAs instead of RVAs, this bit tells them apart
+const DELAY_ATTRIBUTE_RVA_BASED: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct ImportedFunction<'a> {
+    pub name: Option<&'a str>,
+    pub hint: Option<u16>,
+    pub ordinal: Option<u16>,
+    pub iat_rva: u32,
+    pub iat_va: u64,
+    /// Raw value found in the IAT slot, an RVA on disk or the resolved pointer in a memory dump
+    pub thunk: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedLibrary<'a> {
+    pub name: &'a str,
+    pub time_date_stamp: u32,
+    pub functions: Vec<ImportedFunction<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BoundForwarder<'a> {
+    pub name: &'a str,
+    pub time_date_stamp: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BoundImport<'a> {
+    pub name: &'a str,
+    pub time_date_stamp: u32,
+    pub forwarders: Vec<BoundForwarder<'a>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Imports<'a> {
+    pub libraries: Vec<ImportedLibrary<'a>>,
+    pub delay_load: Vec<ImportedLibrary<'a>>,
+    pub bound: Vec<BoundImport<'a>>,
+}
+
+impl Imports<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.libraries.is_empty() && self.delay_load.is_empty() && self.bound.is_empty()
+    }
+}
+
+pub fn get_imports<'a>(bytes: &'a [u8],
+                       optional_header: &OptionalHeader,
+                       sections: &[SectionTable]) -> Result<Option<Imports<'a>>, Error> {
+    let is_64 = optional_header.container()? == container::Container::Big;
+    let image_base = optional_header.windows_fields.image_base;
+    let data_directories = &optional_header.data_directories;
+    let mut imports = Imports::default();
+
+    if let Some(import_table) = data_directories.get_import_table().filter(|table| table.virtual_address != 0) {
+        let mut offset = rva_to_offset(sections, import_table.virtual_address as usize)?;
+        loop {
+            let ilt_rva: u32 = bytes.pread_with(offset, LE)?;
+            let time_date_stamp: u32 = bytes.pread_with(offset + 4, LE)?;
+            let name_rva: u32 = bytes.pread_with(offset + 12, LE)?;
+            let iat_rva: u32 = bytes.pread_with(offset + 16, LE)?;
+            if name_rva == 0 && iat_rva == 0 {
+                break;
+            }
+            // Without an import lookup table the IAT doubles as one, that only holds on disk
+            let lookup_rva = if ilt_rva != 0 { ilt_rva } else { iat_rva };
+            imports.libraries.push(ImportedLibrary {
+                name: read_name(bytes, sections, name_rva)?,
+                time_date_stamp,
+                functions: parse_thunks(bytes, sections, image_base, is_64, lookup_rva, iat_rva)?,
+            });
+            offset += SIZEOF_IMPORT_DESCRIPTOR;
+        }
+    }
+
+    if let Some(delay_table) = data_directories.get_delay_import_descriptor().filter(|table| table.virtual_address != 0) {
+        let mut offset = rva_to_offset(sections, delay_table.virtual_address as usize)?;
+        loop {
+            let attributes: u32 = bytes.pread_with(offset, LE)?;
+            let mut name_rva: u32 = bytes.pread_with(offset + 4, LE)?;
+            let mut iat_rva: u32 = bytes.pread_with(offset + 12, LE)?;
+            let mut int_rva: u32 = bytes.pread_with(offset + 16, LE)?;
+            let time_date_stamp: u32 = bytes.pread_with(offset + 28, LE)?;
+            if name_rva == 0 {
+                break;
+            }
+            if attributes & DELAY_ATTRIBUTE_RVA_BASED == 0 {
+                // Zero stays zero, it means the table is absent rather than a VA to convert
+                for rva in [&mut name_rva, &mut iat_rva, &mut int_rva].into_iter().filter(|rva| **rva != 0) {
+                    *rva = rva.wrapping_sub(image_base as u32);
+                }
+            }
+            imports.delay_load.push(ImportedLibrary {
+                name: read_name(bytes, sections, name_rva)?,
+                time_date_stamp,
+                functions: parse_thunks(bytes, sections, image_base, is_64, int_rva, iat_rva)?,
+            });
+            offset += SIZEOF_DELAY_IMPORT_DESCRIPTOR;
+        }
+    }
+
+    if let Some(bound_table) = data_directories.get_bound_import_table().filter(|table| table.virtual_address != 0) {
+        let table_offset = rva_to_offset(sections, bound_table.virtual_address as usize)?;
+        let mut offset = table_offset;
+        loop {
+            let time_date_stamp: u32 = bytes.pread_with(offset, LE)?;
+            let name_offset: u16 = bytes.pread_with(offset + 4, LE)?;
+            let forwarder_count: u16 = bytes.pread_with(offset + 6, LE)?;
+            if time_date_stamp == 0 && name_offset == 0 {
+                break;
+            }
+            offset += SIZEOF_BOUND_IMPORT_DESCRIPTOR;
+
+            // Forwarder references share the descriptor layout and follow their module directly
+            let mut forwarders = Vec::with_capacity(forwarder_count as usize);
+            for _ in 0..forwarder_count {
+                let forwarder_time_date_stamp: u32 = bytes.pread_with(offset, LE)?;
+                let forwarder_name_offset: u16 = bytes.pread_with(offset + 4, LE)?;
+                forwarders.push(BoundForwarder {
+                    name: bytes.pread(table_offset + forwarder_name_offset as usize)?,
+                    time_date_stamp: forwarder_time_date_stamp,
+                });
+                offset += SIZEOF_BOUND_IMPORT_DESCRIPTOR;
+            }
+
+            imports.bound.push(BoundImport {
+                name: bytes.pread(table_offset + name_offset as usize)?,
+                time_date_stamp,
+                forwarders,
+            });
+        }
+    }
+
+    match imports.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(imports)),
+    }
+}
+
+fn read_name<'a>(bytes: &'a [u8], sections: &[SectionTable], rva: u32) -> Result<&'a str, Error> {
+    Ok(bytes.pread(rva_to_offset(sections, rva as usize)?)?)
+}
+
+fn read_thunk(bytes: &[u8], offset: usize, is_64: bool) -> Result<u64, Error> {
+    match is_64 {
+        true => Ok(bytes.pread_with::<u64>(offset, LE)?),
+        false => Ok(bytes.pread_with::<u32>(offset, LE)? as u64),
+    }
+}
+
+fn parse_thunks<'a>(bytes: &'a [u8],
+                    sections: &[SectionTable],
+                    image_base: u64,
+                    is_64: bool,
+                    lookup_rva: u32,
+                    iat_rva: u32) -> Result<Vec<ImportedFunction<'a>>, Error> {
+    // No lookup table to walk, reading at RVA zero would parse the headers as thunks
+    if lookup_rva == 0 {
+        return Ok(vec![]);
+    }
+    let thunk_size: usize = if is_64 { 8 } else { 4 };
+    let ordinal_flag: u64 = if is_64 { 1 << 63 } else { 1 << 31 };
+    let lookup_offset = rva_to_offset(sections, lookup_rva as usize)?;
+    // A dump may not have the IAT backed by raw data, in that case the thunk is left as zero
+    let iat_offset = rva_to_offset(sections, iat_rva as usize).ok();
+
+    let mut functions = vec![];
+    for index in 0.. {
+        let lookup = read_thunk(bytes, lookup_offset + index * thunk_size, is_64)?;
+        if lookup == 0 {
+            break;
+        }
+        let slot_rva = iat_rva + (index * thunk_size) as u32;
+        let thunk = match iat_offset {
+            Some(iat_offset) => read_thunk(bytes, iat_offset + index * thunk_size, is_64)?,
+            None => 0,
+        };
+
+        let (name, hint, ordinal) = if lookup & ordinal_flag != 0 {
+            (None, None, Some(lookup as u16))
+        } else {
+            let hint_offset = rva_to_offset(sections, (lookup & 0x7FFF_FFFF) as usize)?;
+            let hint: u16 = bytes.pread_with(hint_offset, LE)?;
+            (Some(bytes.pread::<&str>(hint_offset + 2)?), Some(hint), None)
+        };
+
+        functions.push(ImportedFunction {
+            name,
+            hint,
+            ordinal,
+            iat_rva: slot_rva,
+            iat_va: image_base + slot_rva as u64,
+            thunk,
+        });
+    }
+    Ok(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_INITIALIZED_DATA;
+    use scroll::{Pwrite, LE};
+
+    use crate::{PeBuilder, PeImage};
+
+    const IMAGE_BASE: u64 = 0x1_4000_0000;
+
+    /// `.idata` at 0x1000 with one import, one delay-load and one bound module
+    fn build_image() -> PeImage {
+        let mut idata = vec![0u8; 0x500];
+        // Import descriptor and its terminator
+        for (offset, value) in [(0x00, 0x1100u32), (0x0C, 0x1300), (0x10, 0x1200)] {
+            idata.pwrite_with(value, offset, LE).unwrap();
+        }
+        // RVA based delay-load descriptor and its terminator
+        for (offset, value) in [(0x40, 1u32), (0x44, 0x1310), (0x4C, 0x1220), (0x50, 0x1120), (0x5C, 0x1234)] {
+            idata.pwrite_with(value, offset, LE).unwrap();
+        }
+        // Bound module with one forwarder, then the terminator and the names
+        idata.pwrite_with(0x5EEDu32, 0xC0, LE).unwrap();
+        idata.pwrite_with(0x18u16, 0xC4, LE).unwrap();
+        idata.pwrite_with(1u16, 0xC6, LE).unwrap();
+        idata.pwrite_with(7u32, 0xC8, LE).unwrap();
+        idata.pwrite_with(0x25u16, 0xCC, LE).unwrap();
+        idata[0xD8..0xE5].copy_from_slice(b"KERNEL32.dll\0");
+        idata[0xE5..0xEF].copy_from_slice(b"ntdll.dll\0");
+        // Lookup tables and IATs, by name and by ordinal
+        for offset in [0x100, 0x200] {
+            idata.pwrite_with(0x1400u64, offset, LE).unwrap();
+            idata.pwrite_with((1u64 << 63) | 16, offset + 8, LE).unwrap();
+        }
+        idata.pwrite_with(0x1420u64, 0x120, LE).unwrap();
+        idata.pwrite_with(IMAGE_BASE + 0x5000, 0x220, LE).unwrap();
+        idata[0x300..0x30D].copy_from_slice(b"KERNEL32.dll\0");
+        idata[0x310..0x31B].copy_from_slice(b"USER32.dll\0");
+        idata.pwrite_with(0x12u16, 0x400, LE).unwrap();
+        idata[0x402..0x40E].copy_from_slice(b"ExitProcess\0");
+        idata.pwrite_with(3u16, 0x420, LE).unwrap();
+        idata[0x422..0x42E].copy_from_slice(b"MessageBoxA\0");
+
+        let mut builder = PeBuilder::new(true, IMAGE_BASE).unwrap();
+        builder.add_section(".idata", idata, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(1, 0x1000, 0x28).unwrap();
+        builder.set_data_directory(11, 0x10C0, 0x2F).unwrap();
+        builder.set_data_directory(13, 0x1040, 0x40).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reads_imports_delay_load_and_bound_imports() {
+        let image = build_image();
+        let imports = image.imports().unwrap().unwrap();
+
+        assert_eq!(imports.libraries.len(), 1);
+        let kernel32 = &imports.libraries[0];
+        assert_eq!(kernel32.name, "KERNEL32.dll");
+        assert_eq!(kernel32.functions.len(), 2);
+        assert_eq!(kernel32.functions[0].name, Some("ExitProcess"));
+        assert_eq!(kernel32.functions[0].hint, Some(0x12));
+        assert_eq!(kernel32.functions[0].iat_rva, 0x1200);
+        assert_eq!(kernel32.functions[0].iat_va, IMAGE_BASE + 0x1200);
+        assert_eq!(kernel32.functions[0].thunk, 0x1400);
+        assert_eq!((kernel32.functions[1].name, kernel32.functions[1].ordinal), (None, Some(16)));
+        assert_eq!(kernel32.functions[1].iat_rva, 0x1208);
+
+        assert_eq!(imports.delay_load.len(), 1);
+        let user32 = &imports.delay_load[0];
+        assert_eq!((user32.name, user32.time_date_stamp), ("USER32.dll", 0x1234));
+        assert_eq!(user32.functions.len(), 1);
+        assert_eq!(user32.functions[0].name, Some("MessageBoxA"));
+        assert_eq!(user32.functions[0].iat_rva, 0x1220);
+        assert_eq!(user32.functions[0].thunk, IMAGE_BASE + 0x5000);
+
+        assert_eq!(imports.bound.len(), 1);
+        assert_eq!((imports.bound[0].name, imports.bound[0].time_date_stamp), ("KERNEL32.dll", 0x5EED));
+        assert_eq!(imports.bound[0].forwarders.len(), 1);
+        assert_eq!((imports.bound[0].forwarders[0].name, imports.bound[0].forwarders[0].time_date_stamp), ("ntdll.dll", 7));
+    }
+
+    #[test]
+    fn converts_va_based_delay_load_descriptors() {
+        let image = build_image();
+        let mut builder = PeBuilder::from_image(image.data()).unwrap();
+        // Old toolchains: no attributes and VAs everywhere
+        builder.write_at_rva(0x1040, &0u32.to_le_bytes()).unwrap();
+        for (rva, value) in [(0x1044, 0x1310u64), (0x104C, 0x1220), (0x1050, 0x1120)] {
+            builder.write_at_rva(rva, &((IMAGE_BASE + value) as u32).to_le_bytes()).unwrap();
+        }
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+        let imports = image.imports().unwrap().unwrap();
+
+        assert_eq!(imports.delay_load[0].name, "USER32.dll");
+        assert_eq!(imports.delay_load[0].functions[0].name, Some("MessageBoxA"));
+        assert_eq!(imports.delay_load[0].functions[0].iat_rva, 0x1220);
+    }
+
+    #[test]
+    fn image_without_imports() {
+        let mut builder = PeBuilder::new(false, 0x40_0000).unwrap();
+        builder.add_section(".rdata", vec![0; 0x10], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+        assert!(image.imports().unwrap().is_none());
+    }
+}
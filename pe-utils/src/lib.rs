@@ -1,18 +1,60 @@
-use goblin::container;
-use goblin::pe::{import, options};
 use goblin::pe::header::Header;
-use goblin::pe::import::ImportData;
 use goblin::pe::optional_header::OptionalHeader;
 use goblin::pe::section_table::SectionTable;
 
+mod builder;
+mod debug;
+mod exports;
+mod fixups;
+mod image;
+mod mapped;
+mod packer;
+mod imports;
+mod relocations;
+mod resources;
+
+pub use builder::{BuilderSection, PeBuilder};
+pub use debug::{
+    get_debug_info, CodeView, DebugEntry, DebugInfo, Pogo, PogoEntry, IMAGE_DEBUG_TYPE_CODEVIEW, IMAGE_DEBUG_TYPE_POGO,
+    IMAGE_DEBUG_TYPE_REPRO, IMAGE_DEBUG_TYPE_VC_FEATURE,
+};
+pub use exports::{get_exports, ExportedFunction, Exports};
+pub use fixups::{compute_checksum, fix_headers, Fixup};
+pub use image::PeImage;
+pub use mapped::{map_file, map_file_mut};
+pub use packer::{analyze_packing, Detection, PackerAnalysis, Protector, SectionEntropy, HIGH_ENTROPY};
+pub use imports::{get_imports, BoundForwarder, BoundImport, ImportedFunction, ImportedLibrary, Imports};
+pub use relocations::{
+    parse_base_relocations, BaseRelocation, BaseRelocationBlock, IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_DIR64,
+    IMAGE_REL_BASED_HIGH, IMAGE_REL_BASED_HIGHADJ, IMAGE_REL_BASED_HIGHLOW, IMAGE_REL_BASED_LOW,
+};
+pub use resources::{
+    get_resources, get_version_info, parse_version_info, Resource, ResourceId, VersionInfo, RT_GROUP_ICON, RT_ICON,
+    RT_MANIFEST, RT_STRING, RT_VERSION,
+};
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Goblin Error: {0}")]
     Goblin(#[from] goblin::error::Error),
+    #[error("Scroll Error: {0}")]
+    Scroll(#[from] scroll::Error),
+    #[error("IO Error: {0}")]
+    Io(#[from] std::io::Error),
     #[error("Optional header missing")]
     NoOptionalHeader,
     #[error("Offset: {0} not found in any section")]
     NotInSection(usize),
+    #[error("RVA: {0:#X} not found in any section")]
+    RvaNotInSection(usize),
+    #[error("Section not found: {0}")]
+    SectionNotFound(String),
+    #[error("Section {0} would overlap the next section")]
+    SectionOverlap(String),
+    #[error("No room for another section header before the first section")]
+    NoRoomForSection,
+    #[error("Data directory {0} not present in the optional header")]
+    NoDataDirectory(usize),
 }
 
 pub fn take_hint_bytes(bytes: &[u8]) -> Option<&[u8; 16]> {
@@ -45,7 +87,7 @@ impl MemAlignedAddress<Self> for u64 {
     }
 }
 
-pub fn parse_headers(dump: &[u8]) -> Result<Header, Error> {
+pub fn parse_headers(dump: &[u8]) -> Result<Header<'_>, Error> {
     let result = if let Some(hint_bytes) = take_hint_bytes(dump) {
         match goblin::peek_bytes(hint_bytes)? {
             goblin::Hint::PE => Ok(Header::parse(dump)?),
@@ -79,56 +121,53 @@ pub fn resolve_symbol(image_base: usize,
                       sections: &[SectionTable],
                       addr: usize) -> Result<usize, Error> {
     for section in sections {
-        if (addr > section.pointer_to_raw_data as usize) &&
-            (addr < (section.pointer_to_raw_data + section.size_of_raw_data) as usize) {
+        if (addr >= section.pointer_to_raw_data as usize) &&
+            (addr < section.pointer_to_raw_data as usize + section.size_of_raw_data as usize) {
             return Ok(image_base + (section.virtual_address - section.pointer_to_raw_data) as usize + addr);
         }
     }
     Err(Error::NotInSection(addr))
 }
 
-pub fn get_imports<'a>(bytes: &'a [u8], optional_header: &OptionalHeader, sections: &[SectionTable]) -> Result<Option<ImportData<'a>>, Error> {
-    let opts = &options::ParseOptions::default();
-    let file_alignment = optional_header.windows_fields.file_alignment;
-    let is_64 = optional_header.container()? == container::Container::Big;
-    let mut _imports = vec![];
-    let mut import_data = None;
-
-    if let Some(&import_table) = optional_header.data_directories.get_import_table() {
-        let id = if is_64 {
-            ImportData::parse_with_opts::<u64>(
-                bytes,
-                import_table,
-                &sections,
-                file_alignment,
-                opts,
-            )?
-        } else {
-            ImportData::parse_with_opts::<u32>(
-                bytes,
-                import_table,
-                &sections,
-                file_alignment,
-                opts,
-            )?
-        };
-
-        if is_64 {
-            _imports = import::Import::parse::<u64>(bytes, &id, &sections)?
-        } else {
-            _imports = import::Import::parse::<u32>(bytes, &id, &sections)?
+pub fn rva_to_offset(sections: &[SectionTable], rva: usize) -> Result<usize, Error> {
+    for section in sections {
+        let virt_start = section.virtual_address as usize;
+        let virt_end = virt_start + section.virtual_size.max(section.size_of_raw_data) as usize;
+        if rva >= virt_start && rva < virt_end {
+            let delta = rva - virt_start;
+            if delta >= section.size_of_raw_data as usize {
+                break;
+            }
+            return Ok(section.pointer_to_raw_data as usize + delta);
         }
-
-        let mut libraries = id
-            .import_data
-            .iter()
-            .map(|data| data.name)
-            .collect::<Vec<&'a str>>();
-        libraries.sort();
-        libraries.dedup();
-
-        import_data = Some(id);
     }
+    // Anything before the first section lives in the headers, which are mapped 1:1
+    let headers_end = sections.iter()
+        .map(|section| section.pointer_to_raw_data)
+        .filter(|pointer| *pointer != 0)
+        .min()
+        .unwrap_or(0) as usize;
+    if rva < headers_end {
+        return Ok(rva);
+    }
+    Err(Error::RvaNotInSection(rva))
+}
 
-    Ok(import_data)
-}
\ No newline at end of file
+/// Shannon entropy of `bytes` in bits per byte, from 0.0 (constant) to 8.0 (uniformly random)
+pub fn entropy(bytes: &[u8]) -> f32 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for byte in bytes {
+        counts[*byte as usize] += 1;
+    }
+    let length = bytes.len() as f32;
+    counts.iter()
+        .filter(|count| **count != 0)
+        .map(|count| {
+            let probability = *count as f32 / length;
+            -probability * probability.log2()
+        })
+        .sum()
+}
@@ -0,0 +1,22 @@
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::Error;
+
+/// Maps a file read-only, multi-gigabyte executables and dumps are paged in on demand instead of
+/// being read into memory up front
+pub fn map_file<P: AsRef<Path>>(path: P) -> Result<Mmap, Error> {
+    let file = File::open(path)?;
+    // Safety: the mapping is only valid as long as nobody truncates the file behind our back,
+    // which is the usual contract for tools working on files they were pointed at
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+/// Maps a file for in-place editing, changes are written back when the map is flushed or dropped
+pub fn map_file_mut<P: AsRef<Path>>(path: P) -> Result<MmapMut, Error> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    // Safety: see `map_file`
+    Ok(unsafe { MmapMut::map_mut(&file)? })
+}
@@ -0,0 +1,149 @@
+use goblin::pe::section_table::{SectionTable, IMAGE_SCN_CNT_CODE, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_WRITE};
+
+use crate::PeImage;
+
+/// Entropy above which section data is most likely compressed or encrypted, plain x86 code sits
+/// around 6.0 to 6.7
+pub const HIGH_ENTROPY: f32 = 7.2;
+
+const UPX_MAGIC: &[u8] = b"UPX!";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protector {
+    Themida,
+    VMProtect,
+    Enigma,
+    Denuvo,
+    Upx,
+}
+
+impl Protector {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protector::Themida => "Themida/WinLicense",
+            Protector::VMProtect => "VMProtect",
+            Protector::Enigma => "Enigma Protector",
+            Protector::Denuvo => "Denuvo",
+            Protector::Upx => "UPX",
+        }
+    }
+
+    /// The protector known to leave a section with this name, compared without the padding
+    pub fn from_section_name(name: &str) -> Option<Protector> {
+        SECTION_NAMES.iter()
+            .find(|(section_name, _)| *section_name == name.trim_end_matches('\0'))
+            .map(|(_, protector)| *protector)
+    }
+}
+
+// Section names the protectors leave behind, compared without the trailing padding
+const SECTION_NAMES: [(&str, Protector); 14] = [
+    (".themida", Protector::Themida),
+    (".winlice", Protector::Themida),
+    (".boot", Protector::Themida),
+    (".vmp0", Protector::VMProtect),
+    (".vmp1", Protector::VMProtect),
+    (".vmp2", Protector::VMProtect),
+    (".enigma1", Protector::Enigma),
+    (".enigma2", Protector::Enigma),
+    (".arch", Protector::Denuvo),
+    (".srdata", Protector::Denuvo),
+    (".xpdata", Protector::Denuvo),
+    ("UPX0", Protector::Upx),
+    ("UPX1", Protector::Upx),
+    ("UPX2", Protector::Upx),
+];
+
+#[derive(Debug, Clone)]
+pub struct SectionEntropy {
+    pub name: String,
+    pub virtual_address: u32,
+    pub size_of_raw_data: u32,
+    pub entropy: f32,
+    pub executable: bool,
+}
+
+impl SectionEntropy {
+    pub fn is_high(&self) -> bool {
+        self.entropy >= HIGH_ENTROPY
+    }
+}
+
+/// A protector and what gave it away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub protector: Protector,
+    pub evidence: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackerAnalysis {
+    pub sections: Vec<SectionEntropy>,
+    pub detections: Vec<Detection>,
+    /// `AddressOfEntryPoint` lies outside the code sections or in a writable one, typical for
+    /// unpacker stubs
+    pub suspicious_entry_point: bool,
+}
+
+impl PackerAnalysis {
+    pub fn high_entropy_sections(&self) -> impl Iterator<Item = &SectionEntropy> {
+        self.sections.iter().filter(|section| section.is_high())
+    }
+
+    /// A protector was recognised, or executable code is too random to be plain code
+    pub fn is_packed(&self) -> bool {
+        !self.detections.is_empty() || self.high_entropy_sections().any(|section| section.executable)
+    }
+
+    /// Records a detection unless the protector was already recognised, the first evidence is kept
+    pub fn add_detection(&mut self, detection: Detection) {
+        if !self.detections.iter().any(|known| known.protector == detection.protector) {
+            self.detections.push(detection);
+        }
+    }
+}
+
+/// Computes the entropy of every section and looks for the marks left by common packers and
+/// protectors: section names, the UPX header magic and the entry point placement
+pub fn analyze_packing(image: &PeImage) -> PackerAnalysis {
+    let sections = image.sections().iter()
+        .map(|section| SectionEntropy {
+            name: section_name(section),
+            virtual_address: section.virtual_address,
+            size_of_raw_data: section.size_of_raw_data,
+            entropy: crate::entropy(image.section_data(section)),
+            executable: section.characteristics & (IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_CNT_CODE) != 0,
+        })
+        .collect::<Vec<SectionEntropy>>();
+
+    let mut detections: Vec<Detection> = vec![];
+    for section in &sections {
+        if let Some(protector) = Protector::from_section_name(&section.name) {
+            detections.push(Detection { protector, evidence: format!("section {}", section.name) });
+        }
+    }
+    let size_of_headers = image.optional_header().windows_fields.size_of_headers as usize;
+    let headers = &image.data()[0..size_of_headers.min(image.data().len())];
+    if headers.windows(UPX_MAGIC.len()).any(|window| window == UPX_MAGIC) {
+        detections.push(Detection { protector: Protector::Upx, evidence: "UPX! magic in the headers".to_string() });
+    }
+
+    let entry_point = image.entry_point();
+    let suspicious_entry_point = entry_point != 0 && !image.sections().iter().any(|section| {
+        let size = section.virtual_size.max(section.size_of_raw_data);
+        section.characteristics & (IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_CNT_CODE) != 0
+            && section.characteristics & IMAGE_SCN_MEM_WRITE == 0
+            && entry_point >= section.virtual_address
+            && (entry_point as u64) < section.virtual_address as u64 + size as u64
+    });
+
+    let mut analysis = PackerAnalysis { sections, detections: vec![], suspicious_entry_point };
+    for detection in detections {
+        analysis.add_detection(detection);
+    }
+    analysis
+}
+
+fn section_name(section: &SectionTable) -> String {
+    String::from_utf8_lossy(&section.name).trim_end_matches('\0').to_string()
+}
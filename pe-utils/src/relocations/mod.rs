@@ -0,0 +1,125 @@
+use scroll::{Pread, LE};
+
+use crate::Error;
+
+pub const IMAGE_REL_BASED_ABSOLUTE: u8 = 0;
+pub const IMAGE_REL_BASED_HIGH: u8 = 1;
+pub const IMAGE_REL_BASED_LOW: u8 = 2;
+pub const IMAGE_REL_BASED_HIGHLOW: u8 = 3;
+pub const IMAGE_REL_BASED_HIGHADJ: u8 = 4;
+pub const IMAGE_REL_BASED_DIR64: u8 = 10;
+
+const SIZEOF_BASE_RELOCATION_BLOCK: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BaseRelocation {
+    pub kind: u8,
+    pub offset: u16,
+    /// Only used by `IMAGE_REL_BASED_HIGHADJ`, which carries the low half in the following entry
+    pub parameter: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct BaseRelocationBlock {
+    pub page_rva: u32,
+    pub size: u32,
+    pub entries: Vec<BaseRelocation>,
+}
+
+/// Parses the blocks of a base relocation directory, `bytes` being the directory contents
+pub fn parse_base_relocations(bytes: &[u8]) -> Result<Vec<BaseRelocationBlock>, Error> {
+    let mut blocks = vec![];
+    let mut offset = 0;
+    while offset + SIZEOF_BASE_RELOCATION_BLOCK <= bytes.len() {
+        let page_rva: u32 = bytes.pread_with(offset, LE)?;
+        let size: u32 = bytes.pread_with(offset + 4, LE)?;
+        if size < SIZEOF_BASE_RELOCATION_BLOCK as u32 {
+            break;
+        }
+
+        let mut entries = vec![];
+        let mut entry_offset = offset + SIZEOF_BASE_RELOCATION_BLOCK;
+        let block_end = (offset + size as usize).min(bytes.len());
+        while entry_offset + 2 <= block_end {
+            let entry: u16 = bytes.pread_with(entry_offset, LE)?;
+            entry_offset += 2;
+            let kind = (entry >> 12) as u8;
+            let mut parameter = 0;
+            if kind == IMAGE_REL_BASED_HIGHADJ && entry_offset + 2 <= block_end {
+                parameter = bytes.pread_with(entry_offset, LE)?;
+                entry_offset += 2;
+            }
+            entries.push(BaseRelocation {
+                kind,
+                offset: entry & 0x0FFF,
+                parameter,
+            });
+        }
+
+        blocks.push(BaseRelocationBlock { page_rva, size, entries });
+        offset += size as usize;
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_INITIALIZED_DATA;
+    use scroll::Pwrite;
+
+    use super::*;
+    use crate::{PeBuilder, PeImage};
+
+    fn block(page_rva: u32, entries: &[u16]) -> Vec<u8> {
+        let size = SIZEOF_BASE_RELOCATION_BLOCK + entries.len() * 2;
+        let mut block = vec![0u8; size];
+        block.pwrite_with(page_rva, 0, LE).unwrap();
+        block.pwrite_with(size as u32, 4, LE).unwrap();
+        for (index, entry) in entries.iter().enumerate() {
+            block.pwrite_with(*entry, SIZEOF_BASE_RELOCATION_BLOCK + index * 2, LE).unwrap();
+        }
+        block
+    }
+
+    #[test]
+    fn parses_the_relocation_directory_of_an_image() {
+        let mut reloc = block(0x1000, &[0xA010, 0xA018, 0x0000]);
+        reloc.extend(block(0x2000, &[0x3004, 0x4008, 0x1234, 0x2FFF]));
+        let size = reloc.len() as u32;
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        let rva = builder.add_section(".reloc", reloc, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(5, rva, size).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        let directory = image.data_directory(5).unwrap();
+        let start = image.rva_to_offset(directory.virtual_address as usize).unwrap();
+        let blocks = parse_base_relocations(&image.data()[start..start + directory.size as usize]).unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].page_rva, blocks[0].size), (0x1000, 14));
+        let kinds = blocks[0].entries.iter().map(|entry| (entry.kind, entry.offset)).collect::<Vec<_>>();
+        assert_eq!(kinds, [(IMAGE_REL_BASED_DIR64, 0x10), (IMAGE_REL_BASED_DIR64, 0x18), (IMAGE_REL_BASED_ABSOLUTE, 0)]);
+        // HIGHADJ takes the next entry as its parameter
+        let entries = &blocks[1].entries;
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].kind, entries[0].offset), (IMAGE_REL_BASED_HIGHLOW, 4));
+        assert_eq!((entries[1].kind, entries[1].offset, entries[1].parameter), (IMAGE_REL_BASED_HIGHADJ, 8, 0x1234));
+        assert_eq!((entries[2].kind, entries[2].offset), (IMAGE_REL_BASED_LOW, 0xFFF));
+    }
+
+    #[test]
+    fn stops_at_truncated_or_empty_blocks() {
+        let mut bytes = block(0x1000, &[0x3000, 0x3004]);
+        // Declares more entries than the directory holds
+        bytes.extend(&block(0x2000, &[0x3000, 0x3004, 0x3008])[0..12]);
+        let blocks = parse_base_relocations(&bytes).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].entries.len(), 2);
+
+        let mut bytes = block(0x1000, &[0x3000]);
+        bytes.extend([0; 8]);
+        bytes.extend(block(0x2000, &[0x3000]));
+        assert_eq!(parse_base_relocations(&bytes).unwrap().len(), 1);
+        assert!(parse_base_relocations(&[1, 2, 3]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashSet;
+
+use goblin::pe::optional_header::OptionalHeader;
+use goblin::pe::section_table::SectionTable;
+use scroll::{Pread, LE};
+
+use crate::Error;
+
+pub const RT_ICON: u16 = 3;
+pub const RT_STRING: u16 = 6;
+pub const RT_GROUP_ICON: u16 = 14;
+pub const RT_VERSION: u16 = 16;
+pub const RT_MANIFEST: u16 = 24;
+
+const DATA_DIRECTORY_RESOURCE: usize = 2;
+const SIZEOF_RESOURCE_DIRECTORY: usize = 16;
+const SIZEOF_RESOURCE_DIRECTORY_ENTRY: usize = 8;
+const RESOURCE_HIGH_BIT: u32 = 0x8000_0000;
+// Type, name and language, anything deeper is malformed or a loop
+const MAX_RESOURCE_DEPTH: usize = 3;
+// Entries walked over the whole tree, real executables stay far below it while crafted directories
+// could otherwise fan out to 65535 entries on every level
+const MAX_RESOURCE_ENTRIES: usize = 0x10000;
+const VS_FIXEDFILEINFO_SIGNATURE: u32 = 0xFEEF_04BD;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceId {
+    Id(u16),
+    Name(String),
+}
+
+/// A leaf of the resource tree
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub kind: ResourceId,
+    pub name: ResourceId,
+    pub language: ResourceId,
+    pub rva: u32,
+    pub size: u32,
+    pub code_page: u32,
+}
+
+/// Walks the resource directory of a file layout image and returns every resource it holds
+pub fn get_resources(bytes: &[u8],
+                     optional_header: &OptionalHeader,
+                     sections: &[SectionTable]) -> Result<Option<Vec<Resource>>, Error> {
+    let directory = match optional_header.data_directories.data_directories.get(DATA_DIRECTORY_RESOURCE) {
+        Some(Some((_, directory))) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Ok(None),
+    };
+    let root = crate::rva_to_offset(sections, directory.virtual_address as usize)?;
+    let root = bytes.get(root..).ok_or(Error::RvaNotInSection(directory.virtual_address as usize))?;
+
+    let mut resources = vec![];
+    walk_directory(root, 0, &mut vec![], &mut HashSet::from([0]), &mut 0, &mut resources)?;
+    Ok(Some(resources))
+}
+
+/// Offsets inside the tree are relative to the root directory. Every directory is walked once, a
+/// subdirectory referenced twice is skipped the second time, and the walk stops after
+/// `MAX_RESOURCE_ENTRIES` entries
+fn walk_directory(root: &[u8],
+                  offset: usize,
+                  path: &mut Vec<ResourceId>,
+                  visited: &mut HashSet<usize>,
+                  entries: &mut usize,
+                  resources: &mut Vec<Resource>) -> Result<(), Error> {
+    if path.len() >= MAX_RESOURCE_DEPTH {
+        return Ok(());
+    }
+    let named: u16 = root.pread_with(offset + 12, LE)?;
+    let ids: u16 = root.pread_with(offset + 14, LE)?;
+    for index in 0..(named as usize + ids as usize) {
+        if *entries >= MAX_RESOURCE_ENTRIES {
+            break;
+        }
+        *entries += 1;
+        let entry = offset + SIZEOF_RESOURCE_DIRECTORY + index * SIZEOF_RESOURCE_DIRECTORY_ENTRY;
+        let name: u32 = root.pread_with(entry, LE)?;
+        let target: u32 = root.pread_with(entry + 4, LE)?;
+        let id = match name & RESOURCE_HIGH_BIT {
+            0 => ResourceId::Id(name as u16),
+            _ => ResourceId::Name(resource_name(root, (name & !RESOURCE_HIGH_BIT) as usize)?),
+        };
+
+        path.push(id);
+        if target & RESOURCE_HIGH_BIT != 0 {
+            let subdirectory = (target & !RESOURCE_HIGH_BIT) as usize;
+            if visited.insert(subdirectory) {
+                walk_directory(root, subdirectory, path, visited, entries, resources)?;
+            }
+        } else if path.len() == MAX_RESOURCE_DEPTH {
+            let data = target as usize;
+            resources.push(Resource {
+                kind: path[0].clone(),
+                name: path[1].clone(),
+                language: path[2].clone(),
+                rva: root.pread_with(data, LE)?,
+                size: root.pread_with(data + 4, LE)?,
+                code_page: root.pread_with(data + 8, LE)?,
+            });
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+fn resource_name(root: &[u8], offset: usize) -> Result<String, Error> {
+    let length: u16 = root.pread_with(offset, LE)?;
+    let units = (0..length as usize)
+        .map(|index| root.pread_with::<u16>(offset + 2 + index * 2, LE))
+        .collect::<Result<Vec<u16>, scroll::Error>>()?;
+    Ok(String::from_utf16_lossy(&units))
+}
+
+/// The `VS_VERSIONINFO` resource
+#[derive(Debug, Clone, Default)]
+pub struct VersionInfo {
+    /// From `VS_FIXEDFILEINFO`, major, minor, build, revision
+    pub file_version: Option<[u16; 4]>,
+    pub product_version: Option<[u16; 4]>,
+    /// Language and code page of the string table the strings come from, e.g. `040904B0`
+    pub language: Option<String>,
+    /// `StringFileInfo` values (`ProductName`, `CompanyName`, `FileVersion`...) in file order
+    pub strings: Vec<(String, String)>,
+}
+
+impl VersionInfo {
+    pub fn string(&self, key: &str) -> Option<&str> {
+        self.strings.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn product_name(&self) -> Option<&str> {
+        self.string("ProductName")
+    }
+
+    pub fn company_name(&self) -> Option<&str> {
+        self.string("CompanyName")
+    }
+
+    /// `FileVersion` from the fixed info, falling back to the string table
+    pub fn file_version_string(&self) -> Option<String> {
+        self.file_version.map(format_version)
+            .or_else(|| self.string("FileVersion").map(str::to_string))
+    }
+
+    /// `ProductVersion` from the fixed info, falling back to the string table
+    pub fn product_version_string(&self) -> Option<String> {
+        self.product_version.map(format_version)
+            .or_else(|| self.string("ProductVersion").map(str::to_string))
+    }
+}
+
+fn format_version(version: [u16; 4]) -> String {
+    format!("{}.{}.{}.{}", version[0], version[1], version[2], version[3])
+}
+
+/// Finds and parses the first `RT_VERSION` resource
+pub fn get_version_info(bytes: &[u8],
+                        optional_header: &OptionalHeader,
+                        sections: &[SectionTable]) -> Result<Option<VersionInfo>, Error> {
+    let Some(resources) = get_resources(bytes, optional_header, sections)? else { return Ok(None) };
+    let Some(resource) = resources.iter().find(|resource| resource.kind == ResourceId::Id(RT_VERSION)) else {
+        return Ok(None);
+    };
+    let offset = crate::rva_to_offset(sections, resource.rva as usize)?;
+    let data = bytes.get(offset..offset + resource.size as usize)
+        .ok_or(Error::RvaNotInSection(resource.rva as usize))?;
+    Ok(parse_version_info(data))
+}
+
+/// Parses a `VS_VERSIONINFO` block
+pub fn parse_version_info(data: &[u8]) -> Option<VersionInfo> {
+    let (root, _) = read_block(data)?;
+    if root.key != "VS_VERSION_INFO" {
+        return None;
+    }
+
+    let mut info = VersionInfo::default();
+    if root.value.pread_with::<u32>(0, LE).ok() == Some(VS_FIXEDFILEINFO_SIGNATURE) {
+        let version = |offset: usize| -> Option<[u16; 4]> {
+            let most: u32 = root.value.pread_with(offset, LE).ok()?;
+            let least: u32 = root.value.pread_with(offset + 4, LE).ok()?;
+            Some([(most >> 16) as u16, most as u16, (least >> 16) as u16, least as u16])
+        };
+        info.file_version = version(8);
+        info.product_version = version(16);
+    }
+
+    for child in blocks(root.children) {
+        if child.key != "StringFileInfo" {
+            continue;
+        }
+        // Only the first string table is kept, multilingual builds repeat the same values
+        let Some(table) = blocks(child.children).next() else { continue };
+        info.language = Some(table.key.to_uppercase());
+        for string in blocks(table.children) {
+            let value = match string.value_type {
+                1 => utf16_string(string.value),
+                _ => String::from_utf8_lossy(string.value).trim_end_matches('\0').to_string(),
+            };
+            info.strings.push((string.key, value));
+        }
+    }
+    Some(info)
+}
+
+/// A node of the version resource, every node shares the same header layout
+struct Block<'a> {
+    key: String,
+    value_type: u16,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+/// Reads the block at the start of `data`, returning it with its aligned length
+fn read_block(data: &[u8]) -> Option<(Block<'_>, usize)> {
+    let length = (data.pread_with::<u16>(0, LE).ok()? as usize).min(data.len());
+    let value_length: u16 = data.pread_with(2, LE).ok()?;
+    let value_type: u16 = data.pread_with(4, LE).ok()?;
+    if length < 6 {
+        return None;
+    }
+    let data = &data[0..length];
+
+    let mut key = vec![];
+    let mut offset = 6;
+    loop {
+        let unit: u16 = data.pread_with(offset, LE).ok()?;
+        offset += 2;
+        if unit == 0 {
+            break;
+        }
+        key.push(unit);
+    }
+    let value_start = align4(offset).min(length);
+    // Text values are measured in UTF-16 units, binary ones in bytes
+    let value_size = match value_type {
+        1 => value_length as usize * 2,
+        _ => value_length as usize,
+    };
+    let value_end = (value_start + value_size).min(length);
+    let children_start = align4(value_end).min(length);
+
+    let block = Block {
+        key: String::from_utf16_lossy(&key),
+        value_type,
+        value: &data[value_start..value_end],
+        children: &data[children_start..],
+    };
+    Some((block, align4(length)))
+}
+
+fn blocks(mut data: &[u8]) -> impl Iterator<Item = Block<'_>> {
+    std::iter::from_fn(move || {
+        let (block, length) = read_block(data)?;
+        data = data.get(length..).unwrap_or_default();
+        Some(block)
+    })
+}
+
+fn utf16_string(bytes: &[u8]) -> String {
+    let units = bytes.chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units)
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_INITIALIZED_DATA;
+    use scroll::Pwrite;
+
+    use super::*;
+    use crate::{PeBuilder, PeImage};
+
+    const RSRC_RVA: u32 = 0x1000;
+    const VERSION_OFFSET: usize = 0x200;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// Serializes a version resource node, `text` values are measured in UTF-16 units
+    fn version_block(key: &str, value: &[u8], text: bool, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut block = vec![0u8; 6];
+        block.extend(utf16(key));
+        block.resize(align4(block.len()), 0);
+        block.extend(value);
+        for child in children {
+            block.resize(align4(block.len()), 0);
+            block.extend(child);
+        }
+        let value_length = if text { value.len() / 2 } else { value.len() };
+        let length = block.len() as u16;
+        block.pwrite_with(length, 0, LE).unwrap();
+        block.pwrite_with(value_length as u16, 2, LE).unwrap();
+        block.pwrite_with(text as u16, 4, LE).unwrap();
+        block
+    }
+
+    fn version_resource() -> Vec<u8> {
+        let mut fixed = vec![0u8; 52];
+        fixed.pwrite_with(VS_FIXEDFILEINFO_SIGNATURE, 0, LE).unwrap();
+        fixed.pwrite_with(0x0005_0003u32, 8, LE).unwrap();
+        fixed.pwrite_with(0x0002_0001u32, 12, LE).unwrap();
+        fixed.pwrite_with(0x0001_0000u32, 16, LE).unwrap();
+        fixed.pwrite_with(0x0000_0007u32, 20, LE).unwrap();
+        let strings = [("CompanyName", "Epic Games, Inc."), ("ProductName", "Game"), ("FileVersion", "5.3.2.1")]
+            .map(|(key, value)| version_block(key, &utf16(value), true, &[]));
+        let table = version_block("040904b0", &[], true, &strings);
+        let string_file_info = version_block("StringFileInfo", &[], true, &[table]);
+        version_block("VS_VERSION_INFO", &fixed, false, &[string_file_info])
+    }
+
+    /// A resource directory with `named` named entries followed by the ID entries
+    fn write_directory(rsrc: &mut [u8], offset: usize, named: u16, entries: &[(u32, u32)]) {
+        rsrc.pwrite_with(named, offset + 12, LE).unwrap();
+        rsrc.pwrite_with(entries.len() as u16 - named, offset + 14, LE).unwrap();
+        for (index, (name, target)) in entries.iter().enumerate() {
+            let entry = offset + SIZEOF_RESOURCE_DIRECTORY + index * SIZEOF_RESOURCE_DIRECTORY_ENTRY;
+            rsrc.pwrite_with(*name, entry, LE).unwrap();
+            rsrc.pwrite_with(*target, entry + 4, LE).unwrap();
+        }
+    }
+
+    fn build_image(rsrc: Vec<u8>) -> PeImage {
+        let size = rsrc.len() as u32;
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".rsrc", rsrc, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(DATA_DIRECTORY_RESOURCE, RSRC_RVA, size).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    /// `RT_VERSION` / "VERSION" / 0x409, the data entry pointing at the version resource
+    fn version_tree() -> Vec<u8> {
+        let version = version_resource();
+        let mut rsrc = vec![0u8; VERSION_OFFSET + version.len()];
+        write_directory(&mut rsrc, 0, 0, &[(RT_VERSION as u32, RESOURCE_HIGH_BIT | 0x18)]);
+        write_directory(&mut rsrc, 0x18, 1, &[(RESOURCE_HIGH_BIT | 0x100, RESOURCE_HIGH_BIT | 0x30)]);
+        write_directory(&mut rsrc, 0x30, 0, &[(0x409, 0x48)]);
+        rsrc.pwrite_with(RSRC_RVA + VERSION_OFFSET as u32, 0x48, LE).unwrap();
+        rsrc.pwrite_with(version.len() as u32, 0x4C, LE).unwrap();
+        rsrc.pwrite_with(1252u32, 0x50, LE).unwrap();
+        rsrc.pwrite_with(7u16, 0x100, LE).unwrap();
+        let name = utf16("VERSION");
+        rsrc[0x102..0x102 + name.len() - 2].copy_from_slice(&name[0..name.len() - 2]);
+        rsrc[VERSION_OFFSET..].copy_from_slice(&version);
+        rsrc
+    }
+
+    #[test]
+    fn reads_the_version_resource() {
+        let image = build_image(version_tree());
+
+        let resources = image.resources().unwrap().unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].kind, ResourceId::Id(RT_VERSION));
+        assert_eq!(resources[0].name, ResourceId::Name("VERSION".to_string()));
+        assert_eq!(resources[0].language, ResourceId::Id(0x409));
+        assert_eq!((resources[0].rva, resources[0].code_page), (RSRC_RVA + VERSION_OFFSET as u32, 1252));
+
+        let info = image.version_info().unwrap().unwrap();
+        assert_eq!(info.file_version, Some([5, 3, 2, 1]));
+        assert_eq!(info.product_version_string().unwrap(), "1.0.0.7");
+        assert_eq!(info.language.as_deref(), Some("040904B0"));
+        assert_eq!(info.company_name(), Some("Epic Games, Inc."));
+        assert_eq!(info.product_name(), Some("Game"));
+        assert_eq!(info.string("FileVersion"), Some("5.3.2.1"));
+        assert_eq!(info.strings.len(), 3);
+    }
+
+    #[test]
+    fn walks_each_directory_once() {
+        let mut rsrc = version_tree();
+        // A second type sharing the name directory, and a language entry looping back to the root
+        write_directory(&mut rsrc, 0, 0, &[(RT_ICON as u32, RESOURCE_HIGH_BIT | 0x18), (RT_VERSION as u32, RESOURCE_HIGH_BIT | 0x18)]);
+        write_directory(&mut rsrc, 0x18, 1, &[(RESOURCE_HIGH_BIT | 0x100, RESOURCE_HIGH_BIT | 0x30)]);
+        write_directory(&mut rsrc, 0x30, 0, &[(0x409, 0x48), (0x407, RESOURCE_HIGH_BIT)]);
+        let image = build_image(rsrc);
+
+        let resources = image.resources().unwrap().unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].kind, ResourceId::Id(RT_ICON));
+        assert!(image.version_info().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_other_blocks() {
+        assert!(parse_version_info(&version_block("VarFileInfo", &[], true, &[])).is_none());
+        assert!(parse_version_info(&[4, 0]).is_none());
+        let info = parse_version_info(&version_block("VS_VERSION_INFO", &[], false, &[])).unwrap();
+        assert_eq!((info.file_version, info.file_version_string()), (None, None));
+    }
+}
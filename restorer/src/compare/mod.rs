@@ -0,0 +1,223 @@
+use log::info;
+use pe_utils::PeImage;
+
+use crate::Error;
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone)]
+pub struct SectionComparison {
+    pub name: String,
+    pub virtual_address: u32,
+    /// Bytes covered by either image, the longer raw size of the two. Bytes only one of them has,
+    /// like data a packed original leaves out of its raw size, count as differing
+    pub compared: usize,
+    pub identical: usize,
+    pub original_entropy: f32,
+    pub restored_entropy: f32,
+    /// RVA ranges (start, end) of the pages that differ, adjacent pages are merged
+    pub differing_pages: Vec<(u32, u32)>,
+}
+
+impl SectionComparison {
+    /// Identical bytes over compared bytes, from 0.0 to 1.0. Sections with no raw data in either
+    /// image give 0.0, there is nothing showing they match
+    pub fn similarity(&self) -> f32 {
+        match self.compared {
+            0 => 0.0,
+            compared => self.identical as f32 / compared as f32,
+        }
+    }
+
+    /// Negative when the restored section is less random, usually because it was decrypted or
+    /// unpacked at runtime
+    pub fn entropy_change(&self) -> f32 {
+        self.restored_entropy - self.original_entropy
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub headers_match: bool,
+    /// Names of the header fields that differ
+    pub header_differences: Vec<String>,
+    pub sections: Vec<SectionComparison>,
+    /// Sections of the original with no section at the same RVA in the restored image
+    pub missing_sections: Vec<String>,
+}
+
+/// Compares a restored image with the on-disk executable it was dumped from. Both are expected in
+/// the file layout, sections are matched by RVA and compared through their raw data. The header
+/// comparison skips the fields restoring is expected to change (raw pointers and sizes)
+pub fn compare_images(restored: &PeImage, original: &PeImage) -> Result<Comparison, Error> {
+    let header_differences = header_differences(restored, original);
+
+    let mut sections = vec![];
+    let mut missing_sections = vec![];
+    for original_section in original.sections() {
+        let name = String::from_utf8_lossy(&original_section.name).trim_end_matches('\0').to_string();
+        let Some(restored_section) = restored.sections().iter()
+            .find(|section| section.virtual_address == original_section.virtual_address) else {
+            missing_sections.push(name);
+            continue;
+        };
+        let original_data = original.section_data(original_section);
+        let restored_data = restored.section_data(restored_section);
+        sections.push(compare_section(name, original_section.virtual_address, original_data, restored_data));
+    }
+
+    let comparison = Comparison {
+        headers_match: header_differences.is_empty(),
+        header_differences,
+        sections,
+        missing_sections,
+    };
+    info!("Compared {} sections, headers match: {}", comparison.sections.len(), comparison.headers_match);
+    Ok(comparison)
+}
+
+fn compare_section(name: String, virtual_address: u32, original: &[u8], restored: &[u8]) -> SectionComparison {
+    let compared = original.len().max(restored.len());
+
+    let mut identical = 0;
+    let mut differing_pages: Vec<(u32, u32)> = vec![];
+    for page_start in (0..compared).step_by(PAGE_SIZE) {
+        let page_end = (page_start + PAGE_SIZE).min(compared);
+        // Only the bytes both images have can be identical
+        let original_page = original.get(page_start..page_end.min(original.len())).unwrap_or_default();
+        let restored_page = restored.get(page_start..page_end.min(restored.len())).unwrap_or_default();
+        let same = original_page.iter().zip(restored_page).filter(|(left, right)| left == right).count();
+        identical += same;
+        if same == page_end - page_start {
+            continue;
+        }
+        let start = virtual_address.wrapping_add(page_start as u32);
+        let end = start.wrapping_add((page_end - page_start) as u32);
+        match differing_pages.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => differing_pages.push((start, end)),
+        }
+    }
+
+    SectionComparison {
+        name,
+        virtual_address,
+        compared,
+        identical,
+        original_entropy: pe_utils::entropy(original),
+        restored_entropy: pe_utils::entropy(restored),
+        differing_pages,
+    }
+}
+
+fn header_differences(restored: &PeImage, original: &PeImage) -> Vec<String> {
+    let mut differences = vec![];
+    let mut check = |field: &str, same: bool| {
+        if !same {
+            differences.push(field.to_string());
+        }
+    };
+
+    let restored_coff = restored.coff_header();
+    let original_coff = original.coff_header();
+    check("Machine", restored_coff.machine == original_coff.machine);
+    check("NumberOfSections", restored_coff.number_of_sections == original_coff.number_of_sections);
+    check("TimeDateStamp", restored_coff.time_date_stamp == original_coff.time_date_stamp);
+    check("Characteristics", restored_coff.characteristics == original_coff.characteristics);
+
+    let restored_standard = &restored.optional_header().standard_fields;
+    let original_standard = &original.optional_header().standard_fields;
+    check("Magic", restored_standard.magic == original_standard.magic);
+    check("AddressOfEntryPoint", restored_standard.address_of_entry_point == original_standard.address_of_entry_point);
+    check("BaseOfCode", restored_standard.base_of_code == original_standard.base_of_code);
+
+    let restored_windows = &restored.optional_header().windows_fields;
+    let original_windows = &original.optional_header().windows_fields;
+    check("ImageBase", restored_windows.image_base == original_windows.image_base);
+    check("SectionAlignment", restored_windows.section_alignment == original_windows.section_alignment);
+    check("SizeOfImage", restored_windows.size_of_image == original_windows.size_of_image);
+    check("Subsystem", restored_windows.subsystem == original_windows.subsystem);
+    check("DllCharacteristics", restored_windows.dll_characteristics == original_windows.dll_characteristics);
+
+    let restored_directories = restored.optional_header().data_directories.data_directories.iter();
+    let original_directories = original.optional_header().data_directories.data_directories.iter();
+    for (index, (restored_directory, original_directory)) in restored_directories.zip(original_directories).enumerate() {
+        let same = match (restored_directory, original_directory) {
+            (Some((_, restored)), Some((_, original))) => {
+                restored.virtual_address == original.virtual_address && restored.size == original.size
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        check(&format!("DataDirectory[{}]", index), same);
+    }
+
+    let sections_match = restored.sections().len() == original.sections().len()
+        && restored.sections().iter().zip(original.sections()).all(|(restored, original)| {
+            restored.name == original.name
+                && restored.virtual_address == original.virtual_address
+                && restored.virtual_size == original.virtual_size
+                && restored.characteristics == original.characteristics
+        });
+    check("SectionTable", sections_match);
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA};
+    use pe_utils::PeBuilder;
+
+    use super::*;
+
+    fn build_original() -> Vec<u8> {
+        let code = (0..0x2400).map(|index| (index * 7 % 251) as u8).collect();
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", code, IMAGE_SCN_CNT_CODE).unwrap();
+        builder.add_section(".data", vec![0x5A; 0x100], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_entry_point(0x1000).unwrap();
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn identical_images() {
+        let original = PeImage::parse(build_original()).unwrap();
+        let comparison = compare_images(&original, &original).unwrap();
+
+        assert!(comparison.headers_match);
+        assert!(comparison.missing_sections.is_empty());
+        assert_eq!(comparison.sections.len(), 2);
+        for section in &comparison.sections {
+            assert_eq!(section.similarity(), 1.0);
+            assert_eq!(section.entropy_change(), 0.0);
+            assert!(section.differing_pages.is_empty());
+        }
+    }
+
+    #[test]
+    fn reports_differing_pages_and_headers() {
+        let original_bytes = build_original();
+        let mut builder = PeBuilder::from_image(&original_bytes).unwrap();
+        builder.write_at_rva(0x2010, &[0; 0x10]).unwrap();
+        builder.write_at_rva(0x3000, &[0; 4]).unwrap();
+        builder.set_entry_point(0x1800).unwrap();
+        builder.remove_section(".data").unwrap();
+        let restored = PeImage::parse(builder.build().unwrap()).unwrap();
+        let original = PeImage::parse(original_bytes).unwrap();
+
+        let comparison = compare_images(&restored, &original).unwrap();
+        assert!(!comparison.headers_match);
+        for field in ["AddressOfEntryPoint", "NumberOfSections", "SizeOfImage", "SectionTable"] {
+            assert!(comparison.header_differences.iter().any(|difference| difference == field), "{}", field);
+        }
+        assert_eq!(comparison.missing_sections, [".data"]);
+
+        let text = &comparison.sections[0];
+        assert_eq!((text.name.as_str(), text.compared), (".text", 0x2400));
+        assert_eq!(text.identical, 0x2400 - 0x10 - 4);
+        // Adjacent differing pages are merged into one range
+        assert_eq!(text.differing_pages, [(0x2000, 0x3400)]);
+        assert!(text.similarity() < 1.0);
+    }
+}
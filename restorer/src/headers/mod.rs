@@ -0,0 +1,388 @@
+use goblin::pe::characteristic::{IMAGE_FILE_32BIT_MACHINE, IMAGE_FILE_EXECUTABLE_IMAGE, IMAGE_FILE_LARGE_ADDRESS_AWARE};
+use goblin::pe::header::{
+    COFF_MACHINE_X86, COFF_MACHINE_X86_64, DOS_MAGIC, PE_MAGIC, PE_POINTER_OFFSET, SIZEOF_COFF_HEADER, SIZEOF_PE_MAGIC,
+};
+use goblin::pe::optional_header::{MAGIC_32, MAGIC_64};
+use goblin::pe::section_table::{
+    SectionTable, IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ,
+    IMAGE_SCN_MEM_WRITE, SIZEOF_SECTION_TABLE,
+};
+use goblin::pe::subsystem::IMAGE_SUBSYSTEM_WINDOWS_GUI;
+use log::{info, warn};
+use pe_utils::PeImage;
+use scroll::{Pwrite, LE};
+
+use crate::Error;
+
+const PAGE_SIZE: usize = 0x1000;
+const FILE_ALIGNMENT: u32 = 0x200;
+const PE_POINTER: usize = 0x80;
+const SIZEOF_OPTIONAL_HEADER_32: u16 = 0xE0;
+const SIZEOF_OPTIONAL_HEADER_64: u16 = 0xF0;
+const NUMBER_OF_RVA_AND_SIZES: u32 = 16;
+// Opcode bytes that dominate x86/x64 compiler output (REX prefixes, mov, call, jcc, ret and int3 padding)
+const CODE_BYTES: [u8; 14] = [0x48, 0x49, 0x4C, 0x89, 0x8B, 0x8D, 0xE8, 0xE9, 0xFF, 0x0F, 0x83, 0xC3, 0xCC, 0x74];
+const CODE_BYTES_RATIO: f32 = 0.22;
+// Runs shorter than this are folded into their neighbour, jump tables and literals live inside .text
+const MIN_SECTION_PAGES: usize = 2;
+
+/// A region of the module as reported by the OS, used instead of content heuristics when available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionHint {
+    pub rva: u32,
+    pub size: u32,
+    pub executable: bool,
+    pub writable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageKind {
+    Empty,
+    Code,
+    Data,
+}
+
+/// Whether the headers of a memory dump are unusable: unparsable, or parsable with the section
+/// table or `SizeOfImage` wiped, which anti-dump code does while leaving the DOS and NT headers
+pub fn headers_wiped(dump: &[u8]) -> bool {
+    let Ok(header) = pe_utils::parse_headers(dump) else { return true };
+    let Ok(optional_header) = pe_utils::get_optional_headers(&header) else { return true };
+    let Ok(sections) = pe_utils::get_sections(&header, dump) else { return true };
+    optional_header.windows_fields.size_of_image == 0
+        || sections.is_empty()
+        || sections.iter().any(|section| {
+            section.virtual_address == 0 || section.virtual_size.max(section.size_of_raw_data) == 0
+        })
+}
+
+/// Overwrites the headers of a memory dump with the headers of the on-disk executable it was
+/// loaded from
+pub fn rebuild_headers_from_template(dump: &[u8], template: &PeImage) -> Result<Vec<u8>, Error> {
+    let optional_header = template.optional_header();
+    let size_of_headers = optional_header.windows_fields.size_of_headers as usize;
+    let size_of_image = optional_header.windows_fields.size_of_image as usize;
+
+    if size_of_headers > template.data().len() || size_of_headers > dump.len() {
+        return Err(Error::OutOfBounds(0, size_of_headers));
+    }
+    if size_of_image != dump.len() {
+        warn!("Template SizeOfImage 0x{:X} does not match the dump size 0x{:X}", size_of_image, dump.len());
+    }
+
+    let mut output = dump.to_vec();
+    output[0..size_of_headers].copy_from_slice(&template.data()[0..size_of_headers]);
+    info!("Headers rebuilt from template, {} sections", template.sections().len());
+    Ok(output)
+}
+
+/// Synthesizes PE32 or PE32+ headers for a memory dump, deriving the section table from `regions` when the
+/// page protections are known and from the page contents otherwise. Data directories are left
+/// empty, imports can be recovered afterwards with [`crate::iat::rebuild_imports`]
+pub fn rebuild_headers_heuristic(dump: &[u8],
+                                 image_base: u64,
+                                 is_64: bool,
+                                 regions: &[RegionHint]) -> Result<Vec<u8>, Error> {
+    let mut sections = match regions.is_empty() {
+        true => sections_from_content(dump),
+        false => sections_from_regions(regions),
+    };
+    if sections.is_empty() {
+        return Err(Error::NoSectionsDetected);
+    }
+
+    let size_of_headers = sections.iter()
+        .map(|section| section.virtual_address as usize)
+        .min()
+        .unwrap_or(PAGE_SIZE)
+        .min(PAGE_SIZE);
+    let size_of_optional_header = match is_64 {
+        true => SIZEOF_OPTIONAL_HEADER_64,
+        false => SIZEOF_OPTIONAL_HEADER_32,
+    };
+    let optional_header_offset = PE_POINTER + SIZEOF_PE_MAGIC + SIZEOF_COFF_HEADER;
+    let section_table_offset = optional_header_offset + size_of_optional_header as usize;
+    let max_sections = size_of_headers.saturating_sub(section_table_offset) / SIZEOF_SECTION_TABLE;
+    if sections.len() > max_sections {
+        return Err(Error::NoRoomForSection);
+    }
+
+    let mut text_count = 0;
+    let mut data_count = 0;
+    for section in &mut sections {
+        let (prefix, count) = match section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0 {
+            true => (".text", &mut text_count),
+            false => (".data", &mut data_count),
+        };
+        *count += 1;
+        let name = match *count {
+            1 => prefix.to_string(),
+            count => format!("{}{}", prefix, count),
+        };
+        section.name = [0; 8];
+        section.name[0..name.len().min(8)].copy_from_slice(&name.as_bytes()[0..name.len().min(8)]);
+    }
+
+    let size_of_image = sections.iter()
+        .map(|section| section.virtual_address as usize + section.virtual_size as usize)
+        .max()
+        .unwrap_or(dump.len())
+        .next_multiple_of(PAGE_SIZE);
+    // Summed wide, the regions of a large module can add up past what the u32 fields hold
+    let size_of_code = sections.iter()
+        .filter(|section| section.characteristics & IMAGE_SCN_CNT_CODE != 0)
+        .map(|section| section.size_of_raw_data as u64)
+        .sum::<u64>()
+        .min(u32::MAX as u64) as u32;
+    let size_of_initialized_data = sections.iter()
+        .filter(|section| section.characteristics & IMAGE_SCN_CNT_INITIALIZED_DATA != 0)
+        .map(|section| section.size_of_raw_data as u64)
+        .sum::<u64>()
+        .min(u32::MAX as u64) as u32;
+    let base_of_code = sections.iter()
+        .find(|section| section.characteristics & IMAGE_SCN_CNT_CODE != 0)
+        .map(|section| section.virtual_address)
+        .unwrap_or(0);
+
+    let mut output = dump.to_vec();
+    output.resize(output.len().max(size_of_image), 0);
+    output[0..size_of_headers].fill(0);
+
+    output.pwrite_with(DOS_MAGIC, 0, LE)?;
+    output.pwrite_with(PE_POINTER as u32, PE_POINTER_OFFSET as usize, LE)?;
+    output.pwrite_with(PE_MAGIC, PE_POINTER, LE)?;
+
+    let coff = PE_POINTER + SIZEOF_PE_MAGIC;
+    let (machine, characteristics) = match is_64 {
+        true => (COFF_MACHINE_X86_64, IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE),
+        false => (COFF_MACHINE_X86, IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_32BIT_MACHINE),
+    };
+    output.pwrite_with(machine, coff, LE)?;
+    output.pwrite_with(sections.len() as u16, coff + 2, LE)?;
+    output.pwrite_with(size_of_optional_header, coff + 16, LE)?;
+    output.pwrite_with(characteristics, coff + 18, LE)?;
+
+    let optional = optional_header_offset;
+    output.pwrite_with(if is_64 { MAGIC_64 } else { MAGIC_32 }, optional, LE)?;
+    output.pwrite_with(size_of_code, optional + 4, LE)?;
+    output.pwrite_with(size_of_initialized_data, optional + 8, LE)?;
+    output.pwrite_with(base_of_code, optional + 20, LE)?;
+    // PE32 has BaseOfData where PE32+ widens ImageBase, everything up to the stack sizes lines up
+    match is_64 {
+        true => output.pwrite_with(image_base, optional + 24, LE)?,
+        false => output.pwrite_with(image_base as u32, optional + 28, LE)?,
+    };
+    output.pwrite_with(PAGE_SIZE as u32, optional + 32, LE)?;
+    output.pwrite_with(FILE_ALIGNMENT, optional + 36, LE)?;
+    // Operating system and subsystem version 6.0
+    output.pwrite_with(6u16, optional + 40, LE)?;
+    output.pwrite_with(6u16, optional + 48, LE)?;
+    output.pwrite_with(size_of_image as u32, optional + 56, LE)?;
+    output.pwrite_with(size_of_headers as u32, optional + 60, LE)?;
+    output.pwrite_with(IMAGE_SUBSYSTEM_WINDOWS_GUI, optional + 68, LE)?;
+    // Stack and heap reserve/commit sizes, pointer sized
+    let pointer_size = if is_64 { 8 } else { 4 };
+    for (index, size) in [0x10_0000u64, PAGE_SIZE as u64, 0x10_0000, PAGE_SIZE as u64].into_iter().enumerate() {
+        let offset = optional + 72 + index * pointer_size;
+        match is_64 {
+            true => output.pwrite_with(size, offset, LE)?,
+            false => output.pwrite_with(size as u32, offset, LE)?,
+        };
+    }
+    output.pwrite_with(NUMBER_OF_RVA_AND_SIZES, optional + 72 + 4 * pointer_size + 4, LE)?;
+
+    for (index, section) in sections.iter().enumerate() {
+        output.pwrite_with(section, section_table_offset + index * SIZEOF_SECTION_TABLE, LE)
+            .map_err(pe_utils::Error::from)?;
+    }
+
+    info!("Headers synthesized with {} sections", sections.len());
+    Ok(output)
+}
+
+fn new_section(rva: usize, size: usize, executable: bool, writable: bool) -> SectionTable {
+    let characteristics = match (executable, writable) {
+        (true, _) => IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+        (false, true) => IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+        (false, false) => IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+    };
+    // Raw pointers mirror the RVAs, the dump is still in its mapped layout
+    SectionTable {
+        virtual_size: size as u32,
+        virtual_address: rva as u32,
+        size_of_raw_data: size as u32,
+        pointer_to_raw_data: rva as u32,
+        characteristics,
+        ..Default::default()
+    }
+}
+
+fn sections_from_regions(regions: &[RegionHint]) -> Vec<SectionTable> {
+    let mut regions = regions.iter()
+        .filter(|region| region.rva as usize >= PAGE_SIZE && region.size != 0)
+        .copied()
+        .collect::<Vec<RegionHint>>();
+    regions.sort_by_key(|region| region.rva);
+
+    let mut merged: Vec<RegionHint> = vec![];
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if last.rva.checked_add(last.size) == Some(region.rva)
+                && last.executable == region.executable
+                && last.writable == region.writable => last.size = last.size.saturating_add(region.size),
+            _ => merged.push(region),
+        }
+    }
+
+    merged.iter()
+        .map(|region| new_section(region.rva as usize, region.size as usize, region.executable, region.writable))
+        .collect()
+}
+
+fn classify_page(page: &[u8]) -> PageKind {
+    if page.iter().all(|byte| *byte == 0) {
+        return PageKind::Empty;
+    }
+    let code_bytes = page.iter().filter(|byte| CODE_BYTES.contains(byte)).count();
+    match code_bytes as f32 / page.len() as f32 >= CODE_BYTES_RATIO {
+        true => PageKind::Code,
+        false => PageKind::Data,
+    }
+}
+
+fn sections_from_content(dump: &[u8]) -> Vec<SectionTable> {
+    // (first page, page count, kind), empty pages extend whatever section precedes them
+    let mut runs: Vec<(usize, usize, PageKind)> = vec![];
+    for (index, page) in dump.chunks(PAGE_SIZE).enumerate().skip(1) {
+        let kind = classify_page(page);
+        match (runs.last_mut(), kind) {
+            (Some(run), PageKind::Empty) => run.1 += 1,
+            (Some(run), kind) if run.2 == kind => run.1 += 1,
+            (_, PageKind::Empty) => {}
+            (_, kind) => runs.push((index, 1, kind)),
+        }
+    }
+
+    let mut merged: Vec<(usize, usize, PageKind)> = vec![];
+    for run in runs {
+        match merged.last_mut() {
+            Some(last) if last.2 == run.2 || run.1 < MIN_SECTION_PAGES => last.1 += run.1,
+            _ => merged.push(run),
+        }
+    }
+
+    merged.iter()
+        .map(|(first_page, pages, kind)| {
+            new_section(first_page * PAGE_SIZE, pages * PAGE_SIZE, *kind == PageKind::Code, *kind == PageKind::Data)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use pe_utils::PeBuilder;
+
+    use super::*;
+
+    const IMAGE_BASE: u64 = 0x1_4000_0000;
+
+    /// Memory layout dump with two pages of code at 0x1000 and two pages of text at 0x3000
+    fn build_image() -> PeImage {
+        let code = [0x48, 0x8B, 0x05, 0x10, 0x00, 0x00, 0x00, 0xE8, 0x00, 0x01, 0x00, 0x00, 0xC3, 0xCC];
+        let text = code.iter().copied().cycle().take(2 * PAGE_SIZE).collect::<Vec<u8>>();
+        let data = b"abcdefgh".iter().copied().cycle().take(2 * PAGE_SIZE).collect::<Vec<u8>>();
+        let mut builder = PeBuilder::new(true, IMAGE_BASE).unwrap();
+        builder.add_section(".text", text, IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ).unwrap();
+        builder.add_section(".data", data, IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    fn memory_layout(image: &PeImage) -> Vec<u8> {
+        let mut dump = vec![0u8; image.optional_header().windows_fields.size_of_image as usize];
+        let size_of_headers = image.optional_header().windows_fields.size_of_headers as usize;
+        dump[0..size_of_headers].copy_from_slice(&image.data()[0..size_of_headers]);
+        for section in image.sections() {
+            let data = image.section_data(section);
+            let start = section.virtual_address as usize;
+            dump[start..start + data.len()].copy_from_slice(data);
+        }
+        dump
+    }
+
+    fn wiped_dump() -> Vec<u8> {
+        let mut dump = memory_layout(&build_image());
+        dump[0..PAGE_SIZE].fill(0);
+        dump
+    }
+
+    fn parse_sections(output: &[u8]) -> Vec<SectionTable> {
+        let header = pe_utils::parse_headers(output).unwrap();
+        pe_utils::get_sections(&header, output).unwrap()
+    }
+
+    #[test]
+    fn detects_wiped_headers() {
+        let dump = memory_layout(&build_image());
+        assert!(!headers_wiped(&dump));
+        assert!(headers_wiped(&wiped_dump()));
+
+        // DOS and NT headers left in place, only the section table cleared
+        let table = crate::section_table_offset(&pe_utils::parse_headers(&dump).unwrap());
+        let mut cleared = dump.clone();
+        cleared[table..table + 2 * SIZEOF_SECTION_TABLE].fill(0);
+        assert!(headers_wiped(&cleared));
+    }
+
+    #[test]
+    fn copies_the_headers_of_the_template() {
+        let template = build_image();
+        let size_of_headers = template.optional_header().windows_fields.size_of_headers as usize;
+        let dump = wiped_dump();
+
+        let output = rebuild_headers_from_template(&dump, &template).unwrap();
+        assert!(!headers_wiped(&output));
+        assert_eq!(&output[0..size_of_headers], &template.data()[0..size_of_headers]);
+        assert_eq!(&output[size_of_headers..], &dump[size_of_headers..]);
+
+        assert!(matches!(rebuild_headers_from_template(&dump[0..0x100], &template), Err(Error::OutOfBounds(0, _))));
+    }
+
+    #[test]
+    fn synthesizes_sections_from_regions() {
+        let regions = [
+            RegionHint { rva: 0x1000, size: 0x1000, executable: true, writable: false },
+            RegionHint { rva: 0x2000, size: 0x1000, executable: true, writable: false },
+            RegionHint { rva: 0x3000, size: 0x2000, executable: false, writable: true },
+        ];
+        let output = rebuild_headers_heuristic(&wiped_dump(), IMAGE_BASE, true, &regions).unwrap();
+        assert!(!headers_wiped(&output));
+
+        let image = PeImage::parse(output).unwrap();
+        assert_eq!(image.image_base(), IMAGE_BASE);
+        let windows = image.optional_header().windows_fields;
+        assert_eq!(windows.size_of_image, 0x5000);
+        let standard = image.optional_header().standard_fields;
+        assert_eq!(standard.size_of_code, 0x2000);
+        assert_eq!(standard.size_of_initialized_data, 0x2000);
+        assert_eq!(standard.base_of_code, 0x1000);
+
+        let sections = parse_sections(image.data());
+        let layout = sections.iter()
+            .map(|section| (&section.name[0..5], section.virtual_address, section.virtual_size))
+            .collect::<Vec<_>>();
+        assert_eq!(layout, [(&b".text"[..], 0x1000, 0x2000), (&b".data"[..], 0x3000, 0x2000)]);
+        assert_ne!(sections[1].characteristics & IMAGE_SCN_MEM_WRITE, 0);
+    }
+
+    #[test]
+    fn synthesizes_sections_from_page_contents() {
+        let output = rebuild_headers_heuristic(&wiped_dump(), IMAGE_BASE, false, &[]).unwrap();
+        let sections = parse_sections(&output);
+        assert_eq!(sections.len(), 2);
+        assert_eq!((sections[0].virtual_address, sections[0].virtual_size), (0x1000, 0x2000));
+        assert_ne!(sections[0].characteristics & IMAGE_SCN_MEM_EXECUTE, 0);
+        assert_eq!((sections[1].virtual_address, sections[1].virtual_size), (0x3000, 0x2000));
+        assert_eq!(sections[1].characteristics & IMAGE_SCN_MEM_EXECUTE, 0);
+
+        assert!(matches!(rebuild_headers_heuristic(&[0; 0x3000], IMAGE_BASE, true, &[]), Err(Error::NoSectionsDetected)));
+    }
+}
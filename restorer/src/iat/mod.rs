@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+
+use goblin::pe::section_table::{SectionTable, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE};
+use log::{debug, info, warn};
+use pe_utils::{PeBuilder, PeImage};
+use scroll::{Pread, Pwrite, LE};
+
+use crate::Error;
+
+pub const IMPORT_SECTION_NAME: &str = ".idata2";
+
+const SIZEOF_IMPORT_DESCRIPTOR: usize = 20;
+// Slots without a symbol we still tolerate inside one IAT cluster, protectors like to leave
+// a few stubs behind
+const MAX_CLUSTER_GAP: usize = 4;
+const DATA_DIRECTORY_IMPORT_TABLE: usize = 1;
+const DATA_DIRECTORY_BOUND_IMPORT: usize = 11;
+const DATA_DIRECTORY_IMPORT_ADDRESS_TABLE: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImportSymbol {
+    pub module: String,
+    pub name: Option<String>,
+    pub ordinal: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolMap {
+    symbols: HashMap<u64, ImportSymbol>,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn insert(&mut self, address: u64, symbol: ImportSymbol) {
+        // Aliased exports share an address, keep the named one so the import stays readable
+        match self.symbols.get(&address) {
+            Some(existing) if existing.name.is_some() => {}
+            _ => {
+                self.symbols.insert(address, symbol);
+            }
+        }
+    }
+
+    pub fn resolve(&self, address: u64) -> Option<&ImportSymbol> {
+        self.symbols.get(&address)
+    }
+
+    /// Registers every export of an on-disk module as it would be seen when loaded at `module_base`
+    pub fn add_module<A: AsRef<str>>(&mut self,
+                                     name: A,
+                                     module_base: u64,
+                                     module: &PeImage) -> Result<usize, Error> {
+        let mut added = 0;
+        if let Some(exports) = module.exports()? {
+            for export in exports.functions {
+                // Forwarded exports never show up in an IAT, the loader resolves them to the target module
+                if export.forwarder.is_some() {
+                    continue;
+                }
+                self.insert(module_base + export.rva as u64, ImportSymbol {
+                    module: name.as_ref().to_string(),
+                    name: export.name.map(str::to_string),
+                    ordinal: export.ordinal,
+                });
+                added += 1;
+            }
+        }
+        debug!("Registered {} exports from {}", added, name.as_ref());
+        Ok(added)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportRebuild {
+    pub image: Vec<u8>,
+    pub iat_rva: u32,
+    pub iat_size: u32,
+    pub section_rva: u32,
+    pub modules: Vec<String>,
+    pub resolved: usize,
+    /// RVAs of IAT slots holding a pointer no export source could explain
+    pub unresolved: Vec<u32>,
+}
+
+struct ThunkGroup<'a> {
+    first_thunk: u32,
+    symbols: Vec<&'a ImportSymbol>,
+}
+
+/// Rebuilds the import directory of a restored (file layout) image whose IAT still holds runtime
+/// pointers, appending the new directory as an extra section
+pub fn rebuild_imports(pe_image: &PeImage, symbols: &SymbolMap) -> Result<ImportRebuild, Error> {
+    let image = pe_image.data();
+    let optional_header = pe_image.optional_header();
+    let sections = pe_image.sections();
+    let is_64 = pe_image.is_64();
+    let pointer_size = if is_64 { 8 } else { 4 };
+
+    let (iat_rva, iat_size) = match locate_iat_from_directory(image, optional_header, sections, symbols, pointer_size)? {
+        Some(iat) => iat,
+        None => locate_iat_by_scan(image, sections, symbols, pointer_size)
+            .ok_or(Error::IatNotFound)?,
+    };
+    info!("IAT located at RVA 0x{:X}, size 0x{:X}", iat_rva, iat_size);
+    // Slot RVAs below stay under the end of the IAT
+    iat_rva.checked_add(iat_size).ok_or(Error::OutOfBounds(iat_rva as usize, iat_rva as usize + iat_size as usize))?;
+
+    let mut groups: Vec<ThunkGroup> = vec![];
+    let mut unresolved = vec![];
+    let mut current: Option<ThunkGroup> = None;
+    for slot in (0..iat_size).step_by(pointer_size) {
+        let slot_rva = iat_rva + slot;
+        let value = read_pointer(image, sections, slot_rva, pointer_size).unwrap_or(0);
+        let symbol = match value {
+            0 => None,
+            value => {
+                let symbol = symbols.resolve(value);
+                if symbol.is_none() {
+                    warn!("Unresolved IAT entry at RVA 0x{:X}: 0x{:X}", slot_rva, value);
+                    unresolved.push(slot_rva);
+                }
+                symbol
+            }
+        };
+
+        match (symbol, current.as_mut()) {
+            (Some(symbol), Some(group)) if group.symbols[0].module == symbol.module => {
+                group.symbols.push(symbol);
+            }
+            (Some(symbol), _) => {
+                groups.extend(current.take());
+                current = Some(ThunkGroup { first_thunk: slot_rva, symbols: vec![symbol] });
+            }
+            (None, _) => groups.extend(current.take()),
+        }
+    }
+    groups.extend(current.take());
+
+    if groups.is_empty() {
+        return Err(Error::IatNotFound);
+    }
+
+    if (optional_header.windows_fields.number_of_rva_and_sizes as usize) <= DATA_DIRECTORY_IMPORT_ADDRESS_TABLE {
+        return Err(Error::MissingDataDirectory(DATA_DIRECTORY_IMPORT_ADDRESS_TABLE));
+    }
+    let mut builder = PeBuilder::from_image(image)?;
+    let section_rva = builder.next_section_rva();
+
+    // Layout: descriptors and lookup tables up front, library names and hint/name entries after them
+    let descriptors_size = (groups.len() + 1) * SIZEOF_IMPORT_DESCRIPTOR;
+    let lookup_size: usize = groups.iter().map(|group| (group.symbols.len() + 1) * pointer_size).sum();
+    let mut content = vec![0u8; descriptors_size + lookup_size];
+    let mut lookup_offset = descriptors_size;
+    let mut modules = vec![];
+    let mut resolved = 0;
+    let mut iat_writes = vec![];
+    for (index, group) in groups.iter().enumerate() {
+        let name_rva = section_rva + content.len() as u32;
+        content.extend_from_slice(group.symbols[0].module.as_bytes());
+        content.push(0);
+        modules.push(group.symbols[0].module.clone());
+
+        let descriptor = index * SIZEOF_IMPORT_DESCRIPTOR;
+        content.pwrite_with(section_rva + lookup_offset as u32, descriptor, LE)?;
+        content.pwrite_with(name_rva, descriptor + 12, LE)?;
+        content.pwrite_with(group.first_thunk, descriptor + 16, LE)?;
+
+        for (thunk_index, symbol) in group.symbols.iter().enumerate() {
+            let thunk = match &symbol.name {
+                Some(name) => {
+                    if !content.len().is_multiple_of(2) {
+                        content.push(0);
+                    }
+                    let hint_name_rva = section_rva + content.len() as u32;
+                    content.extend_from_slice(&[0, 0]);
+                    content.extend_from_slice(name.as_bytes());
+                    content.push(0);
+                    hint_name_rva as u64
+                }
+                None => ordinal_flag(is_64) | symbol.ordinal as u64,
+            };
+            write_pointer(&mut content, lookup_offset + thunk_index * pointer_size, thunk, pointer_size)?;
+            iat_writes.push((group.first_thunk + (thunk_index * pointer_size) as u32, thunk));
+            resolved += 1;
+        }
+        lookup_offset += (group.symbols.len() + 1) * pointer_size;
+    }
+
+    builder.add_section(IMPORT_SECTION_NAME, content,
+                        IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE)?;
+
+    // The IAT on disk mirrors the lookup table, the loader overwrites it again at runtime
+    for (slot_rva, thunk) in iat_writes {
+        let bytes = thunk.to_le_bytes();
+        builder.write_at_rva(slot_rva, &bytes[0..pointer_size])?;
+    }
+
+    builder.set_data_directory(DATA_DIRECTORY_IMPORT_TABLE, section_rva, descriptors_size as u32)?;
+    builder.set_data_directory(DATA_DIRECTORY_IMPORT_ADDRESS_TABLE, iat_rva, iat_size)?;
+    // Bound data describes the old descriptors, the loader would trust it and skip binding
+    builder.set_data_directory(DATA_DIRECTORY_BOUND_IMPORT, 0, 0)?;
+    let output = builder.build().map_err(|err| match err {
+        pe_utils::Error::NoRoomForSection => Error::NoRoomForSection,
+        err => Error::PEUtils(err),
+    })?;
+
+    info!("Rebuilt {} imports from {} modules, {} unresolved", resolved, modules.len(), unresolved.len());
+    Ok(ImportRebuild {
+        image: output,
+        iat_rva,
+        iat_size,
+        section_rva,
+        modules,
+        resolved,
+        unresolved,
+    })
+}
+
+fn ordinal_flag(is_64: bool) -> u64 {
+    match is_64 {
+        true => 1 << 63,
+        false => 1 << 31,
+    }
+}
+
+fn read_pointer(image: &[u8], sections: &[SectionTable], rva: u32, pointer_size: usize) -> Option<u64> {
+    let offset = pe_utils::rva_to_offset(sections, rva as usize).ok()?;
+    match pointer_size {
+        8 => image.pread_with::<u64>(offset, LE).ok(),
+        _ => image.pread_with::<u32>(offset, LE).ok().map(|value| value as u64),
+    }
+}
+
+fn write_pointer(bytes: &mut [u8], offset: usize, value: u64, pointer_size: usize) -> Result<(), Error> {
+    match pointer_size {
+        8 => bytes.pwrite_with(value, offset, LE)?,
+        _ => bytes.pwrite_with(value as u32, offset, LE)?,
+    };
+    Ok(())
+}
+
+/// The IAT data directory when it holds resolvable pointers, an error when it wraps past the end
+/// of the address space
+fn locate_iat_from_directory(image: &[u8],
+                             optional_header: &goblin::pe::optional_header::OptionalHeader,
+                             sections: &[SectionTable],
+                             symbols: &SymbolMap,
+                             pointer_size: usize) -> Result<Option<(u32, u32)>, Error> {
+    let Some(directory) = optional_header.data_directories.get_import_address_table() else { return Ok(None) };
+    if directory.virtual_address == 0 || directory.size == 0 {
+        return Ok(None);
+    }
+    // Every slot below is at most the directory end, checked once here
+    directory.virtual_address.checked_add(directory.size).ok_or(Error::OutOfBounds(
+        directory.virtual_address as usize,
+        directory.virtual_address as usize + directory.size as usize,
+    ))?;
+    let resolvable = (0..directory.size)
+        .step_by(pointer_size)
+        .filter_map(|slot| read_pointer(image, sections, directory.virtual_address + slot, pointer_size))
+        .any(|value| symbols.resolve(value).is_some());
+    match resolvable {
+        true => Ok(Some((directory.virtual_address, directory.size))),
+        false => {
+            debug!("IAT data directory holds no resolvable pointers, falling back to a scan");
+            Ok(None)
+        }
+    }
+}
+
+fn locate_iat_by_scan(image: &[u8],
+                      sections: &[SectionTable],
+                      symbols: &SymbolMap,
+                      pointer_size: usize) -> Option<(u32, u32)> {
+    let mut best: Option<(u32, u32, usize)> = None;
+    for section in sections {
+        let start = section.pointer_to_raw_data as usize;
+        let end = (start + section.size_of_raw_data as usize).min(image.len());
+        if start >= end {
+            continue;
+        }
+
+        let mut cluster: Option<(u32, u32, usize)> = None;
+        for offset in (start..end - (end - start) % pointer_size).step_by(pointer_size) {
+            let value = match pointer_size {
+                8 => image.pread_with::<u64>(offset, LE).unwrap_or(0),
+                _ => image.pread_with::<u32>(offset, LE).unwrap_or(0) as u64,
+            };
+            if value == 0 || symbols.resolve(value).is_none() {
+                continue;
+            }
+            let Some(rva) = section.virtual_address.checked_add((offset - start) as u32) else { break };
+            cluster = match cluster {
+                Some((first, last, count)) if (rva - last) as usize <= MAX_CLUSTER_GAP * pointer_size => {
+                    Some((first, rva, count + 1))
+                }
+                previous => {
+                    best = pick_best(best, previous);
+                    Some((rva, rva, 1))
+                }
+            };
+        }
+        best = pick_best(best, cluster);
+    }
+    best.map(|(first, last, _)| (first, last - first + pointer_size as u32))
+}
+
+fn pick_best(best: Option<(u32, u32, usize)>, candidate: Option<(u32, u32, usize)>) -> Option<(u32, u32, usize)> {
+    match (best, candidate) {
+        (Some(best), Some(candidate)) if candidate.2 > best.2 => Some(candidate),
+        (None, candidate) => candidate,
+        (best, _) => best,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_CODE;
+
+    use super::*;
+
+    const KERNEL32: u64 = 0x7FF8_0000_0000;
+    const USER32: u64 = 0x7FF9_0000_0000;
+
+    fn symbol(module: &str, name: Option<&str>, ordinal: u32) -> ImportSymbol {
+        ImportSymbol { module: module.to_string(), name: name.map(str::to_string), ordinal }
+    }
+
+    fn symbols() -> SymbolMap {
+        let mut symbols = SymbolMap::new();
+        symbols.insert(KERNEL32 + 0x1000, symbol("KERNEL32.dll", Some("ExitProcess"), 1));
+        symbols.insert(KERNEL32 + 0x1010, symbol("KERNEL32.dll", Some("GetTickCount"), 2));
+        symbols.insert(USER32 + 0x2000, symbol("USER32.dll", None, 5));
+        symbols
+    }
+
+    /// A dumped IAT at 0x2000: two KERNEL32 slots, a null separator, one USER32 slot and a pointer
+    /// nothing explains
+    fn build_dump(iat_directory: (u32, u32)) -> PeImage {
+        let mut rdata = vec![0u8; 0x100];
+        for (slot, value) in [KERNEL32 + 0x1000, KERNEL32 + 0x1010, 0, USER32 + 0x2000, 0xDEAD_BEEF].iter().enumerate() {
+            rdata.pwrite_with(*value, slot * 8, LE).unwrap();
+        }
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", vec![0xC3; 0x10], IMAGE_SCN_CNT_CODE).unwrap();
+        builder.add_section(".rdata", rdata, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(DATA_DIRECTORY_IMPORT_ADDRESS_TABLE, iat_directory.0, iat_directory.1).unwrap();
+        PeImage::parse(builder.build().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn regenerates_the_import_directory() {
+        let rebuilt = rebuild_imports(&build_dump((0x2000, 0x28)), &symbols()).unwrap();
+        assert_eq!((rebuilt.iat_rva, rebuilt.iat_size, rebuilt.section_rva), (0x2000, 0x28, 0x3000));
+        assert_eq!(rebuilt.modules, ["KERNEL32.dll", "USER32.dll"]);
+        assert_eq!((rebuilt.resolved, rebuilt.unresolved.as_slice()), (3, [0x2020].as_slice()));
+
+        let image = PeImage::parse(rebuilt.image).unwrap();
+        assert!(image.section_by_name(IMPORT_SECTION_NAME).is_some());
+        assert_eq!(image.data_directory(DATA_DIRECTORY_IMPORT_TABLE).unwrap().virtual_address, 0x3000);
+        assert!(image.data_directory(DATA_DIRECTORY_BOUND_IMPORT).is_none());
+
+        let imports = image.imports().unwrap().unwrap();
+        assert_eq!(imports.libraries.len(), 2);
+        let kernel32 = &imports.libraries[0];
+        assert_eq!(kernel32.name, "KERNEL32.dll");
+        let functions = kernel32.functions.iter().map(|function| (function.name, function.iat_rva)).collect::<Vec<_>>();
+        assert_eq!(functions, [(Some("ExitProcess"), 0x2000), (Some("GetTickCount"), 0x2008)]);
+        // The IAT on disk now mirrors the lookup table, hint/name RVAs in the new section
+        assert!((0x3000..0x4000).contains(&kernel32.functions[0].thunk));
+        let user32 = &imports.libraries[1];
+        assert_eq!(user32.name, "USER32.dll");
+        assert_eq!((user32.functions[0].ordinal, user32.functions[0].iat_rva), (Some(5), 0x2018));
+    }
+
+    #[test]
+    fn scans_for_the_iat_without_a_usable_directory() {
+        let rebuilt = rebuild_imports(&build_dump((0, 0)), &symbols()).unwrap();
+        assert_eq!((rebuilt.iat_rva, rebuilt.iat_size), (0x2000, 0x20));
+        assert_eq!(rebuilt.resolved, 3);
+
+        assert!(matches!(rebuild_imports(&build_dump((0, 0)), &SymbolMap::new()), Err(Error::IatNotFound)));
+        assert!(matches!(rebuild_imports(&build_dump((0xFFFF_FFF0, 0x20)), &symbols()), Err(Error::OutOfBounds(_, _))));
+    }
+}
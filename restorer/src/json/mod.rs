@@ -0,0 +1,37 @@
+#![cfg(feature = "json_input")]
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::iat::{ImportSymbol, SymbolMap};
+use crate::Error;
+
+/// Address to symbol map as exported by debuggers, e.g. `{ "0x7FFA1C2D1000": "KERNEL32.dll!CreateFileW" }`.
+/// Ordinal only imports are written as `module!#ordinal`
+#[derive(Serialize, Deserialize)]
+pub struct SymbolMapJson(pub HashMap<String, String>);
+
+impl TryFrom<&SymbolMapJson> for SymbolMap {
+    type Error = Error;
+
+    fn try_from(json: &SymbolMapJson) -> Result<Self, Self::Error> {
+        let mut symbols = SymbolMap::new();
+        for (address, symbol) in &json.0 {
+            let parsed_address = u64::from_str_radix(address.trim_start_matches("0x").trim_start_matches("0X"), 16)
+                .map_err(|_| Error::InvalidSymbol(address.clone()))?;
+            let (module, function) = symbol.split_once('!')
+                .ok_or_else(|| Error::InvalidSymbol(symbol.clone()))?;
+            let (name, ordinal) = match function.strip_prefix('#') {
+                Some(ordinal) => (None, ordinal.parse().map_err(|_| Error::InvalidSymbol(symbol.clone()))?),
+                None => (Some(function.to_string()), 0),
+            };
+            symbols.insert(parsed_address, ImportSymbol {
+                module: module.to_string(),
+                name,
+                ordinal,
+            });
+        }
+        Ok(symbols)
+    }
+}
@@ -1,9 +1,25 @@
-use std::io::Write;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
+use goblin::pe::header::{Header, SIZEOF_COFF_HEADER, SIZEOF_PE_MAGIC};
 use goblin::pe::optional_header::OptionalHeader;
-use goblin::pe::section_table::SectionTable;
-use log::{info, trace};
-use colored::*;
+use goblin::pe::section_table::{SectionTable, SIZEOF_SECTION_TABLE};
+use log::{info, trace, warn};
+use pe_utils::{MemAlignedAddress, PeBuilder};
+use scroll::{Pwrite, LE};
+use report::{RestoreReport, SectionReport, SectionStatus, SkipReason};
+use source::MemorySource;
+
+pub mod compare;
+pub mod headers;
+pub mod iat;
+#[cfg(feature = "json_input")]
+pub mod json;
+pub mod merge;
+pub mod minidump;
+pub mod oep;
+pub mod rebase;
+pub mod report;
+pub mod source;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -11,98 +27,407 @@ pub enum Error {
     PEUtils(#[from] pe_utils::Error),
     #[error("IO Error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Scroll Error: {0}")]
+    Scroll(#[from] scroll::Error),
+    #[error("No import address table could be located")]
+    IatNotFound,
+    #[error("No room left in the headers for another section")]
+    NoRoomForSection,
+    #[error("No sections could be detected in the dump")]
+    NoSectionsDetected,
+    #[error("Data directory: {0} missing from the optional header")]
+    MissingDataDirectory(usize),
+    #[error("Invalid symbol: {0}")]
+    InvalidSymbol(String),
+    #[error("Range: 0x{0:X}..0x{1:X} is out of bounds")]
+    OutOfBounds(usize, usize),
+    #[error("Not a minidump file")]
+    InvalidMinidump,
+    #[error("Module: {0} not found")]
+    ModuleNotFound(String),
+    #[error("No dumps were provided")]
+    NoDumps,
+    #[error("Offset Finder Error: {0}")]
+    OffsetFinder(#[from] offset_finder::Error),
+    #[error("Entry point: 0x{0:X} is outside of the image")]
+    InvalidEntryPoint(u32),
+    #[error("Dump is already based at 0x{0:X}, a target base such as the ImageBase of the original executable is required")]
+    AlreadyAtBase(u64),
+    #[error("Image size: 0x{0:X} is larger than any loadable image")]
+    ImageTooLarge(u64),
+}
+
+const OPTIONAL_HEADER_SIZE_OF_IMAGE: usize = 56;
+
+pub(crate) fn optional_header_offset(header: &Header) -> usize {
+    header.dos_header.pe_pointer as usize + SIZEOF_PE_MAGIC + SIZEOF_COFF_HEADER
+}
+
+pub(crate) fn section_table_offset(header: &Header) -> usize {
+    optional_header_offset(header) + header.coff_header.size_of_optional_header as usize
 }
 
-pub fn restore_from_ptr<A: AsRef<str>, B: AsRef<str>>(name: A,
-                                                      module_base: usize,
-                                                      restored_filename: Option<B>) -> Result<Vec<u8>, Error> {
-    let data = unsafe { std::slice::from_raw_parts(module_base as *const u8, 0x1000) };
+pub(crate) fn write_size_of_image(output: &mut [u8], header: &Header, size_of_image: u32) -> Result<(), Error> {
+    output.pwrite_with(size_of_image, optional_header_offset(header) + OPTIONAL_HEADER_SIZE_OF_IMAGE, LE)?;
+    Ok(())
+}
+
+pub(crate) fn write_section_table(output: &mut [u8], header: &Header, sections: &[SectionTable]) -> Result<(), Error> {
+    let offset = section_table_offset(header);
+    for (index, section) in sections.iter().enumerate() {
+        output.pwrite_with(section, offset + index * SIZEOF_SECTION_TABLE, LE)
+            .map_err(pe_utils::Error::from)?;
+    }
+    Ok(())
+}
+
+/// Restores a module that is mapped at `module_base` in `source`. Pages the source can not read
+/// are zero filled and counted in [`RestoreReport::missing`]
+pub fn restore_from_source<A: AsRef<str>, S: MemorySource + ?Sized>(name: A,
+                                                                source: &S,
+                                                                module_base: u64) -> Result<RestoreReport, Error> {
+    let (data, missing) = source::read_image(source, module_base)?;
+    let mut report = restore_from_dump(name, &data)?;
+    report.missing = missing;
+    Ok(report)
+}
 
-    let header = pe_utils::parse_headers(data)?;
+pub fn restore_from_dump<A: AsRef<str>>(name: A, dump: &[u8]) -> Result<RestoreReport, Error> {
+    let header = pe_utils::parse_headers(dump)?;
     trace!("{:#?}", header);
     let optional_headers = pe_utils::get_optional_headers(&header)?;
-    let sections = pe_utils::get_sections(&header, data)?;
+    let sections = pe_utils::get_sections(&header, dump)?;
+    restore_raw(name, dump, optional_headers, &sections)
+}
 
-    let mut vaddr_end: u32 = 0;
-    for section in &sections {
-        let virtual_end = section.virtual_address + section.size_of_raw_data;
-        if virtual_end > vaddr_end {
-            vaddr_end = virtual_end;
-        }
-    }
-    let data = unsafe {
-        std::slice::from_raw_parts(module_base as *const u8, vaddr_end as usize)
-    };
-    restore_raw(name, data, optional_headers, &sections, restored_filename)
+pub fn restore_from_minidump<A: AsRef<str>>(name: A,
+                                            minidump: &[u8],
+                                            module_name: &str) -> Result<RestoreReport, Error> {
+    let minidump = minidump::Minidump::parse(minidump)?;
+    let module = minidump.extract_module(module_name)?;
+    let mut report = restore_from_dump(name, &module.image)?;
+    report.missing = module.missing;
+    Ok(report)
+}
+
+pub fn restore_raw<A: AsRef<str>>(
+    name: A,
+    dump: &[u8],
+    optional_headers: OptionalHeader,
+    sections: &[SectionTable],
+) -> Result<RestoreReport, Error> {
+    let mut output = Cursor::new(Vec::with_capacity(dump.len()));
+    let mut report = restore_raw_to(name, dump, optional_headers, sections, &mut output)?;
+    report.image = output.into_inner();
+    Ok(report)
 }
 
-pub fn restore_from_dump<A: AsRef<str>, B: AsRef<str>>(name: A,
-                                                       dump: &[u8],
-                                                       restored_filename: Option<B>) -> Result<Vec<u8>, Error> {
+/// [`restore_from_dump`] writing straight to `writer`, see [`restore_raw_to`]
+pub fn restore_from_dump_to<A: AsRef<str>, W: Write + Seek>(name: A,
+                                                            dump: &[u8],
+                                                            writer: &mut W) -> Result<RestoreReport, Error> {
     let header = pe_utils::parse_headers(dump)?;
     trace!("{:#?}", header);
     let optional_headers = pe_utils::get_optional_headers(&header)?;
     let sections = pe_utils::get_sections(&header, dump)?;
-    restore_raw(name, dump, optional_headers, &sections, restored_filename)
+    restore_raw_to(name, dump, optional_headers, &sections, writer)
 }
 
-pub fn restore_raw<A: AsRef<str>, B: AsRef<str>>(
+/// Streams the restored executable to `writer`, each section is written at its raw pointer as it
+/// is copied so the restored image is never held in memory. [`RestoreReport::image`] is left
+/// empty, the writer ends up holding exactly [`RestoreReport::eof`] bytes
+pub fn restore_raw_to<A: AsRef<str>, W: Write + Seek>(
     name: A,
     dump: &[u8],
     optional_headers: OptionalHeader,
     sections: &[SectionTable],
-    restored_filename: Option<B>,
-) -> Result<Vec<u8>, Error> {
-    let mut output = vec![0; dump.len()];
-    output[0..optional_headers.windows_fields.size_of_headers as usize]
-        .copy_from_slice(&dump[0..optional_headers.windows_fields.size_of_headers as usize]);
+    writer: &mut W,
+) -> Result<RestoreReport, Error> {
+    let size_of_headers = (optional_headers.windows_fields.size_of_headers as usize).min(dump.len());
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&dump[0..size_of_headers])?;
 
-    let mut eof: u32 = 0;
+    let mut report = RestoreReport::default();
+    let mut eof = size_of_headers;
+    let mut written = size_of_headers;
     for section in sections {
         let phys_start = section.pointer_to_raw_data as usize;
         let phys_end = phys_start + section.size_of_raw_data as usize;
         let virt_start = section.virtual_address as usize;
         let virt_end = virt_start + section.size_of_raw_data as usize;
 
-        if phys_start <= output.len()
-            && phys_end <= output.len()
-            && virt_start <= dump.len()
-            && virt_end <= dump.len()
-        {
-            let source_slice = &dump[virt_start..virt_end];
-            let dest_slice = &mut output[phys_start..phys_end];
-
-            if source_slice.len() == dest_slice.len() {
-                dest_slice.copy_from_slice(source_slice);
-            } else {
-                eprintln!(
-                    "Skipping section {} due to slice length mismatch (source: {}, destination: {})",
-                    String::from_utf8_lossy(&section.name).bright_red(),
-                    source_slice.len(),
-                    dest_slice.len()
-                );
-            }
+        let section_report = if section.size_of_raw_data == 0 {
+            SectionReport::new(section, 0, SectionStatus::Empty)
+        } else if phys_end <= dump.len() && virt_start < dump.len() {
+            let source_slice = &dump[virt_start..virt_end.min(dump.len())];
+            writer.seek(SeekFrom::Start(phys_start as u64))?;
+            writer.write_all(source_slice)?;
+            written = written.max(phys_start + source_slice.len());
+            let status = match virt_end <= dump.len() {
+                true => SectionStatus::Copied,
+                false => SectionStatus::Truncated,
+            };
+            SectionReport::new(section, source_slice.len(), status)
         } else {
-            eprintln!(
-                "Skipping section {} due to out-of-bounds access",
-                String::from_utf8_lossy(&section.name).bright_red()
-            );
-        }
+            warn!("Skipping section {} due to out-of-bounds access", String::from_utf8_lossy(&section.name));
+            SectionReport::new(section, 0, SectionStatus::Skipped(SkipReason::OutOfBounds))
+        };
+        report.sections.push(section_report);
+
+        eof = eof.max(phys_end.min(dump.len()));
+    }
+
+    // Truncated sections leave their tail unwritten, pad up to the end of the last section
+    if written < eof {
+        writer.seek(SeekFrom::Start(written as u64))?;
+        std::io::copy(&mut std::io::repeat(0).take((eof - written) as u64), writer)?;
+    }
+    writer.flush()?;
+
+    report.eof = eof;
+    info!("Executable {} restored, 0x{:X} bytes", name.as_ref(), eof);
+    Ok(report)
+}
+
+pub fn restore_from_dump_realigned<A: AsRef<str>>(name: A, dump: &[u8]) -> Result<RestoreReport, Error> {
+    let header = pe_utils::parse_headers(dump)?;
+    trace!("{:#?}", header);
+    let sections = pe_utils::get_sections(&header, dump)?;
+    restore_realigned(name, dump, &sections)
+}
 
-        if phys_end as u32 > eof {
-            eof = phys_end as u32;
+/// Restores a memory dump sizing every section from its `VirtualSize` instead of the on-disk
+/// `SizeOfRawData`, laying the sections out again at `FileAlignment` through [`PeBuilder`]
+pub fn restore_realigned<A: AsRef<str>>(
+    name: A,
+    dump: &[u8],
+    sections: &[SectionTable],
+) -> Result<RestoreReport, Error> {
+    let output = PeBuilder::from_memory_sections(dump, sections)?.build()?;
+    let restored_header = pe_utils::parse_headers(&output)?;
+    let restored_sections = pe_utils::get_sections(&restored_header, &output)?;
+
+    let mut report = RestoreReport::default();
+    for section in &restored_sections {
+        let virtual_size = match section.virtual_size {
+            0 => section.size_of_raw_data,
+            virtual_size => virtual_size,
+        };
+        let virt_start = section.virtual_address as usize;
+        let virt_end = virt_start + virtual_size as usize;
+        if virtual_size == 0 {
+            report.sections.push(SectionReport::new(section, 0, SectionStatus::Empty));
+            continue;
+        }
+        if virt_start >= dump.len() {
+            warn!("Section {} is missing from the dump, it will be empty", String::from_utf8_lossy(&section.name));
+            report.sections.push(SectionReport::new(section, 0, SectionStatus::Skipped(SkipReason::MissingFromDump)));
+            continue;
         }
+        let copied = virt_end.min(dump.len()) - virt_start;
+        let status = match virt_end <= dump.len() {
+            true => SectionStatus::Copied,
+            false => SectionStatus::Truncated,
+        };
+        report.sections.push(SectionReport::new(section, copied, status));
     }
 
-    match restored_filename {
-        None => info!("Since no restored_filename was provided, the restored output will not be saved to a file"),
-        Some(filename) => {
-            let mut data_file = std::fs::File::create(filename.as_ref())?;
-            data_file.write_all(&output[0..eof as usize])?;
-            info!("Restored executable saved to: {}", filename.as_ref());
+    report.eof = output.len();
+    report.image = output;
+    info!("Executable {} restored and realigned, 0x{:X} bytes", name.as_ref(), report.eof);
+    Ok(report)
+}
+
+pub fn restore_from_dump_layout<A: AsRef<str>>(name: A, dump: &[u8]) -> Result<RestoreReport, Error> {
+    let header = pe_utils::parse_headers(dump)?;
+    trace!("{:#?}", header);
+    let optional_headers = pe_utils::get_optional_headers(&header)?;
+    let sections = pe_utils::get_sections(&header, dump)?;
+    restore_dump_layout(name, dump, &header, optional_headers, &sections)
+}
+
+/// Keeps the dump in its mapped layout and rewrites the section table so every section is read
+/// from its RVA (`PointerToRawData == VirtualAddress`). Nothing is moved around, which makes it
+/// the most forgiving strategy for damaged dumps
+pub fn restore_dump_layout<A: AsRef<str>>(
+    name: A,
+    dump: &[u8],
+    header: &Header,
+    optional_headers: OptionalHeader,
+    sections: &[SectionTable],
+) -> Result<RestoreReport, Error> {
+    let mut output = Cursor::new(Vec::with_capacity(dump.len()));
+    let mut report = restore_dump_layout_to(name, dump, header, optional_headers, sections, &mut output)?;
+    report.image = output.into_inner();
+    Ok(report)
+}
+
+/// [`restore_from_dump_layout`] writing straight to `writer`, see [`restore_dump_layout_to`]
+pub fn restore_from_dump_layout_to<A: AsRef<str>, W: Write + Seek>(name: A,
+                                                                   dump: &[u8],
+                                                                   writer: &mut W) -> Result<RestoreReport, Error> {
+    let header = pe_utils::parse_headers(dump)?;
+    trace!("{:#?}", header);
+    let optional_headers = pe_utils::get_optional_headers(&header)?;
+    let sections = pe_utils::get_sections(&header, dump)?;
+    restore_dump_layout_to(name, dump, &header, optional_headers, &sections, writer)
+}
+
+/// Streams the dump layout restore to `writer`: the dump is copied as is, padded to the new
+/// `SizeOfImage`, and only the headers are patched afterwards. [`RestoreReport::image`] is left
+/// empty
+pub fn restore_dump_layout_to<A: AsRef<str>, W: Write + Seek>(
+    name: A,
+    dump: &[u8],
+    header: &Header,
+    optional_headers: OptionalHeader,
+    sections: &[SectionTable],
+    writer: &mut W,
+) -> Result<RestoreReport, Error> {
+    // A wiped or forged header may hold no alignment at all
+    let section_alignment = optional_headers.windows_fields.section_alignment.max(1);
+
+    let mut size_of_image = optional_headers.windows_fields.size_of_headers;
+    for section in sections {
+        let virtual_size = match section.virtual_size {
+            0 => section.size_of_raw_data,
+            virtual_size => virtual_size,
+        };
+        size_of_image = size_of_image.max(section.virtual_address.saturating_add(virtual_size));
+    }
+    let size_of_image = u32::get_mem_aligned_address(size_of_image, section_alignment);
+
+    let mut report = RestoreReport::default();
+    let mut restored_sections = sections.to_vec();
+    for section in &mut restored_sections {
+        let virtual_size = match section.virtual_size {
+            0 => section.size_of_raw_data,
+            virtual_size => virtual_size,
+        };
+        let virt_start = section.virtual_address;
+        let virt_end = u32::get_mem_aligned_address(virt_start.saturating_add(virtual_size), section_alignment).min(size_of_image);
+        section.pointer_to_raw_data = match virt_end > virt_start {
+            true => virt_start,
+            false => 0,
+        };
+        section.size_of_raw_data = virt_end.saturating_sub(virt_start);
+
+        let copied = (virt_end as usize).min(dump.len()).saturating_sub(virt_start as usize);
+        let status = if section.size_of_raw_data == 0 {
+            SectionStatus::Empty
+        } else if virt_start as usize >= dump.len() {
+            warn!("Section {} is missing from the dump, it will be empty", String::from_utf8_lossy(&section.name));
+            SectionStatus::Skipped(SkipReason::MissingFromDump)
+        } else if copied < section.size_of_raw_data as usize {
+            SectionStatus::Truncated
+        } else {
+            SectionStatus::Copied
+        };
+        report.sections.push(SectionReport::new(section, copied, status));
+    }
+
+    let copy_end = (size_of_image as usize).min(dump.len());
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&dump[0..copy_end])?;
+    std::io::copy(&mut std::io::repeat(0).take((size_of_image as usize - copy_end) as u64), writer)?;
+
+    // Everything the header patches touch ends with the section table
+    let headers_end = (section_table_offset(header) + restored_sections.len() * SIZEOF_SECTION_TABLE)
+        .min(size_of_image as usize);
+    let mut headers = vec![0; headers_end];
+    let available = headers_end.min(dump.len());
+    headers[0..available].copy_from_slice(&dump[0..available]);
+    write_section_table(&mut headers, header, &restored_sections)?;
+    write_size_of_image(&mut headers, header, size_of_image)?;
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&headers)?;
+    writer.seek(SeekFrom::Start(size_of_image as u64))?;
+    writer.flush()?;
+
+    report.eof = size_of_image as usize;
+    info!("Executable {} restored in dump layout, 0x{:X} bytes", name.as_ref(), report.eof);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA};
+    use pe_utils::PeImage;
+
+    use super::*;
+
+    /// Memory layout dump whose `.text` (0x250 bytes at 0x1000) claims a stale 0x200 bytes of raw
+    /// data at a bogus file offset, as section tables of unpacked images often do. `.data` follows
+    /// at 0x2000
+    fn build_dump() -> Vec<u8> {
+        let text = (0..0x250u32).map(|index| index as u8 | 1).collect::<Vec<_>>();
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", text, IMAGE_SCN_CNT_CODE).unwrap();
+        builder.add_section(".data", vec![0xDD; 0x80], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        let mut dump = vec![0u8; image.optional_header().windows_fields.size_of_image as usize];
+        let size_of_headers = image.optional_header().windows_fields.size_of_headers as usize;
+        dump[0..size_of_headers].copy_from_slice(&image.data()[0..size_of_headers]);
+        for section in image.sections() {
+            let data = image.section_data(section);
+            let start = section.virtual_address as usize;
+            dump[start..start + data.len()].copy_from_slice(data);
         }
+
+        let original = dump.clone();
+        let header = pe_utils::parse_headers(&original).unwrap();
+        let mut sections = pe_utils::get_sections(&header, &original).unwrap();
+        sections[0].size_of_raw_data = 0x200;
+        sections[0].pointer_to_raw_data = 0x1234;
+        write_section_table(&mut dump, &header, &sections).unwrap();
+        dump
+    }
+
+    #[test]
+    fn realigns_sections_sized_from_virtual_size() {
+        let dump = build_dump();
+        let report = restore_from_dump_realigned("test", &dump).unwrap();
+
+        let header = pe_utils::parse_headers(&report.image).unwrap();
+        let sections = pe_utils::get_sections(&header, &report.image).unwrap();
+        let size_of_headers = header.optional_header.unwrap().windows_fields.size_of_headers;
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].pointer_to_raw_data, size_of_headers);
+        assert_eq!(sections[0].size_of_raw_data, 0x400);
+        assert_eq!(sections[1].pointer_to_raw_data, size_of_headers + 0x400);
+        assert_eq!(sections[1].size_of_raw_data, 0x200);
+
+        // The bytes past the stale raw size are kept
+        let text = &report.image[size_of_headers as usize..size_of_headers as usize + 0x250];
+        assert_eq!(text, &dump[0x1000..0x1250]);
+        assert_eq!(report.eof, report.image.len());
+        assert_eq!(report.incomplete_sections().count(), 0);
     }
 
-    info!("Executable {} restored successfully", name.as_ref());
-    Ok(output)
-}
\ No newline at end of file
+    #[test]
+    fn reports_sections_cut_by_the_end_of_the_dump() {
+        let dump = build_dump();
+        let report = restore_from_dump_realigned("test", &dump[0..0x2040]).unwrap();
+        assert_eq!(report.sections[0].status, SectionStatus::Copied);
+        assert_eq!(report.sections[1].status, SectionStatus::Truncated);
+        assert_eq!(report.sections[1].bytes_copied, 0x40);
+
+        let report = restore_from_dump_realigned("test", &dump[0..0x1800]).unwrap();
+        assert_eq!(report.sections[1].status, SectionStatus::Skipped(SkipReason::MissingFromDump));
+    }
+
+    #[test]
+    fn restores_the_dump_layout_without_a_section_alignment() {
+        let mut dump = build_dump();
+        // SectionAlignment sits 32 bytes into the optional header
+        let offset = optional_header_offset(&pe_utils::parse_headers(&dump).unwrap()) + 32;
+        dump.pwrite_with(0u32, offset, LE).unwrap();
+
+        let report = restore_from_dump_layout("test", &dump).unwrap();
+        assert_eq!(report.eof, 0x2080);
+        assert_eq!(report.image.len(), 0x2080);
+        assert_eq!(report.sections[0].size_of_raw_data, 0x250);
+        assert_eq!(report.incomplete_sections().count(), 0);
+    }
+}
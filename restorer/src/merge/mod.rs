@@ -0,0 +1,251 @@
+use goblin::pe::section_table::SectionTable;
+use log::{info, warn};
+
+use crate::Error;
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// How a page of the merged image was obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSource {
+    /// No dump captured the page, it is zero filled
+    Missing,
+    /// Every dump that captured the page agrees, the index is the first of them
+    Agreed(usize),
+    /// The dumps disagree, the index is the dump whose page was picked
+    Conflicting(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct SectionCoverage {
+    pub name: String,
+    pub virtual_address: u32,
+    pub pages: usize,
+    pub covered: usize,
+    pub conflicting: usize,
+}
+
+impl SectionCoverage {
+    /// Covered pages over total pages, from 0.0 to 1.0
+    pub fn ratio(&self) -> f32 {
+        match self.pages {
+            0 => 1.0,
+            pages => self.covered as f32 / pages as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeReport {
+    pub image: Vec<u8>,
+    pub pages: Vec<PageSource>,
+    /// Empty when none of the dumps has parsable headers
+    pub sections: Vec<SectionCoverage>,
+}
+
+impl MergeReport {
+    pub fn covered_pages(&self) -> usize {
+        self.pages.iter().filter(|page| **page != PageSource::Missing).count()
+    }
+
+    pub fn conflicting_pages(&self) -> usize {
+        self.pages.iter().filter(|page| matches!(page, PageSource::Conflicting(_))).count()
+    }
+}
+
+/// Merges memory layout dumps (RVA == offset) of the same module taken at different times, page
+/// by page. Zero pages count as not captured. When the captured pages disagree the content shared
+/// by most dumps wins, ties go to the page whose entropy is closest to the agreed pages of the same
+/// section, so a page that is still encrypted loses against its decrypted copy
+pub fn merge_dumps<D: AsRef<[u8]>>(dumps: &[D]) -> Result<MergeReport, Error> {
+    let dumps = dumps.iter().map(|dump| dump.as_ref()).collect::<Vec<&[u8]>>();
+    let size = dumps.iter().map(|dump| dump.len()).max().ok_or(Error::NoDumps)?;
+    if dumps.iter().any(|dump| dump.len() != size) {
+        warn!("Dumps differ in size, the merged image spans the largest one (0x{:X} bytes)", size);
+    }
+
+    let page_count = size.div_ceil(PAGE_SIZE);
+    let mut image = vec![0; size];
+    let mut pages = vec![PageSource::Missing; page_count];
+    let mut conflicts = vec![];
+    for (index, source) in pages.iter_mut().enumerate() {
+        let candidates = captured_pages(&dumps, index);
+        let Some((first, first_page)) = candidates.first() else { continue };
+        if candidates.iter().all(|(_, page)| page == first_page) {
+            copy_page(&mut image, index, first_page);
+            *source = PageSource::Agreed(*first);
+        } else {
+            conflicts.push(index);
+        }
+    }
+
+    // Sections come from the merged headers, or the first dump with usable ones if they conflict
+    let section_tables = section_tables(&image, &dumps);
+    let section_ranges = section_tables.iter().map(page_range).collect::<Vec<PageRange>>();
+    for index in conflicts {
+        let candidates = captured_pages(&dumps, index);
+        let section = section_ranges.iter().find(|section| section_contains(section, index));
+        let reference = section
+            .and_then(|section| agreed_entropy(&image, &pages, section))
+            .or_else(|| agreed_entropy(&image, &pages, &(0, page_count)));
+
+        let (picked, page) = pick_page(&candidates, reference);
+        copy_page(&mut image, index, page);
+        pages[index] = PageSource::Conflicting(picked);
+    }
+
+    let sections = section_tables.iter()
+        .zip(&section_ranges)
+        .map(|(section, (first_page, page_count))| {
+            let section_pages = &pages[(*first_page).min(pages.len())..(*first_page + *page_count).min(pages.len())];
+            SectionCoverage {
+                name: section.name().unwrap_or("").to_string(),
+                virtual_address: section.virtual_address,
+                pages: *page_count,
+                covered: section_pages.iter().filter(|page| **page != PageSource::Missing).count(),
+                conflicting: section_pages.iter().filter(|page| matches!(page, PageSource::Conflicting(_))).count(),
+            }
+        })
+        .collect::<Vec<SectionCoverage>>();
+
+    let report = MergeReport { image, pages, sections };
+    info!("Merged {} dumps, {} of {} pages covered, {} conflicting",
+        dumps.len(), report.covered_pages(), page_count, report.conflicting_pages());
+    Ok(report)
+}
+
+fn captured_pages<'a>(dumps: &[&'a [u8]], index: usize) -> Vec<(usize, &'a [u8])> {
+    let start = index * PAGE_SIZE;
+    dumps.iter()
+        .enumerate()
+        .filter_map(|(dump_index, dump)| {
+            let page = dump.get(start..(start + PAGE_SIZE).min(dump.len()))?;
+            page.iter().any(|byte| *byte != 0).then_some((dump_index, page))
+        })
+        .collect()
+}
+
+fn copy_page(image: &mut [u8], index: usize, page: &[u8]) {
+    let start = index * PAGE_SIZE;
+    image[start..start + page.len()].copy_from_slice(page);
+}
+
+fn pick_page<'a>(candidates: &[(usize, &'a [u8])], reference: Option<f32>) -> (usize, &'a [u8]) {
+    let votes = |page: &[u8]| candidates.iter().filter(|(_, other)| *other == page).count();
+    let best_votes = candidates.iter().map(|(_, page)| votes(page)).max().unwrap_or(0);
+    let tied = candidates.iter().filter(|(_, page)| votes(page) == best_votes);
+    let distance = |page: &[u8]| match reference {
+        Some(reference) => (pe_utils::entropy(page) - reference).abs(),
+        // Without a reference the least random page is the most likely to be decrypted
+        None => pe_utils::entropy(page),
+    };
+    *tied
+        .min_by(|(_, left), (_, right)| distance(left).total_cmp(&distance(right)))
+        .unwrap_or(&candidates[0])
+}
+
+/// (first page, page count) of every section
+type PageRange = (usize, usize);
+
+fn section_contains(section: &PageRange, index: usize) -> bool {
+    index >= section.0 && index < section.0 + section.1
+}
+
+fn agreed_entropy(image: &[u8], pages: &[PageSource], section: &PageRange) -> Option<f32> {
+    let mut entropies = (section.0..(section.0 + section.1).min(pages.len()))
+        .filter(|index| matches!(pages[*index], PageSource::Agreed(_)))
+        .map(|index| {
+            let start = index * PAGE_SIZE;
+            pe_utils::entropy(&image[start..(start + PAGE_SIZE).min(image.len())])
+        })
+        .collect::<Vec<f32>>();
+    if entropies.is_empty() {
+        return None;
+    }
+    entropies.sort_by(f32::total_cmp);
+    Some(entropies[entropies.len() / 2])
+}
+
+fn page_range(section: &SectionTable) -> PageRange {
+    let size = section.virtual_size.max(section.size_of_raw_data) as usize;
+    (section.virtual_address as usize / PAGE_SIZE, size.div_ceil(PAGE_SIZE))
+}
+
+fn section_tables(image: &[u8], dumps: &[&[u8]]) -> Vec<SectionTable> {
+    std::iter::once(image)
+        .chain(dumps.iter().copied())
+        .find_map(|data| {
+            let header = pe_utils::parse_headers(data).ok()?;
+            pe_utils::get_sections(&header, data).ok()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA};
+    use pe_utils::{PeBuilder, PeImage};
+
+    use super::*;
+
+    /// Memory layout dump with two pages of `.text`, a page of `.data` and a zeroed page of `.bss`
+    fn build_dump() -> Vec<u8> {
+        let text = b"\x48\x89\x5C\x24\x08\xC3".iter().copied().cycle().take(2 * PAGE_SIZE).collect::<Vec<u8>>();
+        let data = b"abcdefgh".iter().copied().cycle().take(PAGE_SIZE).collect::<Vec<u8>>();
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", text, IMAGE_SCN_CNT_CODE).unwrap();
+        builder.add_section(".data", data, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.add_section(".bss", vec![0; PAGE_SIZE], IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        let mut dump = vec![0u8; image.optional_header().windows_fields.size_of_image as usize];
+        let size_of_headers = image.optional_header().windows_fields.size_of_headers as usize;
+        dump[0..size_of_headers].copy_from_slice(&image.data()[0..size_of_headers]);
+        for section in image.sections() {
+            let data = image.section_data(section);
+            let start = section.virtual_address as usize;
+            dump[start..start + data.len()].copy_from_slice(data);
+        }
+        dump
+    }
+
+    #[test]
+    fn picks_captured_and_decrypted_pages() {
+        let original = build_dump();
+        assert_eq!(original.len(), 5 * PAGE_SIZE);
+
+        // The first dump missed the second page of .text, the second missed the first one and
+        // still holds .data encrypted
+        let mut first = original.clone();
+        first[0x2000..0x3000].fill(0);
+        let mut second = original.clone();
+        second[0x1000..0x2000].fill(0);
+        let mut state = 0x1234_5678u32;
+        for byte in &mut second[0x3000..0x4000] {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *byte = (state >> 16) as u8;
+        }
+
+        let report = merge_dumps(&[first, second]).unwrap();
+        assert_eq!(report.pages, [
+            PageSource::Agreed(0),
+            PageSource::Agreed(0),
+            PageSource::Agreed(1),
+            PageSource::Conflicting(0),
+            PageSource::Missing,
+        ]);
+        assert_eq!(report.image, original);
+        assert_eq!((report.covered_pages(), report.conflicting_pages()), (4, 1));
+
+        let coverage = report.sections.iter()
+            .map(|section| (section.name.as_str(), section.pages, section.covered, section.conflicting))
+            .collect::<Vec<_>>();
+        assert_eq!(coverage, [(".text", 2, 2, 0), (".data", 1, 1, 1), (".bss", 1, 0, 0)]);
+        assert_eq!(report.sections[2].ratio(), 0.0);
+    }
+
+    #[test]
+    fn requires_a_dump() {
+        assert!(matches!(merge_dumps::<&[u8]>(&[]), Err(Error::NoDumps)));
+    }
+}
@@ -0,0 +1,459 @@
+use log::{info, warn};
+use scroll::{Pread, LE};
+
+use crate::headers::RegionHint;
+use crate::source::MemoryRegion;
+use crate::Error;
+
+pub const MINIDUMP_SIGNATURE: u32 = 0x504D_444D;
+
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const MEMORY64_LIST_STREAM: u32 = 9;
+const MEMORY_INFO_LIST_STREAM: u32 = 16;
+
+const SIZEOF_DIRECTORY: usize = 12;
+const SIZEOF_MODULE: usize = 108;
+const SIZEOF_MEMORY_DESCRIPTOR: usize = 16;
+const SIZEOF_MEMORY_DESCRIPTOR64: usize = 16;
+const SIZEOF_MEMORY_INFO: usize = 48;
+
+const MEM_COMMIT: u32 = 0x1000;
+const PAGE_NOACCESS: u32 = 0x01;
+const PAGE_EXECUTE_FLAGS: u32 = 0x10 | 0x20 | 0x40 | 0x80;
+const PAGE_WRITE_FLAGS: u32 = 0x04 | 0x08 | 0x40 | 0x80;
+
+#[derive(Debug, Clone)]
+pub struct MinidumpModule {
+    pub name: String,
+    pub base: u64,
+    pub size: u32,
+    pub checksum: u32,
+    pub time_date_stamp: u32,
+}
+
+impl MinidumpModule {
+    /// File name part of the module path, minidumps store the full Windows path
+    pub fn file_name(&self) -> &str {
+        self.name.rsplit(['\\', '/']).next().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MemoryRange {
+    start: u64,
+    size: u64,
+    offset: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MemoryInfo {
+    base: u64,
+    size: u64,
+    state: u32,
+    protect: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractedModule {
+    pub name: String,
+    pub base: u64,
+    pub image: Vec<u8>,
+    /// Bytes of the module not captured by the minidump, zero filled in `image`
+    pub missing: usize,
+}
+
+pub struct Minidump<'a> {
+    data: &'a [u8],
+    modules: Vec<MinidumpModule>,
+    ranges: Vec<MemoryRange>,
+    memory_info: Vec<MemoryInfo>,
+}
+
+impl<'a> Minidump<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let signature: u32 = data.pread_with(0, LE)?;
+        if signature != MINIDUMP_SIGNATURE {
+            return Err(Error::InvalidMinidump);
+        }
+        let number_of_streams: u32 = data.pread_with(8, LE)?;
+        let stream_directory: u32 = data.pread_with(12, LE)?;
+
+        let mut minidump = Minidump {
+            data,
+            modules: vec![],
+            ranges: vec![],
+            memory_info: vec![],
+        };
+        for index in 0..number_of_streams as usize {
+            let entry = stream_directory as usize + index * SIZEOF_DIRECTORY;
+            let stream_type: u32 = data.pread_with(entry, LE)?;
+            let stream_rva: u32 = data.pread_with(entry + 8, LE)?;
+            match stream_type {
+                MODULE_LIST_STREAM => minidump.parse_module_list(stream_rva as usize)?,
+                MEMORY_LIST_STREAM => minidump.parse_memory_list(stream_rva as usize)?,
+                MEMORY64_LIST_STREAM => minidump.parse_memory64_list(stream_rva as usize)?,
+                MEMORY_INFO_LIST_STREAM => minidump.parse_memory_info_list(stream_rva as usize)?,
+                _ => {}
+            }
+        }
+        minidump.ranges.sort_by_key(|range| range.start);
+
+        info!("Minidump parsed, {} modules and {} memory ranges", minidump.modules.len(), minidump.ranges.len());
+        Ok(minidump)
+    }
+
+    pub fn modules(&self) -> &[MinidumpModule] {
+        &self.modules
+    }
+
+    /// Looks a module up by file name or full path, ignoring case
+    pub fn find_module(&self, name: &str) -> Option<&MinidumpModule> {
+        self.modules.iter().find(|module| {
+            module.file_name().eq_ignore_ascii_case(name) || module.name.eq_ignore_ascii_case(name)
+        })
+    }
+
+    /// Copies `buffer.len()` bytes starting at `address`, returning how many of them the dump did
+    /// not capture. Missing bytes are left untouched
+    pub fn read_memory(&self, address: u64, buffer: &mut [u8]) -> usize {
+        let end = address.saturating_add(buffer.len() as u64);
+        let mut captured = 0;
+        // Sorted by start, everything before `filled` that a range covers was already copied by an
+        // earlier one. Overlapping descriptors would otherwise be counted twice
+        let mut filled = address;
+        // Ranges were checked to fit both the address space and the file when they were parsed
+        for range in &self.ranges {
+            let range_end = range.start + range.size;
+            if range_end <= filled || range.start >= end {
+                continue;
+            }
+            let copy_start = range.start.max(filled);
+            let copy_end = range_end.min(end);
+            filled = copy_end;
+            let source = (range.offset + (copy_start - range.start)) as usize;
+            let length = (copy_end - copy_start) as usize;
+            let destination = (copy_start - address) as usize;
+            buffer[destination..destination + length].copy_from_slice(&self.data[source..source + length]);
+            captured += length;
+        }
+        buffer.len().saturating_sub(captured)
+    }
+
+    /// How many bytes from `address` on are captured without a gap, up to `length`
+    pub fn captured_prefix(&self, address: u64, length: usize) -> usize {
+        let end = address.saturating_add(length as u64);
+        let mut covered = address;
+        // Sorted by start, once a range starts past the covered end nothing later can extend it
+        for range in &self.ranges {
+            if range.start > covered || covered >= end {
+                break;
+            }
+            covered = covered.max(range.start + range.size);
+        }
+        (covered.min(end) - address) as usize
+    }
+
+    pub fn extract_module(&self, name: &str) -> Result<ExtractedModule, Error> {
+        let module = self.find_module(name).ok_or_else(|| Error::ModuleNotFound(name.to_string()))?;
+        let mut image = vec![0; module.size as usize];
+        let missing = self.read_memory(module.base, &mut image);
+        if missing != 0 {
+            warn!("{} bytes of {} are missing from the minidump", missing, module.file_name());
+        }
+        Ok(ExtractedModule {
+            name: module.file_name().to_string(),
+            base: module.base,
+            image,
+            missing,
+        })
+    }
+
+    /// Page protections of the module's committed regions, when the dump has a memory info stream
+    pub fn region_hints(&self, module: &MinidumpModule) -> Vec<RegionHint> {
+        let module_end = module.base.saturating_add(module.size as u64);
+        self.memory_info.iter()
+            .filter(|info| info.state == MEM_COMMIT && info.base >= module.base && info.base < module_end)
+            .map(|info| RegionHint {
+                rva: (info.base - module.base) as u32,
+                size: info.size.min(module_end - info.base) as u32,
+                executable: info.protect & PAGE_EXECUTE_FLAGS != 0,
+                writable: info.protect & PAGE_WRITE_FLAGS != 0,
+            })
+            .collect()
+    }
+
+    /// Region containing `address`, from the memory info stream when present and from the captured
+    /// ranges otherwise
+    pub fn memory_region(&self, address: u64) -> Option<MemoryRegion> {
+        if !self.memory_info.is_empty() {
+            return self.memory_info.iter()
+                .find(|info| info.state == MEM_COMMIT && address >= info.base && address - info.base < info.size)
+                .map(|info| MemoryRegion {
+                    base: info.base,
+                    size: info.size,
+                    readable: info.protect & PAGE_NOACCESS == 0,
+                    writable: info.protect & PAGE_WRITE_FLAGS != 0,
+                    executable: info.protect & PAGE_EXECUTE_FLAGS != 0,
+                });
+        }
+        self.ranges.iter()
+            .find(|range| address >= range.start && address < range.start + range.size)
+            .map(|range| MemoryRegion {
+                base: range.start,
+                size: range.size,
+                readable: true,
+                writable: false,
+                executable: false,
+            })
+    }
+
+    fn parse_module_list(&mut self, offset: usize) -> Result<(), Error> {
+        let number_of_modules: u32 = self.data.pread_with(offset, LE)?;
+        for index in 0..number_of_modules as usize {
+            let entry = offset + 4 + index * SIZEOF_MODULE;
+            let name_rva: u32 = self.data.pread_with(entry + 20, LE)?;
+            self.modules.push(MinidumpModule {
+                name: self.read_string(name_rva as usize)?,
+                base: self.data.pread_with(entry, LE)?,
+                size: self.data.pread_with(entry + 8, LE)?,
+                checksum: self.data.pread_with(entry + 12, LE)?,
+                time_date_stamp: self.data.pread_with(entry + 16, LE)?,
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_memory_list(&mut self, offset: usize) -> Result<(), Error> {
+        let number_of_ranges: u32 = self.data.pread_with(offset, LE)?;
+        for index in 0..number_of_ranges as usize {
+            let entry = offset + 4 + index * SIZEOF_MEMORY_DESCRIPTOR;
+            let size: u32 = self.data.pread_with(entry + 8, LE)?;
+            let rva: u32 = self.data.pread_with(entry + 12, LE)?;
+            let start = self.data.pread_with(entry, LE)?;
+            self.push_range(MemoryRange { start, size: size as u64, offset: rva as u64 });
+        }
+        Ok(())
+    }
+
+    fn parse_memory64_list(&mut self, offset: usize) -> Result<(), Error> {
+        let number_of_ranges: u64 = self.data.pread_with(offset, LE)?;
+        // Full memory dumps store all ranges back to back starting at the base RVA
+        let mut data_offset: u64 = self.data.pread_with(offset + 8, LE)?;
+        for index in 0..number_of_ranges as usize {
+            let entry = offset + 16 + index * SIZEOF_MEMORY_DESCRIPTOR64;
+            let size: u64 = self.data.pread_with(entry + 8, LE)?;
+            let start = self.data.pread_with(entry, LE)?;
+            self.push_range(MemoryRange { start, size, offset: data_offset });
+            data_offset = data_offset.saturating_add(size);
+        }
+        Ok(())
+    }
+
+    fn parse_memory_info_list(&mut self, offset: usize) -> Result<(), Error> {
+        let size_of_header: u32 = self.data.pread_with(offset, LE)?;
+        let size_of_entry: u32 = self.data.pread_with(offset + 4, LE)?;
+        let number_of_entries: u64 = self.data.pread_with(offset + 8, LE)?;
+        // Smaller entries would be read over and over without ever running out of the file
+        if (size_of_entry as usize) < SIZEOF_MEMORY_INFO {
+            warn!("Memory info entries of {} bytes are too small, ignoring the stream", size_of_entry);
+            return Ok(());
+        }
+        for index in 0..number_of_entries as usize {
+            let entry = offset + size_of_header as usize + index * size_of_entry as usize;
+            self.memory_info.push(MemoryInfo {
+                base: self.data.pread_with(entry, LE)?,
+                size: self.data.pread_with(entry + 24, LE)?,
+                state: self.data.pread_with(entry + 32, LE)?,
+                protect: self.data.pread_with(entry + 36, LE)?,
+            });
+        }
+        Ok(())
+    }
+
+    /// Keeps a captured range only when it fits the address space and the file
+    fn push_range(&mut self, range: MemoryRange) {
+        let fits_address_space = range.start.checked_add(range.size).is_some();
+        let fits_file = range.offset.checked_add(range.size).is_some_and(|end| end <= self.data.len() as u64);
+        match fits_address_space && fits_file {
+            true => self.ranges.push(range),
+            false => warn!("Memory range at 0x{:X} (0x{:X} bytes) points past the end of the minidump, skipping it",
+                           range.start, range.size),
+        }
+    }
+
+    fn read_string(&self, offset: usize) -> Result<String, Error> {
+        let length: u32 = self.data.pread_with(offset, LE)?;
+        let start = offset + 4;
+        let end = start.saturating_add(length as usize);
+        if end > self.data.len() {
+            return Err(Error::OutOfBounds(start, end));
+        }
+        let utf16 = self.data[start..end]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect::<Vec<u16>>();
+        Ok(String::from_utf16_lossy(&utf16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scroll::Pwrite;
+
+    use super::*;
+    use crate::source::{read_zero_filled, MemorySource};
+
+    const BASE: u64 = 0x7FF6_1000_0000;
+    const MODULE_LIST: usize = 0x50;
+    const MODULE_NAME: usize = 0x100;
+    const MEMORY_INFO_LIST: usize = 0x130;
+    const MEMORY64_LIST: usize = 0x1B0;
+    const MEMORY_DATA: usize = 0x200;
+
+    /// A minidump of one 0x3000 byte module with the first page, half of the third and its last
+    /// 0x400 bytes captured, plus a range pointing past the end of the file
+    fn build_minidump() -> Vec<u8> {
+        build_minidump_with(&[(0, 0x1000, 0x11), (0x2000, 0x800, 0x22), (0x2C00, 0x400, 0x33)])
+    }
+
+    /// The same module with `captured` ranges of (RVA, size, fill byte), in that order in the file
+    fn build_minidump_with(captured: &[(u64, u64, u8)]) -> Vec<u8> {
+        let mut data = vec![0u8; MEMORY_DATA];
+        data.pwrite_with(MINIDUMP_SIGNATURE, 0, LE).unwrap();
+        data.pwrite_with(3u32, 8, LE).unwrap();
+        data.pwrite_with(32u32, 12, LE).unwrap();
+        for (index, (kind, rva)) in [
+            (MODULE_LIST_STREAM, MODULE_LIST),
+            (MEMORY_INFO_LIST_STREAM, MEMORY_INFO_LIST),
+            (MEMORY64_LIST_STREAM, MEMORY64_LIST),
+        ].into_iter().enumerate() {
+            data.pwrite_with(kind, 32 + index * SIZEOF_DIRECTORY, LE).unwrap();
+            data.pwrite_with(rva as u32, 32 + index * SIZEOF_DIRECTORY + 8, LE).unwrap();
+        }
+
+        data.pwrite_with(1u32, MODULE_LIST, LE).unwrap();
+        data.pwrite_with(BASE, MODULE_LIST + 4, LE).unwrap();
+        data.pwrite_with(0x3000u32, MODULE_LIST + 12, LE).unwrap();
+        data.pwrite_with(0x6543_2100u32, MODULE_LIST + 20, LE).unwrap();
+        data.pwrite_with(MODULE_NAME as u32, MODULE_LIST + 24, LE).unwrap();
+        let name = "C:\\Games\\Game.exe".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+        data.pwrite_with(name.len() as u32, MODULE_NAME, LE).unwrap();
+        data[MODULE_NAME + 4..MODULE_NAME + 4 + name.len()].copy_from_slice(&name);
+
+        data.pwrite_with(16u32, MEMORY_INFO_LIST, LE).unwrap();
+        data.pwrite_with(SIZEOF_MEMORY_INFO as u32, MEMORY_INFO_LIST + 4, LE).unwrap();
+        data.pwrite_with(2u64, MEMORY_INFO_LIST + 8, LE).unwrap();
+        for (index, (rva, size, protect)) in [(0u64, 0x1000u64, 0x20u32), (0x1000, 0x2000, 0x04)].into_iter().enumerate() {
+            let entry = MEMORY_INFO_LIST + 16 + index * SIZEOF_MEMORY_INFO;
+            data.pwrite_with(BASE + rva, entry, LE).unwrap();
+            data.pwrite_with(size, entry + 24, LE).unwrap();
+            data.pwrite_with(MEM_COMMIT, entry + 32, LE).unwrap();
+            data.pwrite_with(protect, entry + 36, LE).unwrap();
+        }
+
+        let mut ranges = captured.to_vec();
+        ranges.push((0x10000, 0x10_0000, 0));
+        data.pwrite_with(ranges.len() as u64, MEMORY64_LIST, LE).unwrap();
+        data.pwrite_with(MEMORY_DATA as u64, MEMORY64_LIST + 8, LE).unwrap();
+        for (index, (rva, size, _)) in ranges.iter().enumerate() {
+            let entry = MEMORY64_LIST + 16 + index * SIZEOF_MEMORY_DESCRIPTOR64;
+            data.pwrite_with(BASE + rva, entry, LE).unwrap();
+            data.pwrite_with(*size, entry + 8, LE).unwrap();
+        }
+        for (_, size, fill) in captured {
+            data.extend(std::iter::repeat_n(*fill, *size as usize));
+        }
+        data
+    }
+
+    #[test]
+    fn lists_and_finds_modules() {
+        let data = build_minidump();
+        let minidump = Minidump::parse(&data).unwrap();
+
+        assert_eq!(minidump.modules().len(), 1);
+        let module = &minidump.modules()[0];
+        assert_eq!((module.file_name(), module.base, module.size), ("Game.exe", BASE, 0x3000));
+        assert_eq!(module.time_date_stamp, 0x6543_2100);
+        assert!(minidump.find_module("game.EXE").is_some());
+        assert!(minidump.find_module("c:\\games\\game.exe").is_some());
+        assert!(minidump.find_module("Game").is_none());
+        assert!(matches!(minidump.extract_module("ntdll.dll"), Err(Error::ModuleNotFound(_))));
+        assert!(matches!(Minidump::parse(&[0; 32]), Err(Error::InvalidMinidump)));
+    }
+
+    #[test]
+    fn reads_captured_ranges_and_reports_gaps() {
+        let data = build_minidump();
+        let minidump = Minidump::parse(&data).unwrap();
+
+        let mut buffer = [0xEE; 0x20];
+        assert_eq!(minidump.read_memory(BASE + 0xFF0, &mut buffer), 0x10);
+        assert_eq!(buffer[0..0x10], [0x11; 0x10]);
+        assert_eq!(buffer[0x10..], [0xEE; 0x10]);
+
+        let mut buffer = vec![0; 0x500];
+        assert_eq!(minidump.read_memory(BASE + 0x2780, &mut buffer), 0x400);
+        assert_eq!(buffer[0x7F], 0x22);
+        assert_eq!(buffer[0x480], 0x33);
+        assert_eq!(minidump.captured_prefix(BASE + 0x2780, 0x500), 0x80);
+        assert_eq!(minidump.captured_prefix(BASE, 0x1000), 0x1000);
+        assert_eq!(minidump.captured_prefix(BASE + 0x1000, 0x10), 0);
+
+        // The range past the end of the file was dropped
+        assert_eq!(minidump.captured_prefix(BASE + 0x10000, 0x10), 0);
+    }
+
+    #[test]
+    fn keeps_bytes_captured_past_a_gap() {
+        let data = build_minidump();
+        let minidump = Minidump::parse(&data).unwrap();
+
+        let mut page = [0u8; 0x1000];
+        assert_eq!(minidump.read(BASE + 0x2000, &mut page).unwrap(), 0x800);
+        let (page, missing) = read_zero_filled(&minidump, BASE + 0x2000, 0x1000).unwrap();
+        assert_eq!(missing, 0x800);
+        assert_eq!(page[0x7FF], 0x22);
+        assert_eq!(page[0x800..0xC00], [0; 0x400]);
+        assert_eq!(page[0xC00..], [0x33; 0x400]);
+    }
+
+    #[test]
+    fn extracts_modules_with_memory_info() {
+        let data = build_minidump();
+        let minidump = Minidump::parse(&data).unwrap();
+
+        let module = minidump.extract_module("Game.exe").unwrap();
+        assert_eq!((module.name.as_str(), module.base), ("Game.exe", BASE));
+        assert_eq!(module.image.len(), 0x3000);
+        assert_eq!(module.missing, 0x3000 - 0x1C00);
+        assert_eq!((module.image[0], module.image[0x1000], module.image[0x2000]), (0x11, 0, 0x22));
+
+        let hints = minidump.region_hints(&minidump.modules()[0]);
+        assert_eq!(hints.len(), 2);
+        assert!(hints[0].executable && !hints[0].writable);
+        assert_eq!((hints[1].rva, hints[1].size), (0x1000, 0x2000));
+        let region = minidump.memory_region(BASE + 0x1800).unwrap();
+        assert_eq!((region.base, region.size, region.writable), (BASE + 0x1000, 0x2000, true));
+        assert!(minidump.memory_region(BASE + 0x3000).is_none());
+    }
+
+    #[test]
+    fn counts_overlapping_ranges_once() {
+        // The second range starts inside the first, the third lies entirely within it
+        let data = build_minidump_with(&[(0, 0x1000, 0x11), (0x800, 0x1000, 0x22), (0x100, 0x100, 0x33)]);
+        let minidump = Minidump::parse(&data).unwrap();
+
+        let mut buffer = vec![0xEE; 0x2000];
+        assert_eq!(minidump.read_memory(BASE, &mut buffer), 0x800);
+        assert_eq!(buffer[0x100], 0x11);
+        assert_eq!(buffer[0xFFF], 0x11);
+        assert_eq!(buffer[0x1000..0x1800], [0x22; 0x800]);
+        assert_eq!(buffer[0x1800..], [0xEE; 0x800]);
+
+        let mut buffer = vec![0xEE; 0x400];
+        assert_eq!(minidump.read_memory(BASE + 0xE00, &mut buffer), 0);
+        assert_eq!(minidump.extract_module("Game.exe").unwrap().missing, 0x1800);
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::OnceLock;
+
+use goblin::pe::section_table::{SectionTable, IMAGE_SCN_MEM_EXECUTE};
+use log::{info, warn};
+use pe_utils::PeImage;
+use scroll::{Pread, Pwrite, LE};
+
+use crate::Error;
+
+const OPTIONAL_HEADER_ADDRESS_OF_ENTRY_POINT: usize = 16;
+// Offsets of the `jmp __scrt_common_main_seh` inside the x64 and x86 CRT startup stubs
+const CRT_STARTUP_64_JMP: usize = 13;
+const CRT_STARTUP_32_JMP: usize = 5;
+
+const CRT_STARTUP_64: usize = 0;
+const CRT_STARTUP_32: usize = 1;
+const SCRT_COMMON_MAIN_SEH_64: usize = 2;
+const SCRT_COMMON_MAIN_SEH_32: usize = 3;
+const TMAIN_CRT_STARTUP_64: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OepKind {
+    /// `mainCRTStartup`/`WinMainCRTStartup`, which calls `__security_init_cookie` and jumps to
+    /// `__scrt_common_main_seh`
+    CrtStartup,
+    /// `__scrt_common_main_seh` itself (`__tmainCRTStartup` before Visual Studio 2015), reached
+    /// from the real entry point
+    ScrtCommonMainSeh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OepCandidate {
+    pub rva: u32,
+    pub kind: OepKind,
+    /// The startup stub jumps to a recognised `__scrt_common_main_seh`
+    pub confirmed: bool,
+}
+
+fn get_oep_locator() -> &'static offset_finder::OffsetLocator<'static> {
+    static OEP_LOCATOR: OnceLock<offset_finder::OffsetLocator<'static>> = OnceLock::new();
+    OEP_LOCATOR.get_or_init(|| offset_finder::OffsetLocator {
+        name: "OEP",
+        partial_match: vec![
+            // sub rsp, 28h; call __security_init_cookie; add rsp, 28h; jmp __scrt_common_main_seh
+            "48 83 ec 28 e8 ?? ?? ?? ?? 48 83 c4 28 e9 ?? ?? ?? ??",
+            // call ___security_init_cookie; jmp ___scrt_common_main_seh
+            "e8 ?? ?? ?? ?? e9 ?? ?? ?? ?? cc",
+            // mov [rsp+8], rbx; mov [rsp+10h], rsi; push rdi; sub rsp, 30h; mov ecx, 1; call __scrt_initialize_crt
+            "48 89 5c 24 08 48 89 74 24 10 57 48 83 ec 30 b9 01 00 00 00 e8 ?? ?? ?? ?? 84 c0",
+            // push 14h; push offset; call __SEH_prolog4; push 1; call ___scrt_initialize_crt
+            "6a 14 68 ?? ?? ?? ?? e8 ?? ?? ?? ?? 6a 01 e8 ?? ?? ?? ?? 59 84 c0",
+            // mov [rsp+10h], rbx; push rdi; sub rsp, 30h; mov eax, 'MZ' (__tmainCRTStartup, VS2012/2013)
+            "48 89 5c 24 10 57 48 83 ec 30 b8 4d 5a 00 00",
+        ],
+        full_match: "",
+        skip_offset_print: true,
+        allow_multiple_matches: true,
+    })
+}
+
+/// Suggests original entry point candidates in a restored executable (file layout) by looking for
+/// the MSVC CRT startup code. Confirmed startup stubs come first. Like [`set_entry_point`] any
+/// section is accepted, protectors often strip the execute flag from the one holding the code
+pub fn find_oep_candidates(image: &PeImage) -> Result<Vec<OepCandidate>, Error> {
+    let sections = image.sections();
+    let is_64 = image.is_64();
+    let image_base = image.image_base() as usize;
+
+    let matches = match get_oep_locator().find_all_partial_only(image) {
+        Ok(matches) => matches,
+        Err(offset_finder::Error::NotFound(_)) => return Ok(vec![]),
+        Err(err) => return Err(Error::OffsetFinder(err)),
+    };
+    let rvas = |pattern: usize| -> Vec<(usize, u32)> {
+        matches.get(&pattern)
+            .map(|items| items.iter().map(|(offset, va)| (*offset, (va - image_base) as u32)).collect())
+            .unwrap_or_default()
+    };
+    let (startup, startup_jmp, common_main) = match is_64 {
+        true => (CRT_STARTUP_64, CRT_STARTUP_64_JMP, [SCRT_COMMON_MAIN_SEH_64, TMAIN_CRT_STARTUP_64].as_slice()),
+        false => (CRT_STARTUP_32, CRT_STARTUP_32_JMP, [SCRT_COMMON_MAIN_SEH_32].as_slice()),
+    };
+    let common_main = common_main.iter()
+        .flat_map(|pattern| rvas(*pattern))
+        .map(|(_, rva)| rva)
+        .filter(|rva| section_containing(sections, *rva).is_some())
+        .collect::<Vec<u32>>();
+
+    let mut candidates = vec![];
+    for (offset, rva) in rvas(startup) {
+        if section_containing(sections, rva).is_none() {
+            continue;
+        }
+        let jmp = offset + startup_jmp;
+        let displacement: i32 = image.data().pread_with(jmp + 1, LE)?;
+        let target = (rva as i64 + startup_jmp as i64 + 5 + displacement as i64) as u32;
+        let confirmed = common_main.contains(&target);
+        // The bare x86 stub is too generic to report unless it leads somewhere known
+        if !is_64 && !confirmed {
+            continue;
+        }
+        candidates.push(OepCandidate { rva, kind: OepKind::CrtStartup, confirmed });
+    }
+    for rva in common_main {
+        candidates.push(OepCandidate { rva, kind: OepKind::ScrtCommonMainSeh, confirmed: false });
+    }
+    candidates.sort_by_key(|candidate| (!candidate.confirmed, candidate.kind, candidate.rva));
+    candidates.dedup_by_key(|candidate| candidate.rva);
+
+    info!("Found {} OEP candidates", candidates.len());
+    Ok(candidates)
+}
+
+/// Patches `AddressOfEntryPoint`, returning the previous value. The RVA only has to fall inside
+/// the image, protectors often strip the execute flag from the section holding the real entry
+/// point
+pub fn set_entry_point(image: &mut [u8], rva: u32) -> Result<u32, Error> {
+    let header = pe_utils::parse_headers(image)?;
+    let optional_header = pe_utils::get_optional_headers(&header)?;
+    if rva >= optional_header.windows_fields.size_of_image {
+        return Err(Error::InvalidEntryPoint(rva));
+    }
+    let sections = pe_utils::get_sections(&header, image)?;
+    let executable = section_containing(&sections, rva)
+        .is_some_and(|section| section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0);
+    if !executable {
+        warn!("Entry point 0x{:X} is not inside an executable section", rva);
+    }
+    let offset = crate::optional_header_offset(&header) + OPTIONAL_HEADER_ADDRESS_OF_ENTRY_POINT;
+
+    let previous: u32 = image.pread_with(offset, LE)?;
+    image.pwrite_with(rva, offset, LE)?;
+    info!("Entry point moved from 0x{:X} to 0x{:X}", previous, rva);
+    Ok(previous)
+}
+
+fn section_containing(sections: &[SectionTable], rva: u32) -> Option<&SectionTable> {
+    sections.iter().find(|section| {
+        let size = section.virtual_size.max(section.size_of_raw_data);
+        rva >= section.virtual_address && (rva as u64) < section.virtual_address as u64 + size as u64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::{IMAGE_SCN_CNT_CODE, IMAGE_SCN_MEM_READ};
+    use pe_utils::PeBuilder;
+
+    use super::*;
+
+    const STARTUP: [u8; 18] = [0x48, 0x83, 0xEC, 0x28, 0xE8, 0, 0, 0, 0, 0x48, 0x83, 0xC4, 0x28, 0xE9, 0, 0, 0, 0];
+    const COMMON_MAIN: [u8; 27] = [
+        0x48, 0x89, 0x5C, 0x24, 0x08, 0x48, 0x89, 0x74, 0x24, 0x10, 0x57, 0x48, 0x83, 0xEC, 0x30,
+        0xB9, 0x01, 0x00, 0x00, 0x00, 0xE8, 0, 0, 0, 0, 0x84, 0xC0,
+    ];
+
+    /// x64 image whose `.text` had its execute flag stripped: the CRT startup stub at 0x1000
+    /// jumps to `__scrt_common_main_seh` at 0x1100, a second stub at 0x1200 jumps nowhere known
+    fn build_image(characteristics: u32) -> Vec<u8> {
+        let mut text = vec![0xCC; 0x300];
+        text[0..STARTUP.len()].copy_from_slice(&STARTUP);
+        text.pwrite_with(0x100i32 - (CRT_STARTUP_64_JMP as i32 + 5), CRT_STARTUP_64_JMP + 1, LE).unwrap();
+        text[0x100..0x100 + COMMON_MAIN.len()].copy_from_slice(&COMMON_MAIN);
+        text[0x200..0x200 + STARTUP.len()].copy_from_slice(&STARTUP);
+
+        let mut builder = PeBuilder::new(true, 0x1_4000_0000).unwrap();
+        builder.add_section(".text", text, characteristics).unwrap();
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn finds_startup_code_in_sections_without_the_execute_flag() {
+        for characteristics in [IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_READ, IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE] {
+            let image = PeImage::parse(build_image(characteristics)).unwrap();
+            assert_eq!(find_oep_candidates(&image).unwrap(), [
+                OepCandidate { rva: 0x1000, kind: OepKind::CrtStartup, confirmed: true },
+                OepCandidate { rva: 0x1200, kind: OepKind::CrtStartup, confirmed: false },
+                OepCandidate { rva: 0x1100, kind: OepKind::ScrtCommonMainSeh, confirmed: false },
+            ]);
+        }
+    }
+
+    #[test]
+    fn moves_the_entry_point_into_any_section() {
+        let mut image = build_image(IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_READ);
+        let candidates = find_oep_candidates(&PeImage::parse(image.clone()).unwrap()).unwrap();
+
+        assert_eq!(set_entry_point(&mut image, candidates[0].rva).unwrap(), 0);
+        assert_eq!(PeImage::parse(image.clone()).unwrap().optional_header().standard_fields.address_of_entry_point, 0x1000);
+        assert!(matches!(set_entry_point(&mut image, 0x10_0000), Err(Error::InvalidEntryPoint(0x10_0000))));
+    }
+}
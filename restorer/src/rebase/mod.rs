@@ -0,0 +1,187 @@
+use goblin::pe::optional_header::MAGIC_64;
+use log::{info, warn};
+use pe_utils::{
+    BaseRelocation, IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_DIR64, IMAGE_REL_BASED_HIGH, IMAGE_REL_BASED_HIGHADJ,
+    IMAGE_REL_BASED_HIGHLOW, IMAGE_REL_BASED_LOW,
+};
+use scroll::{Pread, Pwrite, LE};
+
+use crate::Error;
+
+const OPTIONAL_HEADER_IMAGE_BASE_32: usize = 28;
+const OPTIONAL_HEADER_IMAGE_BASE_64: usize = 24;
+const DATA_DIRECTORY_BASE_RELOCATION_TABLE: usize = 5;
+
+#[derive(Debug, Clone, Default)]
+pub struct RebaseReport {
+    pub from_base: u64,
+    pub to_base: u64,
+    pub applied: usize,
+    /// Page RVAs of relocation blocks that fall outside the dump
+    pub out_of_range_blocks: Vec<u32>,
+}
+
+/// Reverses the base relocations applied by the loader on a memory layout dump (RVA == offset).
+/// `target_base` defaults to the `ImageBase` stored in the headers, which is also rewritten. The
+/// loader usually updates that field to the load base, in which case the preferred base has to be
+/// taken from the original executable and passed explicitly
+pub fn rebase_dump(dump: &mut [u8],
+                   load_base: u64,
+                   target_base: Option<u64>) -> Result<RebaseReport, Error> {
+    let header = pe_utils::parse_headers(dump)?;
+    let optional_header = pe_utils::get_optional_headers(&header)?;
+    let is_64 = optional_header.standard_fields.magic == MAGIC_64;
+    let to_base = target_base.unwrap_or(optional_header.windows_fields.image_base);
+    let optional_header_offset = crate::optional_header_offset(&header);
+
+    if load_base == to_base {
+        return Err(Error::AlreadyAtBase(load_base));
+    }
+    let mut report = RebaseReport {
+        from_base: load_base,
+        to_base,
+        ..Default::default()
+    };
+
+    let directory = match optional_header.data_directories.get_base_relocation_table() {
+        Some(directory) if directory.virtual_address != 0 && directory.size != 0 => *directory,
+        _ => return Err(Error::MissingDataDirectory(DATA_DIRECTORY_BASE_RELOCATION_TABLE)),
+    };
+    let directory_start = directory.virtual_address as usize;
+    let directory_end = directory_start + directory.size as usize;
+    if directory_end > dump.len() {
+        return Err(Error::OutOfBounds(directory_start, directory_end));
+    }
+    let blocks = pe_utils::parse_base_relocations(&dump[directory_start..directory_end])?;
+
+    let delta = to_base.wrapping_sub(load_base);
+    for block in blocks {
+        if block.page_rva as usize >= dump.len() {
+            report.out_of_range_blocks.push(block.page_rva);
+            continue;
+        }
+        let mut out_of_range = false;
+        for entry in &block.entries {
+            let offset = block.page_rva as usize + entry.offset as usize;
+            match apply_relocation(dump, offset, entry, delta) {
+                Ok(true) => report.applied += 1,
+                Ok(false) => {}
+                Err(_) => out_of_range = true,
+            }
+        }
+        if out_of_range {
+            report.out_of_range_blocks.push(block.page_rva);
+        }
+    }
+
+    if is_64 {
+        dump.pwrite_with(to_base, optional_header_offset + OPTIONAL_HEADER_IMAGE_BASE_64, LE)?;
+    } else {
+        dump.pwrite_with(to_base as u32, optional_header_offset + OPTIONAL_HEADER_IMAGE_BASE_32, LE)?;
+    }
+
+    info!("Rebased dump from 0x{:X} to 0x{:X}, {} fixups applied, {} blocks out of range",
+        load_base, to_base, report.applied, report.out_of_range_blocks.len());
+    Ok(report)
+}
+
+fn apply_relocation(dump: &mut [u8], offset: usize, entry: &BaseRelocation, delta: u64) -> Result<bool, Error> {
+    match entry.kind {
+        IMAGE_REL_BASED_ABSOLUTE => return Ok(false),
+        IMAGE_REL_BASED_HIGH => {
+            let value: u16 = dump.pread_with(offset, LE)?;
+            dump.pwrite_with(value.wrapping_add((delta >> 16) as u16), offset, LE)?;
+        }
+        IMAGE_REL_BASED_LOW => {
+            let value: u16 = dump.pread_with(offset, LE)?;
+            dump.pwrite_with(value.wrapping_add(delta as u16), offset, LE)?;
+        }
+        IMAGE_REL_BASED_HIGHLOW => {
+            let value: u32 = dump.pread_with(offset, LE)?;
+            dump.pwrite_with(value.wrapping_add(delta as u32), offset, LE)?;
+        }
+        IMAGE_REL_BASED_HIGHADJ => {
+            let value: u16 = dump.pread_with(offset, LE)?;
+            let adjusted = ((value as u32) << 16)
+                .wrapping_add(entry.parameter as i16 as u32)
+                .wrapping_add(delta as u32)
+                .wrapping_add(0x8000);
+            dump.pwrite_with((adjusted >> 16) as u16, offset, LE)?;
+        }
+        IMAGE_REL_BASED_DIR64 => {
+            let value: u64 = dump.pread_with(offset, LE)?;
+            dump.pwrite_with(value.wrapping_add(delta), offset, LE)?;
+        }
+        kind => {
+            warn!("Unsupported relocation type {} at offset 0x{:X}", kind, offset);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_INITIALIZED_DATA;
+    use pe_utils::{PeBuilder, PeImage};
+
+    use super::*;
+
+    const PREFERRED_BASE: u64 = 0x1_4000_0000;
+    const LOAD_BASE: u64 = 0x7FF6_1230_0000;
+
+    /// Memory layout dump of an image loaded at `LOAD_BASE`: `.data` at 0x1000 holds two relocated
+    /// pointers and a plain value, `.reloc` lists the pointers, `ImageBase` was updated by the loader
+    fn build_dump() -> Vec<u8> {
+        let mut data = vec![0u8; 0x20];
+        data.pwrite_with(LOAD_BASE + 0x1010, 0, LE).unwrap();
+        data.pwrite_with(LOAD_BASE + 0x2000, 8, LE).unwrap();
+        data.pwrite_with(0x1234_5678u64, 16, LE).unwrap();
+        let mut reloc = vec![0u8; 16];
+        reloc.pwrite_with(0x1000u32, 0, LE).unwrap();
+        reloc.pwrite_with(16u32, 4, LE).unwrap();
+        for (index, entry) in [0xA000u16, 0xA008, 0, 0].iter().enumerate() {
+            reloc.pwrite_with(*entry, 8 + index * 2, LE).unwrap();
+        }
+
+        let mut builder = PeBuilder::new(true, LOAD_BASE).unwrap();
+        builder.add_section(".data", data, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        let reloc_rva = builder.add_section(".reloc", reloc, IMAGE_SCN_CNT_INITIALIZED_DATA).unwrap();
+        builder.set_data_directory(DATA_DIRECTORY_BASE_RELOCATION_TABLE, reloc_rva, 16).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        let mut dump = vec![0u8; image.optional_header().windows_fields.size_of_image as usize];
+        let size_of_headers = image.optional_header().windows_fields.size_of_headers as usize;
+        dump[0..size_of_headers].copy_from_slice(&image.data()[0..size_of_headers]);
+        for section in image.sections() {
+            let data = image.section_data(section);
+            let start = section.virtual_address as usize;
+            dump[start..start + data.len()].copy_from_slice(data);
+        }
+        dump
+    }
+
+    #[test]
+    fn moves_relocated_pointers_back_to_the_preferred_base() {
+        let mut dump = build_dump();
+        let report = rebase_dump(&mut dump, LOAD_BASE, Some(PREFERRED_BASE)).unwrap();
+
+        assert_eq!((report.from_base, report.to_base, report.applied), (LOAD_BASE, PREFERRED_BASE, 2));
+        assert!(report.out_of_range_blocks.is_empty());
+        assert_eq!(dump.pread_with::<u64>(0x1000, LE).unwrap(), PREFERRED_BASE + 0x1010);
+        assert_eq!(dump.pread_with::<u64>(0x1008, LE).unwrap(), PREFERRED_BASE + 0x2000);
+        assert_eq!(dump.pread_with::<u64>(0x1010, LE).unwrap(), 0x1234_5678);
+        let header = pe_utils::parse_headers(&dump).unwrap();
+        assert_eq!(pe_utils::get_optional_headers(&header).unwrap().windows_fields.image_base, PREFERRED_BASE);
+    }
+
+    #[test]
+    fn refuses_dumps_already_at_the_target_base() {
+        let mut dump = build_dump();
+        let original = dump.clone();
+        // The headers say LOAD_BASE, without an explicit target there is nothing to go back to
+        assert!(matches!(rebase_dump(&mut dump, LOAD_BASE, None), Err(Error::AlreadyAtBase(LOAD_BASE))));
+        assert!(matches!(rebase_dump(&mut dump, PREFERRED_BASE, Some(PREFERRED_BASE)), Err(Error::AlreadyAtBase(_))));
+        assert_eq!(dump, original);
+    }
+}
@@ -0,0 +1,63 @@
+use goblin::pe::section_table::SectionTable;
+
+/// What happened to a section while restoring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionStatus {
+    /// Copied in full
+    Copied,
+    /// The dump ends inside the section, only the bytes before its end were copied
+    Truncated,
+    /// The section has no data in the output
+    Empty,
+    Skipped(SkipReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The section lies outside the dump or the output
+    OutOfBounds,
+    /// The section starts past the end of the dump, it is present but zero filled
+    MissingFromDump,
+}
+
+#[derive(Debug, Clone)]
+pub struct SectionReport {
+    pub name: String,
+    pub virtual_address: u32,
+    pub pointer_to_raw_data: u32,
+    pub size_of_raw_data: u32,
+    pub bytes_copied: usize,
+    pub status: SectionStatus,
+}
+
+impl SectionReport {
+    pub(crate) fn new(section: &SectionTable, bytes_copied: usize, status: SectionStatus) -> Self {
+        SectionReport {
+            name: String::from_utf8_lossy(&section.name).trim_end_matches('\0').to_string(),
+            virtual_address: section.virtual_address,
+            pointer_to_raw_data: section.pointer_to_raw_data,
+            size_of_raw_data: section.size_of_raw_data,
+            bytes_copied,
+            status,
+        }
+    }
+}
+
+/// Result of a restore, the caller decides whether and where to save `image`
+#[derive(Debug, Clone, Default)]
+pub struct RestoreReport {
+    /// The restored executable, already cut at `eof`. Empty when it was streamed to a writer
+    pub image: Vec<u8>,
+    pub sections: Vec<SectionReport>,
+    /// End of the last section's raw data, the size of the restored file
+    pub eof: usize,
+    /// Bytes of the input that could not be read and were zero filled
+    pub missing: usize,
+}
+
+impl RestoreReport {
+    /// Sections that were skipped, truncated or left empty
+    pub fn incomplete_sections(&self) -> impl Iterator<Item = &SectionReport> {
+        self.sections.iter().filter(|section| section.status != SectionStatus::Copied)
+    }
+}
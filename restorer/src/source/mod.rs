@@ -0,0 +1,515 @@
+use goblin::pe::header::{PE_POINTER_OFFSET, SIZEOF_COFF_HEADER, SIZEOF_PE_MAGIC};
+use goblin::pe::section_table::SIZEOF_SECTION_TABLE;
+use log::{trace, warn};
+use scroll::{Pread, LE};
+
+use crate::headers::RegionHint;
+use crate::minidump::Minidump;
+use crate::Error;
+
+const PAGE_SIZE: u64 = 0x1000;
+// Reads are attempted in chunks first and only split into pages when a chunk comes back short
+const READ_CHUNK_SIZE: u64 = 0x10_0000;
+// The Windows loader refuses images of 2 GiB or more, anything larger comes from a forged or
+// wiped header and is not worth allocating
+const MAX_IMAGE_SIZE: u64 = 0x8000_0000;
+
+/// A contiguous range of memory sharing the same protection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub size: u64,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> u64 {
+        self.base + self.size
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.base && address < self.end()
+    }
+}
+
+/// Somewhere a module image can be read from, addressed by virtual address
+pub trait MemorySource {
+    /// Copies the memory at `address` into `buffer`, returning the length of the prefix that could
+    /// be read without a gap. Bytes that could not be read are left untouched, bytes past the gap
+    /// may still have been copied
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, Error>;
+
+    /// The region containing `address`, `None` when it is not mapped or the source can not tell
+    fn query_region(&self, address: u64) -> Option<MemoryRegion>;
+}
+
+/// A flat copy of memory that was mapped at `base`
+pub struct BufferSource<'a> {
+    pub base: u64,
+    pub data: &'a [u8],
+}
+
+impl<'a> BufferSource<'a> {
+    pub fn new(base: u64, data: &'a [u8]) -> Self {
+        BufferSource { base, data }
+    }
+}
+
+impl MemorySource for BufferSource<'_> {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        if address < self.base || address - self.base >= self.data.len() as u64 {
+            return Ok(0);
+        }
+        let start = (address - self.base) as usize;
+        let length = buffer.len().min(self.data.len() - start);
+        buffer[0..length].copy_from_slice(&self.data[start..start + length]);
+        Ok(length)
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        let region = MemoryRegion {
+            base: self.base,
+            size: self.data.len() as u64,
+            readable: true,
+            writable: false,
+            executable: false,
+        };
+        region.contains(address).then_some(region)
+    }
+}
+
+impl MemorySource for Minidump<'_> {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.read_memory(address, buffer);
+        Ok(self.captured_prefix(address, buffer.len()))
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.memory_region(address)
+    }
+}
+
+/// Memory of the current process
+#[cfg(windows)]
+pub struct InProcessMemory;
+
+#[cfg(windows)]
+impl InProcessMemory {
+    pub fn new() -> Result<Self, Error> {
+        Ok(InProcessMemory)
+    }
+}
+
+#[cfg(windows)]
+impl MemorySource for InProcessMemory {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        use windows_sys::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+        use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+        // ReadProcessMemory fails instead of faulting on unmapped or guarded pages, a partial
+        // copy reports failure along with the bytes it did read
+        let mut read = 0usize;
+        unsafe {
+            ReadProcessMemory(GetCurrentProcess(),
+                              address as *const _,
+                              buffer.as_mut_ptr().cast(),
+                              buffer.len(),
+                              &mut read);
+        }
+        Ok(read)
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        use windows_sys::Win32::System::Memory::{
+            VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_EXECUTE, PAGE_EXECUTE_READ,
+            PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS, PAGE_READWRITE,
+            PAGE_WRITECOPY,
+        };
+
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+        let written = unsafe {
+            VirtualQuery(address as *const _, &mut info, size_of::<MEMORY_BASIC_INFORMATION>())
+        };
+        if written == 0 || info.State != MEM_COMMIT {
+            return None;
+        }
+        let protect = info.Protect;
+        Some(MemoryRegion {
+            base: info.BaseAddress as u64,
+            size: info.RegionSize as u64,
+            readable: protect & (PAGE_NOACCESS | PAGE_GUARD) == 0 && protect != PAGE_EXECUTE,
+            writable: protect & (PAGE_READWRITE | PAGE_WRITECOPY | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY) != 0,
+            executable: protect & (PAGE_EXECUTE | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY) != 0,
+        })
+    }
+}
+
+/// A line of `/proc/<pid>/maps`
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct MappedRegion {
+    pub region: MemoryRegion,
+    pub offset: u64,
+    pub path: Option<String>,
+}
+
+/// A PE module found in the mappings of a process
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct ProcessModule {
+    pub name: String,
+    pub path: String,
+    pub base: u64,
+    pub size: u64,
+}
+
+/// Memory of another process read through `/proc/<pid>/mem`, this also covers Windows games
+/// running under Wine or Proton. Reading requires ptrace access to the target
+#[cfg(target_os = "linux")]
+pub struct ProcessMemory {
+    pid: Option<u32>,
+    mem: std::fs::File,
+    // Parsed once for region queries, a dump only needs a consistent snapshot of the mappings
+    regions: std::sync::OnceLock<Vec<MemoryRegion>>,
+}
+
+#[cfg(target_os = "linux")]
+impl ProcessMemory {
+    pub fn open(pid: u32) -> Result<Self, Error> {
+        Ok(ProcessMemory {
+            pid: Some(pid),
+            mem: std::fs::File::open(format!("/proc/{}/mem", pid))?,
+            regions: std::sync::OnceLock::new(),
+        })
+    }
+
+    pub fn open_self() -> Result<Self, Error> {
+        Ok(ProcessMemory {
+            pid: None,
+            mem: std::fs::File::open("/proc/self/mem")?,
+            regions: std::sync::OnceLock::new(),
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid.unwrap_or_else(std::process::id)
+    }
+
+    /// Parses `/proc/<pid>/maps`, the regions are sorted by address
+    pub fn maps(&self) -> Result<Vec<MappedRegion>, Error> {
+        let path = match self.pid {
+            Some(pid) => format!("/proc/{}/maps", pid),
+            None => "/proc/self/maps".to_string(),
+        };
+        let maps = std::fs::read_to_string(path)?;
+        Ok(maps.lines().filter_map(parse_maps_line).collect())
+    }
+
+    /// PE modules mapped in the process. Wine maps the headers of every image from its file, so
+    /// each module shows up as a file mapping at offset 0 starting with the DOS signature, or
+    /// named after a PE image when anti-dump code wiped that signature. The size comes from
+    /// `SizeOfImage` since the sections are often copied into anonymous memory
+    pub fn modules(&self) -> Result<Vec<ProcessModule>, Error> {
+        let maps = self.maps()?;
+        let mut modules: Vec<ProcessModule> = vec![];
+        for mapped in &maps {
+            let Some(path) = mapped.path.as_deref() else { continue };
+            if mapped.offset != 0 || !mapped.region.readable || path.starts_with('[') {
+                continue;
+            }
+            let path = path.trim_end_matches(" (deleted)");
+            let mut magic = [0u8; 2];
+            match self.read(mapped.region.base, &mut magic) {
+                Ok(2) if magic == *b"MZ" => {}
+                Ok(_) if is_image_path(path) => {}
+                Ok(_) => continue,
+                Err(error) => {
+                    warn!("Skipping {} at 0x{:X}, its header could not be read: {}", path, mapped.region.base, error);
+                    continue;
+                }
+            }
+
+            let size = match read_zero_filled(self, mapped.region.base, PAGE_SIZE as usize) {
+                Ok((header, _)) => pe_utils::parse_headers(&header).ok()
+                    .and_then(|header| pe_utils::get_optional_headers(&header).ok())
+                    .map(|optional_header| optional_header.windows_fields.size_of_image as u64)
+                    .filter(|size_of_image| *size_of_image != 0),
+                Err(_) => None,
+            };
+            // Without usable headers fall back to the contiguous mappings of the same file
+            let size = size.unwrap_or_else(|| {
+                let mut end = mapped.region.end();
+                let following = maps.iter().filter(|next| next.region.base >= mapped.region.end());
+                for next in following {
+                    if next.region.base != end || next.path.as_deref().map(|next| next.trim_end_matches(" (deleted)")) != Some(path) {
+                        break;
+                    }
+                    end = next.region.end();
+                }
+                end - mapped.region.base
+            });
+            modules.push(ProcessModule {
+                name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                path: path.to_string(),
+                base: mapped.region.base,
+                size,
+            });
+        }
+        Ok(modules)
+    }
+
+    /// Looks a module up by file name or full path, ignoring case
+    pub fn find_module(&self, name: &str) -> Result<Option<ProcessModule>, Error> {
+        Ok(self.modules()?.into_iter().find(|module| {
+            module.name.eq_ignore_ascii_case(name) || module.path.eq_ignore_ascii_case(name)
+        }))
+    }
+}
+
+/// Whether a mapped file is named like a PE image, for mappings whose headers were wiped
+#[cfg(target_os = "linux")]
+fn is_image_path(path: &str) -> bool {
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    ["exe", "dll", "sys", "ocx", "drv"].iter().any(|image| extension.eq_ignore_ascii_case(image))
+}
+
+#[cfg(target_os = "linux")]
+fn parse_maps_line(line: &str) -> Option<MappedRegion> {
+    // 7f0000000000-7f0000001000 r-xp 00001000 08:01 1234   /path/to/file
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let permissions = fields.next()?.as_bytes();
+    let offset = fields.next()?;
+    let _device = fields.next()?;
+    let _inode = fields.next()?;
+    let path = fields.next().map(str::trim).filter(|path| !path.is_empty());
+
+    let base = u64::from_str_radix(start, 16).ok()?;
+    let end = u64::from_str_radix(end, 16).ok()?;
+    Some(MappedRegion {
+        region: MemoryRegion {
+            base,
+            size: end.checked_sub(base)?,
+            readable: permissions.first() == Some(&b'r'),
+            writable: permissions.get(1) == Some(&b'w'),
+            executable: permissions.get(2) == Some(&b'x'),
+        },
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        path: path.map(str::to_string),
+    })
+}
+
+#[cfg(target_os = "linux")]
+impl MemorySource for ProcessMemory {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        use std::os::unix::fs::FileExt;
+
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.mem.read_at(&mut buffer[read..], address + read as u64) {
+                Ok(0) => break,
+                Ok(count) => read += count,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                // EIO marks the first unmapped page
+                Err(_) if read != 0 => break,
+                Err(error) if error.raw_os_error() == Some(5) => break,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(read)
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        let regions = self.regions.get_or_init(|| {
+            let mut regions = self.maps()
+                .map(|maps| maps.into_iter().map(|mapped| mapped.region).collect::<Vec<_>>())
+                .unwrap_or_else(|error| {
+                    warn!("Could not read the mappings of process {}: {}", self.pid(), error);
+                    vec![]
+                });
+            regions.sort_by_key(|region| region.base);
+            regions
+        });
+        let index = regions.partition_point(|region| region.base <= address).checked_sub(1)?;
+        Some(regions[index]).filter(|region| region.contains(address))
+    }
+}
+
+/// Memory of the current process, read through `/proc/self/mem` so unmapped pages fail the read
+/// instead of faulting
+#[cfg(target_os = "linux")]
+pub struct InProcessMemory(ProcessMemory);
+
+#[cfg(target_os = "linux")]
+impl InProcessMemory {
+    pub fn new() -> Result<Self, Error> {
+        Ok(InProcessMemory(ProcessMemory::open_self()?))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl MemorySource for InProcessMemory {
+    fn read(&self, address: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.0.read(address, buffer)
+    }
+
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.0.query_region(address)
+    }
+}
+
+/// Reads `size` bytes at `address`, zero filling whatever the source can not provide. Returns
+/// the bytes and how many of them were missing, counted from the first gap of each page
+pub fn read_zero_filled<S: MemorySource + ?Sized>(source: &S, address: u64, size: usize) -> Result<(Vec<u8>, usize), Error> {
+    if size as u64 >= MAX_IMAGE_SIZE {
+        return Err(Error::ImageTooLarge(size as u64));
+    }
+    let mut output = vec![0; size];
+    let mut missing = 0;
+    let mut offset = 0u64;
+    while offset < size as u64 {
+        let chunk_end = (offset + READ_CHUNK_SIZE).min(size as u64);
+        let chunk = &mut output[offset as usize..chunk_end as usize];
+        if source.read(address + offset, chunk)? != chunk.len() {
+            chunk.fill(0);
+            for (index, page) in chunk.chunks_mut(PAGE_SIZE as usize).enumerate() {
+                let page_address = address + offset + index as u64 * PAGE_SIZE;
+                // The page starts zeroed and unread bytes stay untouched, anything copied past a
+                // gap is kept
+                let read = source.read(page_address, page).unwrap_or(0);
+                missing += page.len() - read.min(page.len());
+            }
+        }
+        offset = chunk_end;
+    }
+    if missing != 0 {
+        warn!("0x{:X} bytes at 0x{:X} could not be read and were zero filled", missing, address);
+    }
+    Ok((output, missing))
+}
+
+/// Reads the module mapped at `module_base` in its memory layout. The headers are read first and
+/// the image spans `SizeOfImage` or the end of the last section, whichever is larger. Returns the
+/// image and how many of its bytes were zero filled, or [`Error::ImageTooLarge`] when the headers
+/// claim 2 GiB or more
+pub fn read_image<S: MemorySource + ?Sized>(source: &S, module_base: u64) -> Result<(Vec<u8>, usize), Error> {
+    let (data, _) = read_zero_filled(source, module_base, PE_POINTER_OFFSET as usize + 4)?;
+    let pe_pointer: u32 = data.pread_with(PE_POINTER_OFFSET as usize, LE)?;
+    let coff_header = pe_pointer as usize + SIZEOF_PE_MAGIC;
+    let (data, _) = read_zero_filled(source, module_base, coff_header + SIZEOF_COFF_HEADER)?;
+    let number_of_sections: u16 = data.pread_with(coff_header + 2, LE)?;
+    let size_of_optional_header: u16 = data.pread_with(coff_header + 16, LE)?;
+    let headers_end = coff_header + SIZEOF_COFF_HEADER + size_of_optional_header as usize
+        + number_of_sections as usize * SIZEOF_SECTION_TABLE;
+    let (data, _) = read_zero_filled(source, module_base, headers_end)?;
+
+    let header = pe_utils::parse_headers(&data)?;
+    trace!("{:#?}", header);
+    let optional_header = pe_utils::get_optional_headers(&header)?;
+    let sections = pe_utils::get_sections(&header, &data)?;
+
+    let mut image_end = optional_header.windows_fields.size_of_image as u64;
+    for section in &sections {
+        let virtual_size = section.virtual_size.max(section.size_of_raw_data);
+        image_end = image_end.max(section.virtual_address as u64 + virtual_size as u64);
+    }
+    if image_end >= MAX_IMAGE_SIZE {
+        return Err(Error::ImageTooLarge(image_end));
+    }
+    read_zero_filled(source, module_base, image_end as usize)
+}
+
+/// Region hints for the heuristic header rebuild, derived from the page protections reported
+/// by the source
+pub fn region_hints<S: MemorySource + ?Sized>(source: &S, module_base: u64, size: u64) -> Vec<RegionHint> {
+    let module_end = module_base + size;
+    let mut hints = vec![];
+    let mut address = module_base;
+    while address < module_end {
+        let Some(region) = source.query_region(address) else {
+            address += PAGE_SIZE;
+            continue;
+        };
+        let end = region.end().min(module_end);
+        if end <= address {
+            break;
+        }
+        if region.readable {
+            hints.push(RegionHint {
+                rva: (address - module_base) as u32,
+                size: (end - address) as u32,
+                executable: region.executable,
+                writable: region.writable,
+            });
+        }
+        address = end;
+    }
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use goblin::pe::section_table::IMAGE_SCN_CNT_CODE;
+    use pe_utils::{PeBuilder, PeImage};
+    use scroll::Pwrite;
+
+    use super::*;
+
+    const BASE: u64 = 0x1_4000_0000;
+
+    /// Memory layout dump of an image with 0x1800 bytes of `.text`, 0x3000 bytes in all
+    fn build_dump() -> Vec<u8> {
+        let mut builder = PeBuilder::new(true, BASE).unwrap();
+        builder.add_section(".text", vec![0xC3; 0x1800], IMAGE_SCN_CNT_CODE).unwrap();
+        let image = PeImage::parse(builder.build().unwrap()).unwrap();
+
+        let mut dump = vec![0u8; image.optional_header().windows_fields.size_of_image as usize];
+        let size_of_headers = image.optional_header().windows_fields.size_of_headers as usize;
+        dump[0..size_of_headers].copy_from_slice(&image.data()[0..size_of_headers]);
+        for section in image.sections() {
+            let data = image.section_data(section);
+            let start = section.virtual_address as usize;
+            dump[start..start + data.len()].copy_from_slice(data);
+        }
+        dump
+    }
+
+    #[test]
+    fn reads_images_sized_from_the_headers() {
+        let dump = build_dump();
+        assert_eq!(dump.len(), 0x3000);
+
+        let (image, missing) = read_image(&BufferSource::new(BASE, &dump[0..0x2000]), BASE).unwrap();
+        assert_eq!(image.len(), 0x3000);
+        assert_eq!(missing, 0x1000);
+        assert_eq!(image[0..0x2000], dump[0..0x2000]);
+        assert_eq!(image[0x2000..], [0; 0x1000]);
+    }
+
+    #[test]
+    fn refuses_forged_image_sizes() {
+        let mut dump = build_dump();
+        let offset = crate::optional_header_offset(&pe_utils::parse_headers(&dump).unwrap()) + 56;
+        dump.pwrite_with(0xFFFF_F000u32, offset, LE).unwrap();
+        let source = BufferSource::new(BASE, &dump);
+
+        assert!(matches!(read_image(&source, BASE), Err(Error::ImageTooLarge(0xFFFF_F000))));
+        assert!(matches!(read_zero_filled(&source, BASE, MAX_IMAGE_SIZE as usize), Err(Error::ImageTooLarge(_))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn recognizes_image_mappings_by_name() {
+        let line = "140000000-140001000 r--p 00000000 08:01 1234                       /games/Game/Binaries/Win64/Game-Win64-Shipping.exe";
+        let mapped = parse_maps_line(line).unwrap();
+        assert_eq!((mapped.region.base, mapped.region.size, mapped.offset), (0x1_4000_0000, 0x1000, 0));
+        assert!(mapped.region.readable && !mapped.region.executable);
+        assert!(is_image_path(mapped.path.as_deref().unwrap()));
+
+        assert!(is_image_path("/windows/system32/KERNEL32.DLL"));
+        assert!(!is_image_path("/usr/lib/libc.so.6"));
+        assert!(!is_image_path("/home/user/.wine/drive_c/notes"));
+    }
+}